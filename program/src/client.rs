@@ -0,0 +1,540 @@
+//! Off-chain helpers for clients integrating with the treasury program.
+
+use std::{collections::BTreeMap, mem::size_of, str::FromStr};
+
+use borsh::BorshDeserialize;
+use solana_program::{
+    instruction::AccountMeta, program_pack::Pack, pubkey::Pubkey, rent::Rent, system_program,
+};
+use spl_associated_token_account::get_associated_token_address;
+use spl_token::state::Account as TokenAccount;
+
+use crate::{
+    voucher_code_hash, BalanceSnapshot, Campaign, ClaimReceipt, ClaimRecord, ClaimStats,
+    ClaimantMarker, MintRegistry, NoncePage, StakePosition, TreasuryConfig, TreasuryState,
+    UserClaim, Voucher, CAMPAIGN_SEED, CLAIMANT_SEED, CLAIM_RECORD_LEN, CONFIG_SEED,
+    ELIGIBILITY_ALLOCATION, ELIGIBILITY_ALLOWLIST, HISTORY_CAPACITY, HISTORY_HEADER_LEN,
+    HISTORY_SEED, HISTORY_SPACE, MIN_CLIENT_VERSION, NONCES_PER_PAGE, NONCE_SEED, RECEIPT_SEED,
+    SNAPSHOT_CAPACITY, SNAPSHOT_HEADER_LEN, SNAPSHOT_RECORD_LEN, SNAPSHOT_SPACE, STAKE_SEED,
+    STATS_SEED, TREASURY_SEED, USER_CLAIM_SEED, VERSIONED_INSTRUCTION_TAG, VOUCHER_SEED,
+};
+
+/// Decode the claim history ring buffer, oldest record first.
+///
+/// Returns an empty list when `data` is too short to be a history account.
+pub fn read_history(data: &[u8]) -> Vec<ClaimRecord> {
+    if data.len() < HISTORY_SPACE {
+        return Vec::new();
+    }
+
+    let head = u32::from_le_bytes(data[0..4].try_into().unwrap()) as usize % HISTORY_CAPACITY;
+    let len = (u32::from_le_bytes(data[4..8].try_into().unwrap()) as usize).min(HISTORY_CAPACITY);
+    let start = (head + HISTORY_CAPACITY - len) % HISTORY_CAPACITY;
+
+    (0..len)
+        .filter_map(|i| {
+            let index = (start + i) % HISTORY_CAPACITY;
+            let offset = HISTORY_HEADER_LEN + index * CLAIM_RECORD_LEN;
+            ClaimRecord::try_from_slice(&data[offset..offset + CLAIM_RECORD_LEN]).ok()
+        })
+        .collect()
+}
+
+/// Decode the balance snapshots ring buffer, oldest snapshot first.
+///
+/// Returns an empty list when `data` is too short to be a snapshots account.
+pub fn read_snapshots(data: &[u8]) -> Vec<BalanceSnapshot> {
+    if data.len() < SNAPSHOT_SPACE {
+        return Vec::new();
+    }
+
+    let head = u32::from_le_bytes(data[0..4].try_into().unwrap()) as usize % SNAPSHOT_CAPACITY;
+    let len = (u32::from_le_bytes(data[4..8].try_into().unwrap()) as usize).min(SNAPSHOT_CAPACITY);
+    let start = (head + SNAPSHOT_CAPACITY - len) % SNAPSHOT_CAPACITY;
+
+    (0..len)
+        .filter_map(|i| {
+            let index = (start + i) % SNAPSHOT_CAPACITY;
+            let offset = SNAPSHOT_HEADER_LEN + index * SNAPSHOT_RECORD_LEN;
+            BalanceSnapshot::try_from_slice(&data[offset..offset + SNAPSHOT_RECORD_LEN]).ok()
+        })
+        .collect()
+}
+
+/// Build the ordered account list for `Claim` (and `ClaimNative`) from a decoded config.
+///
+/// Tokens go to the user's associated token account, and the treasury token account is the
+/// associated token account of the treasury PDA. The config owner is listed as the signing
+/// authority and pays for the receipt; the user only signs when a flat fee is charged or a
+/// co-signer is configured. For token-gated treasuries the user's associated account of the
+/// gate mint is appended, followed by the co-signer when one is set, then the user-claim PDA
+/// when the eligibility mode is allowlist or allocation, then, once claim stats exist, the
+/// stats PDA, the system program and the user's claimant marker.
+pub fn claim_account_metas(
+    program_id: &Pubkey,
+    user: &Pubkey,
+    config: &TreasuryConfig,
+) -> Vec<AccountMeta> {
+    let treasury_pda =
+        Pubkey::create_program_address(&[TREASURY_SEED, &[config.treasury_bump]], program_id)
+            .expect("config stores a valid treasury bump");
+    let config_pda =
+        Pubkey::create_program_address(&[CONFIG_SEED, &[config.config_bump]], program_id)
+            .expect("config stores a valid config bump");
+    let history_pda =
+        Pubkey::create_program_address(&[HISTORY_SEED, &[config.history_bump]], program_id)
+            .expect("config stores a valid history bump");
+    let (receipt_pda, _) = receipt_address(program_id, config.campaign_id, user);
+    let charges_fee = config.flat_fee_lamports > 0;
+    let user_signs = charges_fee || config.cosigner.is_some();
+
+    let mut metas = vec![
+        if user_signs {
+            AccountMeta::new(*user, true)
+        } else {
+            AccountMeta::new_readonly(*user, false)
+        },
+        AccountMeta::new(get_associated_token_address(user, &config.mint), false),
+        AccountMeta::new(treasury_token_account(program_id, &config.mint), false),
+        AccountMeta::new_readonly(spl_token::id(), false),
+        AccountMeta::new(treasury_pda, false),
+        AccountMeta::new(config.owner, true),
+        AccountMeta::new_readonly(config_pda, false),
+        AccountMeta::new(history_pda, false),
+        if charges_fee {
+            AccountMeta::new(config.fee_vault, false)
+        } else {
+            AccountMeta::new_readonly(config.fee_vault, false)
+        },
+        AccountMeta::new_readonly(system_program::id(), false),
+        AccountMeta::new_readonly(config.mint, false),
+        AccountMeta::new(receipt_pda, false),
+    ];
+    if let Some(gate_mint) = config.gate_mint {
+        metas.push(AccountMeta::new_readonly(
+            get_associated_token_address(user, &gate_mint),
+            false,
+        ));
+    }
+    if let Some(cosigner) = config.cosigner {
+        metas.push(AccountMeta::new_readonly(cosigner, true));
+    }
+    if matches!(
+        config.eligibility_mode,
+        ELIGIBILITY_ALLOWLIST | ELIGIBILITY_ALLOCATION
+    ) {
+        let (user_claim_pda, _) =
+            Pubkey::find_program_address(&[USER_CLAIM_SEED, user.as_ref()], program_id);
+        metas.push(AccountMeta::new(user_claim_pda, false));
+    }
+    if let Some(bump) = config.stats_bump {
+        let stats_pda = Pubkey::create_program_address(&[STATS_SEED, &[bump]], program_id)
+            .expect("config stores a valid stats bump");
+        metas.push(AccountMeta::new(stats_pda, false));
+        metas.push(AccountMeta::new_readonly(system_program::id(), false));
+        metas.push(AccountMeta::new(
+            claimant_address(program_id, user).0,
+            false,
+        ));
+    }
+    metas
+}
+
+/// Build the ordered account list for a user-signed `ClaimTo` or `ClaimVested`, paying out
+/// to `destination`, which for `ClaimVested` must be a token account the user owns.
+///
+/// The same layout as `claim_account_metas`, except that the user signs and also fills the
+/// authority slot, paying for their own receipt, and the user-claim PDA is always listed
+/// whatever the eligibility mode.
+pub fn user_claim_account_metas(
+    program_id: &Pubkey,
+    user: &Pubkey,
+    destination: &Pubkey,
+    config: &TreasuryConfig,
+) -> Vec<AccountMeta> {
+    let mut metas = claim_account_metas(program_id, user, config);
+    metas[0] = AccountMeta::new(*user, true);
+    metas[1] = AccountMeta::new(*destination, false);
+    metas[5] = AccountMeta::new(*user, true);
+
+    let (user_claim_pda, _) =
+        Pubkey::find_program_address(&[USER_CLAIM_SEED, user.as_ref()], program_id);
+    if !metas.iter().any(|meta| meta.pubkey == user_claim_pda) {
+        // 🔹 The stats block, when present, always comes last
+        let stats_len = if config.stats_bump.is_some() { 3 } else { 0 };
+        metas.insert(
+            metas.len() - stats_len,
+            AccountMeta::new(user_claim_pda, false),
+        );
+    }
+    metas
+}
+
+/// Derive the claim-stats PDA.
+pub fn stats_address(program_id: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[STATS_SEED], program_id)
+}
+
+/// Derive `user`'s claimant marker PDA, which every payout to them passes once claim stats
+/// exist.
+pub fn claimant_address(program_id: &Pubkey, user: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[CLAIMANT_SEED, user.as_ref()], program_id)
+}
+
+/// Decode the claim-stats account, or `None` when `data` is not one.
+pub fn read_claim_stats(data: &[u8]) -> Option<ClaimStats> {
+    ClaimStats::decode(data).ok()
+}
+
+/// The canonical treasury token account for `mint`: the associated token account of the
+/// treasury PDA. This is the account `CreateTreasuryTokenAccount` creates and the one claims
+/// require when `enforce_treasury_ata` is set.
+pub fn treasury_token_account(program_id: &Pubkey, mint: &Pubkey) -> Pubkey {
+    let (treasury_pda, _) = Pubkey::find_program_address(&[TREASURY_SEED], program_id);
+    get_associated_token_address(&treasury_pda, mint)
+}
+
+/// Derive the receipt PDA proving `user` claimed from `campaign_id`.
+pub fn receipt_address(program_id: &Pubkey, campaign_id: u64, user: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(
+        &[RECEIPT_SEED, &campaign_id.to_le_bytes(), user.as_ref()],
+        program_id,
+    )
+}
+
+/// Derive the campaign PDA for `campaign_id`.
+pub fn campaign_address(program_id: &Pubkey, campaign_id: u64) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[CAMPAIGN_SEED, &campaign_id.to_le_bytes()], program_id)
+}
+
+/// Derive the stake PDA holding `user`'s `ClaimWithStake` position.
+pub fn stake_address(program_id: &Pubkey, user: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[STAKE_SEED, user.as_ref()], program_id)
+}
+
+/// Derive the voucher PDA redeemed with `code`.
+pub fn voucher_address(program_id: &Pubkey, code: &str) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[VOUCHER_SEED, &voucher_code_hash(code)], program_id)
+}
+
+/// Derive the nonce page PDA that tracks authorization `nonce`.
+pub fn nonce_page_address(program_id: &Pubkey, nonce: u64) -> (Pubkey, u8) {
+    Pubkey::find_program_address(
+        &[NONCE_SEED, &(nonce / NONCES_PER_PAGE).to_le_bytes()],
+        program_id,
+    )
+}
+
+/// Wrap privileged `instruction_data` in a `Versioned` instruction stamped with
+/// `MIN_CLIENT_VERSION`, so a program whose account layouts moved on rejects it with
+/// `ClientOutdated` instead of acting on stale assumptions.
+pub fn versioned_instruction_data(instruction_data: &[u8]) -> Vec<u8> {
+    let mut data = Vec::with_capacity(1 + 4 + 4 + instruction_data.len());
+    data.push(VERSIONED_INSTRUCTION_TAG);
+    data.extend_from_slice(&MIN_CLIENT_VERSION.to_le_bytes());
+    data.extend_from_slice(&(instruction_data.len() as u32).to_le_bytes());
+    data.extend_from_slice(instruction_data);
+    data
+}
+
+/// One recipient line logged by `SimulateBatch` or `SimulateBatchCapped`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SimulatedAmount {
+    pub index: usize,
+    pub recipient: Pubkey,
+    pub amount: u64,
+    pub covered: bool,
+    pub status: Option<String>, // paid / skipped / aborts; only logged by SimulateBatchCapped
+}
+
+/// Parse the `SIMULATED` lines from `simulateTransaction` logs, in the order they were logged.
+///
+/// Accepts raw program logs (with the `Program log: ` prefix) and skips lines that do not
+/// parse.
+pub fn parse_simulated_batch(logs: &[String]) -> Vec<SimulatedAmount> {
+    logs.iter()
+        .filter_map(|line| {
+            let line = line.strip_prefix("Program log: ").unwrap_or(line);
+            let fields = line.strip_prefix("SIMULATED ")?;
+
+            let mut index = None;
+            let mut recipient = None;
+            let mut amount = None;
+            let mut covered = None;
+            let mut status = None;
+            for field in fields.split_whitespace() {
+                match field.split_once('=')? {
+                    ("index", value) => index = value.parse().ok(),
+                    ("recipient", value) => recipient = value.parse().ok(),
+                    ("amount", value) => amount = value.parse().ok(),
+                    ("covered", value) => covered = value.parse().ok(),
+                    ("status", value) => status = Some(value.to_string()),
+                    _ => {}
+                }
+            }
+
+            Some(SimulatedAmount {
+                index: index?,
+                recipient: recipient?,
+                amount: amount?,
+                covered: covered?,
+                status,
+            })
+        })
+        .collect()
+}
+
+/// Render a raw token amount as a decimal string using the mint's `decimals`, e.g.
+/// `format_amount(1_500_000, 6) == "1.5"`. Trailing fractional zeros are dropped, and the
+/// decimal point too when nothing is left after it.
+pub fn format_amount(raw: u64, decimals: u8) -> String {
+    let digits = raw.to_string();
+    let decimals = decimals as usize;
+    if decimals == 0 {
+        return digits;
+    }
+
+    let padded = format!("{:0>width$}", digits, width = decimals + 1);
+    let (whole, fraction) = padded.split_at(padded.len() - decimals);
+    let fraction = fraction.trim_end_matches('0');
+    if fraction.is_empty() {
+        whole.to_string()
+    } else {
+        format!("{}.{}", whole, fraction)
+    }
+}
+
+/// Parse a decimal string such as `"1.5"` into a raw amount for a mint with `decimals`.
+///
+/// Returns `None` for anything other than plain digits with at most one `.`, for more
+/// significant fractional digits than the mint supports, and on `u64` overflow. Surrounding
+/// whitespace and trailing fractional zeros are ignored.
+pub fn parse_amount(value: &str, decimals: u8) -> Option<u64> {
+    let value = value.trim();
+    let (whole, fraction) = value.split_once('.').unwrap_or((value, ""));
+    if whole.is_empty() && fraction.is_empty() {
+        return None;
+    }
+    if !whole.bytes().all(|b| b.is_ascii_digit()) || !fraction.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+
+    let fraction = fraction.trim_end_matches('0');
+    if fraction.len() > decimals as usize {
+        return None;
+    }
+
+    let scale = 10u64.checked_pow(decimals as u32)?;
+    let whole: u64 = if whole.is_empty() {
+        0
+    } else {
+        whole.parse().ok()?
+    };
+    let fraction_raw: u64 = if fraction.is_empty() {
+        0
+    } else {
+        let fraction_scale = 10u64.checked_pow((decimals as usize - fraction.len()) as u32)?;
+        fraction.parse::<u64>().ok()?.checked_mul(fraction_scale)?
+    };
+
+    whole.checked_mul(scale)?.checked_add(fraction_raw)
+}
+
+/// Fields logged by `LogState`, keyed as `config.<field>` / `state.<field>` (array elements
+/// as `<section>.<field>.<index>.<member>`). Unset optional keys hold `none`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct StateDump {
+    pub fields: BTreeMap<String, String>,
+}
+
+impl StateDump {
+    /// Parse the field `key` (e.g. `"config.multiplier_bps"`) as `T`.
+    pub fn get<T: FromStr>(&self, key: &str) -> Option<T> {
+        self.fields.get(key)?.parse().ok()
+    }
+
+    /// Parse an optional pubkey field, mapping `none` to `Some(None)`.
+    pub fn get_optional_pubkey(&self, key: &str) -> Option<Option<Pubkey>> {
+        match self.fields.get(key)?.as_str() {
+            "none" => Some(None),
+            value => value.parse().ok().map(Some),
+        }
+    }
+}
+
+/// Collect the `DUMP` lines from `simulateTransaction` logs of a `LogState` call.
+///
+/// Accepts raw program logs (with the `Program log: ` prefix) and skips other lines.
+pub fn parse_state_dump(logs: &[String]) -> StateDump {
+    let fields = logs
+        .iter()
+        .filter_map(|line| {
+            let line = line.strip_prefix("Program log: ").unwrap_or(line);
+            let (key, value) = line.strip_prefix("DUMP ")?.split_once('=')?;
+            Some((key.to_string(), value.to_string()))
+        })
+        .collect();
+    StateDump { fields }
+}
+
+/// What a deployment plans to create, for `provisioning_rent`. The config, treasury state,
+/// history buffer and treasury token account are always counted.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ProvisioningPlan {
+    pub nonce_pages: u64, // Authorization nonce pages, NONCES_PER_PAGE nonces each
+    pub campaigns: u64,   // Campaign PDAs
+    pub user_claims: u64, // User-claim PDAs (allocations, allowlists)
+    pub receipts: u64,    // Claim receipts, one per user per campaign
+    pub stake_positions: u64, // Stake PDAs created by ClaimWithStake
+    pub vouchers: u64,    // Outstanding voucher PDAs
+    pub stats: bool,      // Claim-stats PDA
+    pub claimants: u64,   // Claimant markers, one per user paid while stats exist
+    pub snapshots: bool,  // Balance snapshots ring buffer
+    pub registry: bool,   // Multi-mint registry
+}
+
+/// Total rent-exempt lamports needed to create every account in `plan`, using the same
+/// account sizes the program allocates.
+pub fn provisioning_rent(rent: &Rent, plan: &ProvisioningPlan) -> u64 {
+    let each = |space: usize, count: u64| rent.minimum_balance(space).saturating_mul(count);
+
+    [
+        each(8 + size_of::<TreasuryConfig>(), 1),
+        each(8 + size_of::<TreasuryState>(), 1),
+        each(HISTORY_SPACE, 1),
+        each(TokenAccount::LEN, 1),
+        each(8 + size_of::<NoncePage>(), plan.nonce_pages),
+        each(8 + size_of::<Campaign>(), plan.campaigns),
+        each(8 + size_of::<UserClaim>(), plan.user_claims),
+        each(8 + size_of::<ClaimReceipt>(), plan.receipts),
+        each(8 + size_of::<StakePosition>(), plan.stake_positions),
+        each(8 + size_of::<Voucher>(), plan.vouchers),
+        each(8 + size_of::<ClaimStats>(), plan.stats as u64),
+        each(8 + size_of::<ClaimantMarker>(), plan.claimants),
+        each(SNAPSHOT_SPACE, plan.snapshots as u64),
+        each(8 + size_of::<MintRegistry>(), plan.registry as u64),
+    ]
+    .iter()
+    .fold(0u64, |total, lamports| total.saturating_add(*lamports))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::append_history;
+
+    #[test]
+    fn format_amount_drops_trailing_zeros() {
+        assert_eq!(format_amount(1_500_000, 6), "1.5");
+        assert_eq!(format_amount(2_000_000, 6), "2");
+        assert_eq!(format_amount(1, 6), "0.000001");
+        assert_eq!(format_amount(0, 6), "0");
+        assert_eq!(format_amount(42, 0), "42");
+        assert_eq!(format_amount(u64::MAX, 9), "18446744073.709551615");
+    }
+
+    #[test]
+    fn parse_amount_round_trips_format_amount() {
+        for raw in [0, 1, 1_500_000, 2_000_000, 123_456_789, u64::MAX] {
+            assert_eq!(parse_amount(&format_amount(raw, 6), 6), Some(raw));
+        }
+        assert_eq!(parse_amount(" 1.50 ", 6), Some(1_500_000));
+        assert_eq!(parse_amount(".5", 1), Some(5));
+        assert_eq!(parse_amount("3.", 2), Some(300));
+    }
+
+    #[test]
+    fn parse_amount_rejects_malformed_input() {
+        for value in ["", ".", "-1", "1e6", "1.2.3", "0x10", "1,5"] {
+            assert_eq!(parse_amount(value, 6), None, "{value:?}");
+        }
+        assert_eq!(parse_amount("0.0000001", 6), None);
+        assert_eq!(parse_amount("18446744073709551616", 0), None);
+        assert_eq!(parse_amount("18446744073709.551616", 6), None);
+    }
+
+    #[test]
+    fn read_history_returns_records_oldest_first_after_wrapping() {
+        let mut data = vec![0u8; HISTORY_SPACE];
+        let user = Pubkey::new_unique();
+        let total = HISTORY_CAPACITY + 3;
+        for i in 0..total {
+            let record = ClaimRecord {
+                user,
+                amount: i as u64,
+                timestamp: i as i64,
+            };
+            append_history(&mut data, &record).unwrap();
+        }
+
+        let history = read_history(&data);
+        assert_eq!(history.len(), HISTORY_CAPACITY);
+        assert_eq!(history.first().map(|record| record.amount), Some(3));
+        assert_eq!(
+            history.last().map(|record| record.amount),
+            Some(total as u64 - 1)
+        );
+        assert!(read_history(&data[..HISTORY_SPACE - 1]).is_empty());
+    }
+
+    #[test]
+    fn parse_state_dump_keeps_only_dump_lines() {
+        let owner = Pubkey::new_unique();
+        let logs = vec![
+            "Program log: DUMP config.multiplier_bps=10000".to_string(),
+            format!("Program log: DUMP config.owner={}", owner),
+            "DUMP config.cosigner=none".to_string(),
+            "Program log: CLAIMED user=x".to_string(),
+        ];
+        let dump = parse_state_dump(&logs);
+        assert_eq!(dump.fields.len(), 3);
+        assert_eq!(dump.get::<u16>("config.multiplier_bps"), Some(10_000));
+        assert_eq!(dump.get_optional_pubkey("config.owner"), Some(Some(owner)));
+        assert_eq!(dump.get_optional_pubkey("config.cosigner"), Some(None));
+        assert_eq!(dump.get::<u64>("state.balance"), None);
+    }
+
+    #[test]
+    fn parse_simulated_batch_reads_three_recipients() {
+        let recipients = [
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+        ];
+        let logs = vec![
+            format!(
+                "Program log: SIMULATED index=0 recipient={} amount=100 covered=true",
+                recipients[0]
+            ),
+            format!(
+                "Program log: SIMULATED index=1 recipient={} amount=0 covered=true cap=5 received=0 status=skipped",
+                recipients[1]
+            ),
+            "Program log: SIMULATED index=x recipient=bad amount=1 covered=true".to_string(),
+            format!(
+                "SIMULATED index=2 recipient={} amount=50 covered=false",
+                recipients[2]
+            ),
+            "Program log: SIMULATED_TOTAL total=150 vault=120 paused=false fails=true".to_string(),
+        ];
+
+        let parsed = parse_simulated_batch(&logs);
+        assert_eq!(parsed.len(), 3);
+        assert_eq!(
+            parsed[1],
+            SimulatedAmount {
+                index: 1,
+                recipient: recipients[1],
+                amount: 0,
+                covered: true,
+                status: Some("skipped".to_string()),
+            }
+        );
+        assert_eq!(
+            parsed.iter().map(|line| line.amount).collect::<Vec<_>>(),
+            vec![100, 0, 50]
+        );
+        assert!(!parsed[2].covered);
+        assert_eq!(parsed[0].status, None);
+    }
+}
@@ -1,222 +1,7223 @@
 use borsh::{BorshDeserialize, BorshSchema, BorshSerialize}; // Add BorshSerialize
 use solana_program::{
     account_info::{next_account_info, AccountInfo},
+    ed25519_program,
     entrypoint,
-    entrypoint::ProgramResult,
+    entrypoint::{ProgramResult, MAX_PERMITTED_DATA_INCREASE},
+    keccak,
+    msg,
     program::{invoke, invoke_signed},
     program_error::ProgramError,
     program_pack::Pack, // Add this import
     pubkey::Pubkey,
     system_instruction,
-    sysvar::{rent::Rent, Sysvar},
+    system_program,
+    sysvar::{
+        clock::Clock,
+        instructions::{load_current_index_checked, load_instruction_at_checked},
+        rent::Rent,
+        Sysvar,
+    },
 };
 
-use spl_token::{instruction as token_instruction, state::Account as TokenAccount};
+use spl_associated_token_account::{
+    get_associated_token_address, instruction::create_associated_token_account_idempotent,
+};
+use spl_token::{
+    instruction as token_instruction, native_mint,
+    state::{Account as TokenAccount, Mint},
+};
+
+#[cfg(feature = "client")]
+pub mod client;
+
+// Declare program entrypoint
+entrypoint!(process_instruction);
+
+/// PDA seeds. Every derivation and `invoke_signed` goes through these so a typo cannot
+/// silently address a different account.
+pub const TREASURY_SEED: &[u8] = b"treasury";
+pub const CONFIG_SEED: &[u8] = b"config";
+pub const HISTORY_SEED: &[u8] = b"history";
+pub const REGISTRY_SEED: &[u8] = b"registry";
+pub const RECEIPT_SEED: &[u8] = b"receipt";
+pub const USER_CLAIM_SEED: &[u8] = b"user_claim";
+pub const SNAPSHOT_SEED: &[u8] = b"snapshots";
+pub const CAMPAIGN_SEED: &[u8] = b"campaign";
+pub const NONCE_SEED: &[u8] = b"nonce";
+pub const STAKE_SEED: &[u8] = b"stake";
+pub const VOUCHER_SEED: &[u8] = b"voucher";
+pub const STATS_SEED: &[u8] = b"stats";
+pub const CLAIMANT_SEED: &[u8] = b"claimant";
+
+/// Oldest client version whose account layouts match this program. Bump it whenever a layout
+/// change would make older clients build privileged instructions against the wrong accounts.
+pub const MIN_CLIENT_VERSION: u32 = 1;
+
+/// Discriminant of `TreasuryInstruction::Versioned`, for clients building the wrapper by hand.
+pub const VERSIONED_INSTRUCTION_TAG: u8 = 59;
+
+/// Number of `TreasuryInstruction` variants; tags at or above this are from a newer client.
+const INSTRUCTION_VARIANTS: u8 = 62;
+
+#[derive(BorshDeserialize, BorshSchema, Debug)]
+enum TreasuryInstruction {
+    Initialize,
+    Claim {
+        amount: u64,
+    },
+    ClaimNative {
+        amount: u64,
+        unwrap: bool,
+    },
+    SetConfig {
+        params: ConfigParams,
+    },
+    ScheduleOwnerChange {
+        new_owner: Pubkey,
+    },
+    FinalizeOwnerChange,
+    CancelOwnerChange,
+    SyncDecimals,
+    AirdropBatch {
+        amounts: Vec<u64>,
+    },
+    CloseTreasury,
+    CloseConfig,
+    GrowConfig {
+        additional_bytes: u32,
+    },
+    SetPaused {
+        claims: bool,
+        deposits: bool,
+    },
+    Deposit {
+        amount: u64,
+    },
+    GrantRole {
+        authority: Pubkey,
+        roles: u8,
+    },
+    RevokeRole {
+        authority: Pubkey,
+        roles: u8,
+    },
+    RegisterMint,
+    DeregisterMint {
+        mint: Pubkey,
+    },
+    ClaimMint {
+        mint: Pubkey,
+        amount: u64,
+    },
+    SetOwnerAndConfig {
+        new_owner: Pubkey,
+        params: ConfigParams,
+    },
+    SetVesting {
+        user: Pubkey,
+        start: i64,
+        duration: i64,
+        total: u64,
+    },
+    ClaimVested {
+        amount: u64,
+    },
+    SimulateBatch {
+        amounts: Vec<u64>,
+    },
+    CreateTreasuryTokenAccount,
+    AllocatePool {
+        pool: [u8; 16],
+        amount: u64,
+    },
+    ClaimPool {
+        pool: [u8; 16],
+        amount: u64,
+    },
+    WithdrawSplit {
+        total: u64,
+        amounts: Vec<u64>,
+    },
+    SetAllocationBatch {
+        entries: Vec<(Pubkey, u64)>,
+    },
+    ClaimTo {
+        amount: u64,
+        destination: Pubkey,
+    },
+    Snapshot,
+    SetMintFee {
+        mint: Pubkey,
+        fee_bps: Option<u16>,
+    },
+    SetUri {
+        uri: String,
+    },
+    SetFeeVault {
+        fee_vault: Pubkey,
+    },
+    LockFunds {
+        amount: u64,
+    },
+    UnlockFunds {
+        amount: u64,
+    },
+    AirdropBatchPartial {
+        amounts: Vec<u64>,
+    },
+    InitializeState,
+    ClaimAuthorized {
+        amount: u64,
+        authorization: ClaimAuthorization,
+    },
+    MigrateTreasury {
+        new_treasury_token_account: Pubkey,
+    },
+    ReduceAllocation {
+        user: Pubkey,
+        new_allocation: u64,
+    },
+    SetEmergencyStop {
+        stopped: bool,
+    },
+    CreateCampaign {
+        campaign_id: u64,
+        merkle_root: [u8; 32],
+        total_cap: u64,
+        per_user_cap: u64,
+        starts_at: i64,
+        ends_at: i64,
+    },
+    ClaimCampaign {
+        campaign_id: u64,
+        amount: u64,
+        allocation: u64,
+        proof: Vec<[u8; 32]>,
+    },
+    ClaimSol {
+        amount: u64,
+    },
+    SetEligibilityMode {
+        mode: u8,
+    },
+    ForceCloseUserClaim {
+        user: Pubkey,
+    },
+    GaslessClaim {
+        amount: u64,
+        authorization: ClaimAuthorization,
+    },
+    LogState,
+    SetupCampaign {
+        campaign_id: u64,
+        merkle_root: [u8; 32],
+        total_cap: u64,
+        per_user_cap: u64,
+        starts_at: i64,
+        ends_at: i64,
+        create_nonce_page: bool,
+    },
+    ClaimWithReferral {
+        amount: u64,
+    },
+    SetGlobalUserCap {
+        cap: u64,
+    },
+    AirdropBatchCapped {
+        amounts: Vec<u64>,
+        skip_over_cap: bool,
+    },
+    SetDelegate {
+        delegate: Option<Pubkey>,
+        budget: u64,
+    },
+    ClaimWithStake {
+        amount: u64,
+    },
+    Unstake,
+    ClaimWithReference {
+        amount: u64,
+        reference: [u8; 16],
+    },
+    SetMinReserve {
+        min_reserve: u64,
+        allow_over_balance: bool,
+    },
+    CreateVoucher {
+        code_hash: [u8; 32],
+        amount: u64,
+    },
+    RedeemVoucher {
+        code: String,
+    },
+    Versioned {
+        client_version: u32,
+        instruction: Vec<u8>,
+    },
+    InitializeStats,
+    SimulateBatchCapped {
+        amounts: Vec<u64>,
+        skip_over_cap: bool,
+    },
+}
+
+/// Owner-tunable parameters, replaced wholesale by `SetConfig`.
+#[derive(BorshSerialize, BorshDeserialize, BorshSchema, Debug)]
+pub struct ConfigParams {
+    pub flat_fee_lamports: u64,
+    pub fee_vault: Pubkey,
+    pub owner_timelock_seconds: i64,
+    pub strict_amounts: bool,
+    pub campaign_id: u64,
+    pub multiplier_bps: u16,
+    pub claim_deadline: i64,
+    pub grace_period_seconds: i64,
+    pub late_fee_bps: u16,
+    pub enforce_treasury_ata: bool,
+    pub gate_mint: Option<Pubkey>,
+    pub allocation_cap: u64,
+    pub max_claims_per_user: u32,
+    pub cosigner: Option<Pubkey>,
+    pub default_mint_fee_bps: u16,
+    pub rounding: u8,
+    pub inactivity_pause_seconds: i64,
+    pub min_claim_interval: i64,
+    pub ramp_duration: i64,
+    pub initial_cap: u64,
+    pub final_cap: u64,
+    pub decay_start: i64,
+    pub decay_end: i64,
+    pub referral_bps: u16,
+    pub withdraw_cooldown_seconds: i64,
+    pub require_ata: bool,
+    pub stake_bps: u16,
+    pub stake_lock_seconds: i64,
+    pub error_verbosity: u8,
+}
+
+#[derive(BorshSerialize, BorshDeserialize, Debug)]
+pub struct TreasuryConfig {
+    pub discriminator: [u8; 8],        // CONFIG_DISCRIMINATOR once initialized
+    pub owner: Pubkey,                 // Store the treasury owner's key
+    pub created_at: i64,               // Unix timestamp of initialization
+    pub flat_fee_lamports: u64,        // Lamports charged to the user per claim
+    pub fee_vault: Pubkey,             // Receives the flat claim fee
+    pub treasury_bump: u8,             // Canonical bump of the treasury PDA
+    pub config_bump: u8,               // Canonical bump of this config PDA
+    pub history_bump: u8,              // Canonical bump of the history PDA
+    pub mint: Pubkey,                  // Mint held by the treasury
+    pub decimals: u8,                  // Cached mint decimals used for transfer_checked
+    pub owner_timelock_seconds: i64,   // Delay between scheduling and finalizing an owner change
+    pub pending_owner: Option<Pubkey>, // Scheduled next owner, if any
+    pub owner_change_effective_at: i64, // Earliest finalize time for `pending_owner`
+    pub claims_paused: bool,           // Blocks Claim / ClaimNative / AirdropBatch
+    pub deposits_paused: bool,         // Blocks Deposit
+    pub authorities: [Authority; MAX_AUTHORITIES], // Delegated keys and their role bits
+    pub strict_amounts: bool,          // Reject claim amounts that look like unit-conversion bugs
+    pub campaign_id: u64, // Campaign that claims and receipts are currently attributed to
+    pub multiplier_bps: u16, // Claim payout multiplier, 10_000 = 1x
+    pub claim_deadline: i64, // Claims close after this time (plus grace); 0 = no deadline
+    pub grace_period_seconds: i64, // Window after the deadline where late claims pay a fee
+    pub late_fee_bps: u16, // Share of a late payout withheld by the treasury
+    pub enforce_treasury_ata: bool, // Claims must use the treasury PDA's ATA as the vault
+    pub gate_mint: Option<Pubkey>, // Claimers must hold a nonzero balance of this mint
+    pub allocation_cap: u64, // Ceiling on total_allocated; 0 = the tracked treasury balance
+    pub max_claims_per_user: u32, // List-mode and user-signed claims allowed per user PDA; 0 = unlimited
+    pub cosigner: Option<Pubkey>, // Must co-sign every Claim alongside the user
+    pub default_mint_fee_bps: u16, // ClaimMint fee for registry mints without an override
+    pub uri: [u8; MAX_URI_LEN],   // Off-chain metadata link, UTF-8 zero-padded
+    pub rounding: u8,             // ROUNDING_* mode for multiplied claim payouts; default floor
+    pub updated_at: i64,          // Unix timestamp of the last config change
+    pub emergency_stop: bool, // Master halt: blocks every mutating instruction but unstop/migrate
+    pub inactivity_pause_seconds: i64, // Pause claims once the config is idle this long; 0 = off
+    pub min_claim_interval: i64, // Seconds required between any two claims treasury-wide; 0 = off
+    pub eligibility_mode: u8, // ELIGIBILITY_* strategy Claim uses to decide who may claim
+    pub ramp_duration: i64,   // Per-claim ceiling ramps to the full allocation over this; 0 = off
+    pub initial_cap: u64,     // Per-claim payout cap until decay_start; 0 = no decaying cap
+    pub final_cap: u64,       // Per-claim payout cap from decay_end on
+    pub decay_start: i64,     // The cap starts falling from initial_cap here
+    pub decay_end: i64,       // ... and reaches final_cap here
+    pub referral_bps: u16,    // Bonus paid to a referrer, as a share of the user's payout
+    pub per_user_cap: u64,    // Most one user may receive through Claim per campaign; 0 = no cap
+    pub withdraw_cooldown_seconds: i64, // Seconds required between owner withdrawals; 0 = off
+    pub require_ata: bool,    // Claims must pay the claimer's ATA of the treasury mint
+    pub delegate: Option<Pubkey>, // May sign Claim in the owner slot while delegate_budget lasts
+    pub delegate_budget: u64, // Payout the delegate may still sign for; the owner tops it up
+    pub stake_bps: u16,       // Share of a ClaimWithStake payout locked in the user's stake PDA
+    pub stake_lock_seconds: i64, // How long newly staked tokens stay locked
+    pub error_verbosity: u8,  // ERROR_VERBOSITY_* detail logged with CLAIM_REJECTED
+    pub vault: Option<Pubkey>, // Treasury token account claims must use; None = not recorded
+    pub min_reserve: u64, // Tracked balance Claim must always leave in the treasury (staked shares stay in it); 0 = none
+    pub stats_bump: Option<u8>, // Claim-stats PDA bump once InitializeStats ran; payouts then update it
+}
+
+/// Role bit allowing an authority to toggle the pause flags.
+pub const ROLE_CAN_PAUSE: u8 = 1 << 0;
+/// Role bit allowing an authority to sign claims and airdrops in place of the owner.
+pub const ROLE_CAN_CLAIM: u8 = 1 << 1;
+/// Role bit allowing an authority to update `ConfigParams`.
+pub const ROLE_CAN_CONFIG: u8 = 1 << 2;
+pub const ALL_ROLES: u8 = ROLE_CAN_PAUSE | ROLE_CAN_CLAIM | ROLE_CAN_CONFIG;
+/// Number of delegated authority slots in the config.
+pub const MAX_AUTHORITIES: usize = 4;
+
+/// A delegated key and its role bits. Unused slots hold the default pubkey.
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Authority {
+    pub key: Pubkey,
+    pub roles: u8,
+}
+
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone)]
+pub struct TreasuryState {
+    pub balance: u64,                // Store the treasury balance
+    pub claim_seq: u64,              // Incremented on every successful claim
+    pub pools: [SubPool; MAX_POOLS], // Earmarked portions of `balance`
+    pub total_allocated: u64,        // Unclaimed user-claim allocations promised against `balance`
+    pub locked_balance: u64, // Part of `balance` committed to allocations; the rest is liquid
+    pub last_global_claim_ts: i64, // Time of the most recent claim from any path
+    pub last_withdraw_ts: i64, // Time of the most recent owner withdrawal
+}
+
+/// Maximum number of named sub-pools tracked in the treasury state.
+pub const MAX_POOLS: usize = 4;
+
+/// A named slice of the treasury balance (e.g. "rewards"). An all-zero label marks a free
+/// slot; labels are zero-padded UTF-8.
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SubPool {
+    pub label: [u8; 16],
+    pub balance: u64,
+}
+
+/// Program-specific errors, surfaced as `ProgramError::Custom(code)`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TreasuryError {
+    ClaimsPaused = 0,
+    DepositsPaused = 1,
+    MissingRole = 2,
+    AuthoritiesFull = 3,
+    MalformedAmount = 4,
+    MintAlreadyRegistered = 5,
+    MintNotRegistered = 6,
+    RegistryFull = 7,
+    MintHasBalance = 8,
+    TooManyNewAccounts = 9,
+    /// A program PDA was passed where the signing owner/authority was expected.
+    AuthorityIsProgramAccount = 10,
+    /// The treasury still holds or owes funds, or is not closed yet where that is required.
+    TreasuryStillOpen = 11,
+    /// The treasury token account holds less than the requested transfer.
+    InsufficientTreasuryBalance = 12,
+    /// The claim exceeds what the user's schedule has unlocked so far.
+    ExceedsVested = 13,
+    PoolNotFound = 14,
+    PoolsFull = 15,
+    /// The sub-pool (or the unallocated balance, when allocating) cannot cover the amount.
+    PoolInsufficientBalance = 16,
+    /// An account the instruction modifies was passed as read-only.
+    AccountNotWritable = 17,
+    /// The claim deadline and its grace period have both passed.
+    ClaimWindowClosed = 18,
+    /// The claimer does not hold the config's gate mint.
+    NotEligible = 19,
+    /// Allocations would promise more than the treasury balance (or configured cap).
+    OverAllocated = 20,
+    /// The user has reached the configured `max_claims_per_user`.
+    ClaimLimitReached = 21,
+    /// The instruction discriminant is unknown, usually a client built for a newer program.
+    UnsupportedInstruction = 22,
+    /// The treasury token account is frozen by the mint's freeze authority.
+    AccountFrozen = 23,
+    /// The stored owner is the zero pubkey, so owner-gated actions are disabled.
+    OwnershipRenounced = 24,
+    /// The uncommitted (liquid) balance cannot cover the amount.
+    InsufficientLiquidBalance = 25,
+    /// The signed authorization is missing, malformed, expired or does not match the claim.
+    InvalidAuthorization = 26,
+    /// The emergency stop is set; only unstopping and migration are allowed.
+    EmergencyStopped = 27,
+    /// The merkle proof does not lead from `(user, allocation)` to the campaign root.
+    InvalidMerkleProof = 28,
+    /// The claim would exceed the campaign's total cap or the user's share of it.
+    CampaignCapExceeded = 29,
+    /// Another claim landed less than `min_claim_interval` seconds ago.
+    ClaimRateLimited = 30,
+    /// Paying the lamports would leave the treasury PDA below its rent-exempt minimum.
+    BelowRentFloor = 31,
+    /// Claims are still possible: no deadline is set, or it or its grace period has not passed.
+    ClaimWindowOpen = 32,
+    /// A single claim exceeds the ramp ceiling for the time since the user's unlock.
+    ExceedsRampCeiling = 33,
+    /// The payout exceeds the decaying per-claim cap in force right now.
+    ExceedsClaimCap = 34,
+    /// The named referrer is the claimer themselves.
+    SelfReferral = 35,
+    /// The user has already received `per_user_cap` for the current campaign.
+    UserCapReached = 36,
+    /// The enabled claim features are estimated to exceed the compute budget.
+    ComputeBudgetExceeded = 37,
+    /// The previous owner withdrawal was less than `withdraw_cooldown_seconds` ago.
+    WithdrawCooldown = 38,
+    /// A batch amount exceeds the unclaimed allocation in the recipient's user-claim PDA.
+    RecipientCapExceeded = 39,
+    /// The delegate-signed claim exceeds the remaining `delegate_budget`.
+    DelegateBudgetExhausted = 40,
+    /// The config exists but the treasury state PDA has not been created yet.
+    StateNotInitialized = 41,
+    /// The stake position is still locked, or holds nothing to unstake.
+    StakeLocked = 42,
+    /// The claim would take the tracked balance below `min_reserve`.
+    BelowMinReserve = 43,
+    /// The client is older than `MIN_CLIENT_VERSION` and may not match the account layouts.
+    ClientOutdated = 44,
+}
+
+impl From<TreasuryError> for ProgramError {
+    fn from(e: TreasuryError) -> Self {
+        ProgramError::Custom(e as u32)
+    }
+}
+
+/// Maximum number of additional mints a treasury can hold through the registry.
+pub const MAX_REGISTERED_MINTS: usize = 8;
+
+/// One mint held by the treasury: its vault (owned by the treasury PDA), the balance seen
+/// at the last register/claim, and cached decimals. Unused slots hold the default pubkey.
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MintEntry {
+    pub mint: Pubkey,
+    pub vault: Pubkey,
+    pub balance: u64,
+    pub decimals: u8,
+    pub fee_bps: Option<u16>, // Claim fee for this mint; None = config default_mint_fee_bps
+}
+
+#[derive(BorshSerialize, BorshDeserialize, Debug)]
+pub struct MintRegistry {
+    pub bump: u8,
+    pub entries: [MintEntry; MAX_REGISTERED_MINTS],
+}
+
+impl MintRegistry {
+    /// Decode the registry from raw account data, ignoring any trailing padding.
+    pub fn decode(data: &[u8]) -> Result<Self, ProgramError> {
+        Self::deserialize(&mut &data[..]).map_err(|_| ProgramError::InvalidAccountData)
+    }
+
+    pub fn find(&self, mint: &Pubkey) -> Option<usize> {
+        if *mint == Pubkey::default() {
+            return None;
+        }
+        self.entries.iter().position(|entry| entry.mint == *mint)
+    }
+}
+
+/// Upper bound on PDAs a single batch instruction may create, keeping it clear of the
+/// transaction's compute and account-data limits.
+pub const MAX_NEW_ACCOUNTS_PER_TX: usize = 8;
+
+/// Per-(campaign, user) proof of participation, seeded `[RECEIPT_SEED, campaign_id, user]`.
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ClaimReceipt {
+    pub campaign_id: u64,
+    pub user: Pubkey,
+    pub total_claimed: u64,
+    pub first_claimed_at: i64,
+    pub last_claimed_at: i64,
+    pub bump: u8,
+}
+
+impl ClaimReceipt {
+    /// Decode a receipt from raw account data, ignoring any trailing padding.
+    pub fn decode(data: &[u8]) -> Result<Self, ProgramError> {
+        Self::deserialize(&mut &data[..]).map_err(|_| ProgramError::InvalidAccountData)
+    }
+}
+
+/// Longest merkle proof `ClaimCampaign` accepts; enough for 2^24 recipients.
+pub const MAX_MERKLE_PROOF_LEN: usize = 24;
+
+/// One airdrop campaign drawing on the shared treasury vault, seeded
+/// `[CAMPAIGN_SEED, campaign_id]`. Users prove `(user, allocation)` against `merkle_root`;
+/// `claimed` is the campaign's own running total, so campaigns never eat into each other's caps.
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Campaign {
+    pub campaign_id: u64,
+    pub merkle_root: [u8; 32],
+    pub total_cap: u64,    // Most the campaign may pay out across all users
+    pub per_user_cap: u64, // Most one user may claim; 0 = only their proven allocation
+    pub claimed: u64,      // Paid out by this campaign so far
+    pub starts_at: i64,    // Unix timestamp claims open
+    pub ends_at: i64,      // Unix timestamp claims close; 0 = no end
+    pub bump: u8,
+}
+
+impl Campaign {
+    /// Decode a campaign from raw account data, ignoring any trailing padding.
+    pub fn decode(data: &[u8]) -> Result<Self, ProgramError> {
+        Self::deserialize(&mut &data[..]).map_err(|_| ProgramError::InvalidAccountData)
+    }
+
+    /// Whether claims are open at `now`.
+    pub fn is_open(&self, now: i64) -> bool {
+        now >= self.starts_at && (self.ends_at == 0 || now <= self.ends_at)
+    }
+}
+
+/// Tokens a user staked through `ClaimWithStake`, seeded `[STAKE_SEED, user]`. They stay in
+/// the vault, counted in `locked_balance`, until `Unstake` pays them out after `unlock_at`.
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, PartialEq, Eq)]
+pub struct StakePosition {
+    pub user: Pubkey,
+    pub amount: u64,    // Staked and not yet paid out
+    pub unlock_at: i64, // Unix timestamp the whole position unlocks
+    pub bump: u8,
+}
+
+impl StakePosition {
+    /// Decode a stake position from raw account data, ignoring any trailing padding.
+    pub fn decode(data: &[u8]) -> Result<Self, ProgramError> {
+        Self::deserialize(&mut &data[..]).map_err(|_| ProgramError::InvalidAccountData)
+    }
+}
+
+/// A one-time voucher worth `amount`, seeded `[VOUCHER_SEED, code_hash]`. Its amount is
+/// locked in the treasury until `RedeemVoucher` pays it out and closes the voucher, returning
+/// the rent to `creator`.
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Voucher {
+    pub code_hash: [u8; 32], // voucher_code_hash of the redemption code
+    pub amount: u64,
+    pub creator: Pubkey, // Paid the rent; receives it back on redemption
+    pub bump: u8,
+}
+
+impl Voucher {
+    /// Decode a voucher from raw account data, ignoring any trailing padding.
+    pub fn decode(data: &[u8]) -> Result<Self, ProgramError> {
+        Self::deserialize(&mut &data[..]).map_err(|_| ProgramError::InvalidAccountData)
+    }
+}
+
+/// Longest redemption code `RedeemVoucher` accepts.
+pub const MAX_VOUCHER_CODE_LEN: usize = 64;
+
+/// Hash a voucher redemption code the way the voucher PDA is seeded: `keccak(code)`.
+pub fn voucher_code_hash(code: &str) -> [u8; 32] {
+    keccak::hash(code.as_bytes()).to_bytes()
+}
+
+/// Running claim totals for dashboards, seeded `[STATS_SEED]`. Updated by every payout path
+/// once created: the claims, airdrop batches, vesting, campaign and voucher payouts, ClaimSol
+/// and ClaimMint. `unique_claimants` counts users by their `ClaimantMarker`, so a returning
+/// user is not counted again after a campaign rollover.
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct ClaimStats {
+    pub total_claimed: u64,    // Treasury-mint payouts, referral bonuses included
+    pub total_claims: u64,     // Number of payouts, in any asset
+    pub unique_claimants: u64, // Users ever paid, one claimant marker each
+    pub last_claim_ts: i64,    // Time of the most recent payout
+    pub bump: u8,
+}
+
+impl ClaimStats {
+    /// Decode the claim stats from raw account data, ignoring any trailing padding.
+    pub fn decode(data: &[u8]) -> Result<Self, ProgramError> {
+        Self::deserialize(&mut &data[..]).map_err(|_| ProgramError::InvalidAccountData)
+    }
+}
+
+/// Marks that `user` has been paid while claim stats existed, seeded `[CLAIMANT_SEED, user]`.
+/// Created by the user's first payout, which is what `unique_claimants` counts.
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ClaimantMarker {
+    pub user: Pubkey,
+    pub first_paid_at: i64,
+    pub bump: u8,
+}
+
+impl ClaimantMarker {
+    /// Decode a claimant marker from raw account data, ignoring any trailing padding.
+    pub fn decode(data: &[u8]) -> Result<Self, ProgramError> {
+        Self::deserialize(&mut &data[..]).map_err(|_| ProgramError::InvalidAccountData)
+    }
+}
+
+/// Merkle leaf for `user`'s campaign allocation. Off-chain tree builders must hash the
+/// same way: `keccak(user || allocation_le)`.
+pub fn campaign_leaf(user: &Pubkey, allocation: u64) -> [u8; 32] {
+    keccak::hashv(&[user.as_ref(), &allocation.to_le_bytes()]).to_bytes()
+}
+
+/// Fold `proof` into `leaf` with sorted-pair hashing and compare against `root`.
+fn verify_merkle_proof(proof: &[[u8; 32]], root: &[u8; 32], leaf: [u8; 32]) -> bool {
+    let computed = proof.iter().fold(leaf, |node, sibling| {
+        if node <= *sibling {
+            keccak::hashv(&[&node, sibling]).to_bytes()
+        } else {
+            keccak::hashv(&[sibling, &node]).to_bytes()
+        }
+    });
+    computed == *root
+}
+
+/// Per-user allocation and vesting schedule, seeded `[USER_CLAIM_SEED, user]`.
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, PartialEq, Eq)]
+pub struct UserClaim {
+    pub user: Pubkey,
+    pub total: u64,            // Total allocation vesting to the user
+    pub claimed: u64,          // Amount already paid out
+    pub vesting_start: i64,    // Unix timestamp vesting begins
+    pub vesting_duration: i64, // Seconds until `total` is fully unlocked
+    pub bump: u8,
+    pub claim_count: u32, // Successful list-mode and user-signed claims so far
+}
+
+impl UserClaim {
+    /// Decode a user-claim account from raw data, ignoring any trailing padding.
+    pub fn decode(data: &[u8]) -> Result<Self, ProgramError> {
+        Self::deserialize(&mut &data[..]).map_err(|_| ProgramError::InvalidAccountData)
+    }
+
+    /// Amount unlocked at `now` under this user's linear schedule.
+    pub fn vested(&self, now: i64) -> u64 {
+        vested_amount(self.total, self.vesting_start, self.vesting_duration, now)
+    }
+
+    /// Amount the user may still claim at `now`.
+    pub fn claimable(&self, now: i64) -> u64 {
+        self.vested(now).saturating_sub(self.claimed)
+    }
+}
+
+/// Linear vesting: nothing before `start`, everything once `duration` has elapsed.
+///
+/// A zero (or negative) duration is a cliff at `start` rather than a division by zero, and
+/// the result never exceeds `total`.
+pub fn vested_amount(total: u64, start: i64, duration: i64, now: i64) -> u64 {
+    if now <= start {
+        return 0;
+    }
+    let elapsed = now.saturating_sub(start);
+    if duration <= 0 || elapsed >= duration {
+        return total;
+    }
+    // 🔹 u64 * i64 fits in u128, and elapsed < duration keeps the quotient below `total`
+    (total as u128)
+        .checked_mul(elapsed as u128)
+        .and_then(|scaled| scaled.checked_div(duration as u128))
+        .map_or(total, |vested| vested.min(total as u128) as u64)
+}
+
+/// Maximum number of recipients accepted by one `AirdropBatchPartial`.
+pub const MAX_PARTIAL_BATCH: usize = 16;
+
+/// Maximum number of entries accepted by one `SetAllocationBatch`.
+pub const MAX_ALLOCATION_BATCH: usize = 16;
+
+/// Claim terms signed off-chain by the treasury owner. The owner signs the Borsh encoding of
+/// this struct with an ed25519 instruction placed immediately before the claim.
+#[derive(BorshSerialize, BorshDeserialize, BorshSchema, Debug, Clone, PartialEq, Eq)]
+pub struct ClaimAuthorization {
+    pub user: Pubkey,
+    pub amount: u64,     // Upper bound on what the claim may request
+    pub nonce: u64,      // Distinguishes otherwise identical authorizations
+    pub expires_at: i64, // Unix timestamp after which the authorization is void; 0 = never
+}
+
+/// Authorization nonces tracked by one `NoncePage`.
+pub const NONCES_PER_PAGE: u64 = 256;
+
+/// Bitmap of consumed authorization nonces `page * 256 ..= page * 256 + 255`, seeded
+/// `[NONCE_SEED, page]`. Nonces are treasury-wide, so the owner must never reuse one across
+/// users. Pages are created on first use, paid for by the claim's payer.
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, PartialEq, Eq)]
+pub struct NoncePage {
+    pub page: u64,
+    pub used: [u8; 32],
+    pub bump: u8,
+}
+
+impl NoncePage {
+    /// Decode a nonce page from raw account data, ignoring any trailing padding.
+    pub fn decode(data: &[u8]) -> Result<Self, ProgramError> {
+        Self::deserialize(&mut &data[..]).map_err(|_| ProgramError::InvalidAccountData)
+    }
+
+    fn mask(nonce: u64) -> (usize, u8) {
+        let bit = (nonce % NONCES_PER_PAGE) as usize;
+        (bit / 8, 1 << (bit % 8))
+    }
+
+    pub fn is_used(&self, nonce: u64) -> bool {
+        let (byte, mask) = Self::mask(nonce);
+        self.used[byte] & mask != 0
+    }
+
+    fn mark_used(&mut self, nonce: u64) {
+        let (byte, mask) = Self::mask(nonce);
+        self.used[byte] |= mask;
+    }
+}
+
+impl TreasuryInstruction {
+    /// Instructions that still run while the emergency stop is set: lifting it, the
+    /// emergency drain, and the read-only simulation and state dump.
+    fn allowed_during_emergency_stop(&self) -> bool {
+        matches!(
+            self,
+            TreasuryInstruction::Initialize
+                | TreasuryInstruction::SetEmergencyStop { .. }
+                | TreasuryInstruction::MigrateTreasury { .. }
+                | TreasuryInstruction::SimulateBatch { .. }
+                | TreasuryInstruction::SimulateBatchCapped { .. }
+                | TreasuryInstruction::LogState
+                | TreasuryInstruction::Versioned { .. }
+        )
+    }
+
+    /// Owner- and authority-only instructions, the ones `Versioned` may carry.
+    fn is_privileged(&self) -> bool {
+        matches!(
+            self,
+            TreasuryInstruction::Initialize
+                | TreasuryInstruction::SetConfig { .. }
+                | TreasuryInstruction::ScheduleOwnerChange { .. }
+                | TreasuryInstruction::FinalizeOwnerChange
+                | TreasuryInstruction::CancelOwnerChange
+                | TreasuryInstruction::AirdropBatch { .. }
+                | TreasuryInstruction::CloseTreasury
+                | TreasuryInstruction::CloseConfig
+                | TreasuryInstruction::GrowConfig { .. }
+                | TreasuryInstruction::SetPaused { .. }
+                | TreasuryInstruction::GrantRole { .. }
+                | TreasuryInstruction::RevokeRole { .. }
+                | TreasuryInstruction::RegisterMint
+                | TreasuryInstruction::DeregisterMint { .. }
+                | TreasuryInstruction::SetOwnerAndConfig { .. }
+                | TreasuryInstruction::SetVesting { .. }
+                | TreasuryInstruction::CreateTreasuryTokenAccount
+                | TreasuryInstruction::AllocatePool { .. }
+                | TreasuryInstruction::WithdrawSplit { .. }
+                | TreasuryInstruction::SetAllocationBatch { .. }
+                | TreasuryInstruction::SetMintFee { .. }
+                | TreasuryInstruction::SetUri { .. }
+                | TreasuryInstruction::SetFeeVault { .. }
+                | TreasuryInstruction::LockFunds { .. }
+                | TreasuryInstruction::UnlockFunds { .. }
+                | TreasuryInstruction::AirdropBatchPartial { .. }
+                | TreasuryInstruction::InitializeState
+                | TreasuryInstruction::MigrateTreasury { .. }
+                | TreasuryInstruction::ReduceAllocation { .. }
+                | TreasuryInstruction::SetEmergencyStop { .. }
+                | TreasuryInstruction::CreateCampaign { .. }
+                | TreasuryInstruction::SetEligibilityMode { .. }
+                | TreasuryInstruction::ForceCloseUserClaim { .. }
+                | TreasuryInstruction::SetupCampaign { .. }
+                | TreasuryInstruction::SetGlobalUserCap { .. }
+                | TreasuryInstruction::AirdropBatchCapped { .. }
+                | TreasuryInstruction::SetDelegate { .. }
+                | TreasuryInstruction::SetMinReserve { .. }
+                | TreasuryInstruction::CreateVoucher { .. }
+        )
+    }
+}
+
+/// Whether the config PDA among `accounts` has the emergency stop set. Instructions that do
+/// not pass the config cannot reach treasury state, so they are not affected.
+fn is_emergency_stopped(program_id: &Pubkey, accounts: &[AccountInfo]) -> bool {
+    let (config_pda, _) = Pubkey::find_program_address(&[CONFIG_SEED], program_id);
+    accounts
+        .iter()
+        .find(|account| *account.key == config_pda && account.owner == program_id)
+        .and_then(|account| {
+            let data = account.try_borrow_data().ok()?;
+            TreasuryConfig::decode(&data).ok()
+        })
+        .is_some_and(|config| config.emergency_stop)
+}
+
+/// Number of claims retained by the history ring buffer before the oldest is overwritten.
+pub const HISTORY_CAPACITY: usize = 64;
+/// Serialized size of a single `ClaimRecord` (user, amount, timestamp).
+pub const CLAIM_RECORD_LEN: usize = 32 + 8 + 8;
+/// History header: next write index (`u32`) followed by the number of stored records (`u32`).
+pub const HISTORY_HEADER_LEN: usize = 4 + 4;
+pub const HISTORY_SPACE: usize = HISTORY_HEADER_LEN + HISTORY_CAPACITY * CLAIM_RECORD_LEN;
+
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ClaimRecord {
+    pub user: Pubkey,
+    pub amount: u64,
+    pub timestamp: i64,
+}
+
+/// Write `record` at the history head, overwriting the oldest entry once the buffer is full.
+pub fn append_history(data: &mut [u8], record: &ClaimRecord) -> ProgramResult {
+    if data.len() < HISTORY_SPACE {
+        return Err(ProgramError::InvalidAccountData);
+    }
+
+    let head = u32::from_le_bytes(data[0..4].try_into().unwrap()) as usize % HISTORY_CAPACITY;
+    let len = u32::from_le_bytes(data[4..8].try_into().unwrap()) as usize;
+
+    let offset = HISTORY_HEADER_LEN + head * CLAIM_RECORD_LEN;
+    record.serialize(&mut &mut data[offset..offset + CLAIM_RECORD_LEN])?;
+
+    let next_head = (head + 1) % HISTORY_CAPACITY;
+    let next_len = (len + 1).min(HISTORY_CAPACITY);
+    data[0..4].copy_from_slice(&(next_head as u32).to_le_bytes());
+    data[4..8].copy_from_slice(&(next_len as u32).to_le_bytes());
+
+    Ok(())
+}
+
+/// Number of balance snapshots retained before the oldest is overwritten.
+pub const SNAPSHOT_CAPACITY: usize = 32;
+/// Serialized size of a single `BalanceSnapshot` (balance, timestamp).
+pub const SNAPSHOT_RECORD_LEN: usize = 8 + 8;
+/// Same header layout as the claim history: next write index, then stored count.
+pub const SNAPSHOT_HEADER_LEN: usize = 4 + 4;
+pub const SNAPSHOT_SPACE: usize = SNAPSHOT_HEADER_LEN + SNAPSHOT_CAPACITY * SNAPSHOT_RECORD_LEN;
+
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, PartialEq, Eq)]
+pub struct BalanceSnapshot {
+    pub balance: u64,
+    pub timestamp: i64,
+}
+
+/// Write `snapshot` at the snapshot buffer head, overwriting the oldest once full.
+pub fn append_snapshot(data: &mut [u8], snapshot: &BalanceSnapshot) -> ProgramResult {
+    if data.len() < SNAPSHOT_SPACE {
+        return Err(ProgramError::InvalidAccountData);
+    }
+
+    let head = u32::from_le_bytes(data[0..4].try_into().unwrap()) as usize % SNAPSHOT_CAPACITY;
+    let len = u32::from_le_bytes(data[4..8].try_into().unwrap()) as usize;
+
+    let offset = SNAPSHOT_HEADER_LEN + head * SNAPSHOT_RECORD_LEN;
+    snapshot.serialize(&mut &mut data[offset..offset + SNAPSHOT_RECORD_LEN])?;
+
+    let next_head = (head + 1) % SNAPSHOT_CAPACITY;
+    let next_len = (len + 1).min(SNAPSHOT_CAPACITY);
+    data[0..4].copy_from_slice(&(next_head as u32).to_le_bytes());
+    data[4..8].copy_from_slice(&(next_len as u32).to_le_bytes());
+
+    Ok(())
+}
+
+/// Capacity of the config's metadata `uri` in bytes.
+pub const MAX_URI_LEN: usize = 128;
+
+/// Leading bytes of an initialized config account.
+pub const CONFIG_DISCRIMINATOR: [u8; 8] = *b"tqconfig";
+
+impl TreasuryConfig {
+    /// Decode a config from raw account data, ignoring any trailing padding. Fails with
+    /// `UninitializedAccount` unless the data starts with `CONFIG_DISCRIMINATOR`.
+    pub fn decode(data: &[u8]) -> Result<Self, ProgramError> {
+        if !Self::is_initialized(data) {
+            return Err(ProgramError::UninitializedAccount);
+        }
+        Self::deserialize(&mut &data[..]).map_err(|_| ProgramError::InvalidAccountData)
+    }
+
+    /// The metadata URI with its zero padding trimmed; empty if unset or not valid UTF-8.
+    pub fn uri(&self) -> &str {
+        let len = self
+            .uri
+            .iter()
+            .rposition(|byte| *byte != 0)
+            .map_or(0, |last| last + 1);
+        std::str::from_utf8(&self.uri[..len]).unwrap_or("")
+    }
+
+    /// Claim payout for a requested `amount`: the multiplier applied with the configured
+    /// rounding mode.
+    pub fn claim_payout(&self, amount: u64) -> Result<u64, ProgramError> {
+        apply_bps_rounded(amount, self.multiplier_bps as u64, self.rounding)
+    }
+
+    /// Per-claim payout cap at `now`, falling linearly from `initial_cap` at `decay_start` to
+    /// `final_cap` at `decay_end`. `None` when no decaying cap is configured.
+    pub fn claim_cap_at(&self, now: i64) -> Option<u64> {
+        if self.initial_cap == 0 {
+            return None;
+        }
+        if now <= self.decay_start {
+            return Some(self.initial_cap);
+        }
+        if now >= self.decay_end {
+            return Some(self.final_cap);
+        }
+        // 🔹 decay_start < now < decay_end, so the span is positive and elapsed < span
+        let span = (self.decay_end - self.decay_start) as u128;
+        let elapsed = (now - self.decay_start) as u128;
+        let drop = (self.initial_cap - self.final_cap) as u128 * elapsed / span;
+        Some(self.initial_cap - drop as u64)
+    }
+
+    /// Whether `data` carries the config discriminator, regardless of lamports or size.
+    pub fn is_initialized(data: &[u8]) -> bool {
+        data.len() >= CONFIG_DISCRIMINATOR.len()
+            && data[..CONFIG_DISCRIMINATOR.len()] == CONFIG_DISCRIMINATOR
+    }
+}
+
+impl ConfigParams {
+    fn validate(&self) -> ProgramResult {
+        // 🔹 A fee without a destination would be unclaimable
+        if self.flat_fee_lamports > 0 && self.fee_vault == Pubkey::default() {
+            return Err(ProgramError::InvalidArgument);
+        }
+        if self.owner_timelock_seconds < 0 {
+            return Err(ProgramError::InvalidArgument);
+        }
+        if self.multiplier_bps == 0 {
+            return Err(ProgramError::InvalidArgument);
+        }
+        if self.claim_deadline < 0
+            || self.grace_period_seconds < 0
+            || self.late_fee_bps as u64 > BPS_DENOMINATOR
+            || self.default_mint_fee_bps as u64 > BPS_DENOMINATOR
+            || self.referral_bps as u64 > BPS_DENOMINATOR
+            || self.rounding > ROUNDING_NEAREST
+            || self.inactivity_pause_seconds < 0
+            || self.min_claim_interval < 0
+            || self.withdraw_cooldown_seconds < 0
+            || self.stake_bps as u64 > BPS_DENOMINATOR
+            || self.stake_lock_seconds < 0
+            || self.error_verbosity > ERROR_VERBOSITY_CODE
+            || self.ramp_duration < 0
+            || (self.initial_cap > 0
+                && (self.final_cap > self.initial_cap || self.decay_end < self.decay_start))
+        {
+            return Err(ProgramError::InvalidArgument);
+        }
+        Ok(())
+    }
+
+    fn apply(&self, config: &mut TreasuryConfig) {
+        config.flat_fee_lamports = self.flat_fee_lamports;
+        config.fee_vault = self.fee_vault;
+        config.owner_timelock_seconds = self.owner_timelock_seconds;
+        config.strict_amounts = self.strict_amounts;
+        config.campaign_id = self.campaign_id;
+        config.multiplier_bps = self.multiplier_bps;
+        config.claim_deadline = self.claim_deadline;
+        config.grace_period_seconds = self.grace_period_seconds;
+        config.late_fee_bps = self.late_fee_bps;
+        config.enforce_treasury_ata = self.enforce_treasury_ata;
+        config.gate_mint = self.gate_mint;
+        config.allocation_cap = self.allocation_cap;
+        config.max_claims_per_user = self.max_claims_per_user;
+        config.cosigner = self.cosigner;
+        config.default_mint_fee_bps = self.default_mint_fee_bps;
+        config.rounding = self.rounding;
+        config.inactivity_pause_seconds = self.inactivity_pause_seconds;
+        config.min_claim_interval = self.min_claim_interval;
+        config.ramp_duration = self.ramp_duration;
+        config.initial_cap = self.initial_cap;
+        config.final_cap = self.final_cap;
+        config.decay_start = self.decay_start;
+        config.decay_end = self.decay_end;
+        config.referral_bps = self.referral_bps;
+        config.withdraw_cooldown_seconds = self.withdraw_cooldown_seconds;
+        config.require_ata = self.require_ata;
+        config.stake_bps = self.stake_bps;
+        config.stake_lock_seconds = self.stake_lock_seconds;
+        config.error_verbosity = self.error_verbosity;
+    }
+}
+
+impl TreasuryState {
+    /// Decode the treasury state from raw account data, ignoring any trailing padding.
+    pub fn decode(data: &[u8]) -> Result<Self, ProgramError> {
+        Self::deserialize(&mut &data[..]).map_err(|_| ProgramError::InvalidAccountData)
+    }
+
+    /// Index of the sub-pool labelled `label`, if any.
+    pub fn find_pool(&self, label: &[u8; 16]) -> Option<usize> {
+        self.pools.iter().position(|pool| pool.label == *label)
+    }
+
+    /// Total earmarked across all sub-pools.
+    pub fn allocated(&self) -> u64 {
+        self.pools
+            .iter()
+            .fold(0u64, |sum, pool| sum.saturating_add(pool.balance))
+    }
+
+    /// Uncommitted part of `balance`, available to allocate or withdraw.
+    pub fn liquid_balance(&self) -> u64 {
+        self.balance.saturating_sub(self.locked_balance)
+    }
+
+    /// Move `amount` from liquid to locked.
+    pub fn lock(&mut self, amount: u64) -> ProgramResult {
+        if amount > self.liquid_balance() {
+            msg!(
+                "Cannot lock {}, liquid balance is {}",
+                amount,
+                self.liquid_balance()
+            );
+            return Err(TreasuryError::InsufficientLiquidBalance.into());
+        }
+        self.locked_balance += amount;
+        Ok(())
+    }
+
+    /// Move `amount` from locked back to liquid.
+    pub fn unlock(&mut self, amount: u64) -> ProgramResult {
+        if amount > self.locked_balance {
+            return Err(ProgramError::InvalidArgument);
+        }
+        self.locked_balance -= amount;
+        Ok(())
+    }
+
+    /// Debit a payout of `amount` from the liquid balance, never from locked funds. A payout
+    /// drawn from the paying instruction's own lock goes through `pay_out_locked` instead.
+    pub fn pay_out(&mut self, amount: u64) -> ProgramResult {
+        if amount > self.liquid_balance() {
+            msg!(
+                "Cannot pay out {}, liquid balance is {}",
+                amount,
+                self.liquid_balance()
+            );
+            return Err(TreasuryError::InsufficientLiquidBalance.into());
+        }
+        self.balance -= amount;
+        Ok(())
+    }
+
+    /// Pay out `amount` the paying instruction locked itself (an allocation, stake position
+    /// or voucher): release it from the lock, then debit it.
+    pub fn pay_out_locked(&mut self, amount: u64) -> ProgramResult {
+        self.unlock(amount)?;
+        self.pay_out(amount)
+    }
+
+    /// Move `total_allocated` from `old_total` to `new_total` for one user, locking or
+    /// unlocking the difference. Increases must fit the liquid balance and, when set, `cap`.
+    pub fn reallocate(&mut self, old_total: u64, new_total: u64, cap: u64) -> ProgramResult {
+        if new_total < old_total {
+            let released = old_total - new_total;
+            self.total_allocated = self.total_allocated.saturating_sub(released);
+            self.locked_balance = self.locked_balance.saturating_sub(released);
+            return Ok(());
+        }
+
+        let added = new_total - old_total;
+        let total_allocated = self
+            .total_allocated
+            .checked_add(added)
+            .ok_or(ProgramError::ArithmeticOverflow)?;
+        if (cap > 0 && total_allocated > cap) || added > self.liquid_balance() {
+            msg!(
+                "Allocations would total {}, cap {} liquid {}",
+                total_allocated,
+                cap,
+                self.liquid_balance()
+            );
+            return Err(TreasuryError::OverAllocated.into());
+        }
+        self.lock(added)?;
+        self.total_allocated = total_allocated;
+        Ok(())
+    }
+
+    /// Enforce the treasury-wide `min_interval` between claims and record `now` as the latest
+    /// claim. A zero interval only records the time.
+    pub fn note_claim_time(&mut self, now: i64, min_interval: i64) -> ProgramResult {
+        if min_interval > 0
+            && self.last_global_claim_ts != 0
+            && now.saturating_sub(self.last_global_claim_ts) < min_interval
+        {
+            msg!(
+                "Claims are throttled: last claim at {}, next allowed at {}",
+                self.last_global_claim_ts,
+                self.last_global_claim_ts.saturating_add(min_interval)
+            );
+            return Err(TreasuryError::ClaimRateLimited.into());
+        }
+        self.last_global_claim_ts = now;
+        Ok(())
+    }
+
+    /// Record an owner withdrawal at `now`, rejecting it while the previous one is still
+    /// within `cooldown` seconds. A cooldown of zero disables the check.
+    pub fn note_withdrawal(&mut self, now: i64, cooldown: i64) -> ProgramResult {
+        if cooldown > 0
+            && self.last_withdraw_ts != 0
+            && now.saturating_sub(self.last_withdraw_ts) < cooldown
+        {
+            msg!(
+                "Withdrawals are cooling down: last withdrawal at {}, next allowed at {}",
+                self.last_withdraw_ts,
+                self.last_withdraw_ts.saturating_add(cooldown)
+            );
+            return Err(TreasuryError::WithdrawCooldown.into());
+        }
+        self.last_withdraw_ts = now;
+        Ok(())
+    }
+
+    /// Advance and return the claim sequence number; the first claim is sequence 1.
+    pub fn next_claim_seq(&mut self) -> Result<u64, ProgramError> {
+        self.claim_seq = self
+            .claim_seq
+            .checked_add(1)
+            .ok_or(ProgramError::ArithmeticOverflow)?;
+        Ok(self.claim_seq)
+    }
+}
+
+/// Log the `CLAIMED` event. `seq` gives indexers an ordering key independent of block time;
+/// `reference` is the claimer's audit code, appended only when one was given.
+fn emit_claimed(user: &Pubkey, mint: &Pubkey, amount: u64, seq: u64, reference: Option<&str>) {
+    match reference {
+        Some(reference) => msg!(
+            "CLAIMED user={} mint={} amount={} seq={} reference={}",
+            user,
+            mint,
+            amount,
+            seq,
+            reference
+        ),
+        None => msg!(
+            "CLAIMED user={} mint={} amount={} seq={}",
+            user,
+            mint,
+            amount,
+            seq
+        ),
+    }
+}
+
+/// The reference code in `reference`: printable ASCII without spaces, zero-padded at the end,
+/// so it stays a single `key=value` token in the logs.
+fn claim_reference(reference: &[u8; 16]) -> Result<&str, ProgramError> {
+    let len = reference
+        .iter()
+        .position(|&byte| byte == 0)
+        .unwrap_or(reference.len());
+    let (code, padding) = reference.split_at(len);
+    if code.is_empty()
+        || !code.iter().all(u8::is_ascii_graphic)
+        || padding.iter().any(|&byte| byte != 0)
+    {
+        msg!("Claim reference must be 1-16 printable ASCII characters, zero-padded");
+        return Err(ProgramError::InvalidArgument);
+    }
+    // 🔹 ASCII checked above, so this cannot fail
+    std::str::from_utf8(code).map_err(|_| ProgramError::InvalidArgument)
+}
+
+/// Largest instruction payload accepted, checked before decoding so oversized `Vec`s
+/// (proofs, batch amounts) cost nothing to reject. Matches the transaction packet size,
+/// which no legitimate instruction approaches; CPI callers are held to the same limit.
+pub const MAX_INSTRUCTION_DATA: usize = 1232;
+
+/// Leading byte reserved for test-only instructions. It is never a valid
+/// `TreasuryInstruction` index, so without the feature such data simply fails to decode.
+#[cfg(feature = "test-helpers")]
+pub const TEST_INSTRUCTION_TAG: u8 = 0xff;
+
+/// Instructions that write state directly, for deterministic tests of time-based logic.
+/// Only compiled with the `test-helpers` feature and never part of production builds.
+#[cfg(feature = "test-helpers")]
+#[derive(BorshSerialize, BorshDeserialize, Debug)]
+pub enum TestInstruction {
+    SetStateForTest {
+        balance: Option<u64>,
+        created_at: Option<i64>,
+        owner_change_effective_at: Option<i64>,
+    },
+}
+
+pub fn process_instruction(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    instruction_data: &[u8],
+) -> ProgramResult {
+    #[cfg(feature = "test-helpers")]
+    {
+        if instruction_data.first() == Some(&TEST_INSTRUCTION_TAG) {
+            return process_test_instruction(program_id, accounts, &instruction_data[1..]);
+        }
+    }
+
+    if instruction_data.len() > MAX_INSTRUCTION_DATA {
+        msg!(
+            "Instruction data is {} bytes, limit is {}",
+            instruction_data.len(),
+            MAX_INSTRUCTION_DATA
+        );
+        return Err(ProgramError::InvalidInstructionData);
+    }
+
+    // 🔹 Decode without implicit length checks so trailing bytes are rejected explicitly
+    let mut remaining = instruction_data;
+    let instruction = TreasuryInstruction::deserialize(&mut remaining).map_err(|_| {
+        match instruction_data.first() {
+            Some(&tag) if tag >= INSTRUCTION_VARIANTS => {
+                msg!(
+                    "Unsupported instruction discriminant {}, this program knows 0..{}",
+                    tag,
+                    INSTRUCTION_VARIANTS
+                );
+                TreasuryError::UnsupportedInstruction.into()
+            }
+            Some(&tag) => {
+                msg!("Malformed data for instruction discriminant {}", tag);
+                ProgramError::InvalidInstructionData
+            }
+            None => {
+                msg!("Empty instruction data");
+                ProgramError::InvalidInstructionData
+            }
+        }
+    })?;
+    if !remaining.is_empty() {
+        msg!(
+            "Instruction data has {} unexpected trailing bytes",
+            remaining.len()
+        );
+        return Err(ProgramError::InvalidInstructionData);
+    }
+
+    // ✅ Under emergency stop only lifting the stop and draining the treasury are allowed
+    if !instruction.allowed_during_emergency_stop() && is_emergency_stopped(program_id, accounts) {
+        msg!("Treasury is under emergency stop");
+        return Err(TreasuryError::EmergencyStopped.into());
+    }
+
+    match instruction {
+        TreasuryInstruction::Initialize => process_initialize(program_id, accounts),
+        TreasuryInstruction::Claim { amount } => process_claim(program_id, accounts, amount),
+        TreasuryInstruction::ClaimNative { amount, unwrap } => {
+            process_claim_native(program_id, accounts, amount, unwrap)
+        }
+        TreasuryInstruction::SetConfig { params } => {
+            process_set_config(program_id, accounts, params)
+        }
+        TreasuryInstruction::ScheduleOwnerChange { new_owner } => {
+            process_schedule_owner_change(program_id, accounts, new_owner)
+        }
+        TreasuryInstruction::FinalizeOwnerChange => {
+            process_finalize_owner_change(program_id, accounts)
+        }
+        TreasuryInstruction::CancelOwnerChange => process_cancel_owner_change(program_id, accounts),
+        TreasuryInstruction::SyncDecimals => process_sync_decimals(program_id, accounts),
+        TreasuryInstruction::AirdropBatch { amounts } => {
+            process_airdrop_batch(program_id, accounts, amounts)
+        }
+        TreasuryInstruction::CloseTreasury => process_close_treasury(program_id, accounts),
+        TreasuryInstruction::CloseConfig => process_close_config(program_id, accounts),
+        TreasuryInstruction::GrowConfig { additional_bytes } => {
+            process_grow_config(program_id, accounts, additional_bytes)
+        }
+        TreasuryInstruction::SetPaused { claims, deposits } => {
+            process_set_paused(program_id, accounts, claims, deposits)
+        }
+        TreasuryInstruction::Deposit { amount } => process_deposit(program_id, accounts, amount),
+        TreasuryInstruction::GrantRole { authority, roles } => {
+            process_grant_role(program_id, accounts, authority, roles)
+        }
+        TreasuryInstruction::RevokeRole { authority, roles } => {
+            process_revoke_role(program_id, accounts, authority, roles)
+        }
+        TreasuryInstruction::RegisterMint => process_register_mint(program_id, accounts),
+        TreasuryInstruction::DeregisterMint { mint } => {
+            process_deregister_mint(program_id, accounts, mint)
+        }
+        TreasuryInstruction::ClaimMint { mint, amount } => {
+            process_claim_mint(program_id, accounts, mint, amount)
+        }
+        TreasuryInstruction::SetOwnerAndConfig { new_owner, params } => {
+            process_set_owner_and_config(program_id, accounts, new_owner, params)
+        }
+        TreasuryInstruction::SetVesting {
+            user,
+            start,
+            duration,
+            total,
+        } => process_set_vesting(program_id, accounts, user, start, duration, total),
+        TreasuryInstruction::ClaimVested { amount } => {
+            process_claim_vested(program_id, accounts, amount)
+        }
+        TreasuryInstruction::SimulateBatch { amounts } => {
+            process_simulate_batch(program_id, accounts, amounts)
+        }
+        TreasuryInstruction::CreateTreasuryTokenAccount => {
+            process_create_treasury_token_account(program_id, accounts)
+        }
+        TreasuryInstruction::AllocatePool { pool, amount } => {
+            process_allocate_pool(program_id, accounts, pool, amount)
+        }
+        TreasuryInstruction::ClaimPool { pool, amount } => {
+            process_claim_pool(program_id, accounts, pool, amount)
+        }
+        TreasuryInstruction::WithdrawSplit { total, amounts } => {
+            process_withdraw_split(program_id, accounts, total, amounts)
+        }
+        TreasuryInstruction::SetAllocationBatch { entries } => {
+            process_set_allocation_batch(program_id, accounts, entries)
+        }
+        TreasuryInstruction::ClaimTo {
+            amount,
+            destination,
+        } => process_claim_to(program_id, accounts, amount, destination),
+        TreasuryInstruction::Snapshot => process_snapshot(program_id, accounts),
+        TreasuryInstruction::SetMintFee { mint, fee_bps } => {
+            process_set_mint_fee(program_id, accounts, mint, fee_bps)
+        }
+        TreasuryInstruction::SetUri { uri } => process_set_uri(program_id, accounts, uri),
+        TreasuryInstruction::SetFeeVault { fee_vault } => {
+            process_set_fee_vault(program_id, accounts, fee_vault)
+        }
+        TreasuryInstruction::LockFunds { amount } => {
+            process_move_locked(program_id, accounts, amount, true)
+        }
+        TreasuryInstruction::UnlockFunds { amount } => {
+            process_move_locked(program_id, accounts, amount, false)
+        }
+        TreasuryInstruction::AirdropBatchPartial { amounts } => {
+            process_airdrop_batch_partial(program_id, accounts, amounts)
+        }
+        TreasuryInstruction::InitializeState => process_initialize_state(program_id, accounts),
+        TreasuryInstruction::ClaimAuthorized {
+            amount,
+            authorization,
+        } => process_claim_authorized(
+            program_id,
+            accounts,
+            amount,
+            authorization,
+            ClaimMode::Authorized,
+        ),
+        TreasuryInstruction::MigrateTreasury {
+            new_treasury_token_account,
+        } => process_migrate_treasury(program_id, accounts, new_treasury_token_account),
+        TreasuryInstruction::ReduceAllocation {
+            user,
+            new_allocation,
+        } => process_reduce_allocation(program_id, accounts, user, new_allocation),
+        TreasuryInstruction::SetEmergencyStop { stopped } => {
+            process_set_emergency_stop(program_id, accounts, stopped)
+        }
+        TreasuryInstruction::CreateCampaign {
+            campaign_id,
+            merkle_root,
+            total_cap,
+            per_user_cap,
+            starts_at,
+            ends_at,
+        } => process_create_campaign(
+            program_id,
+            accounts,
+            campaign_id,
+            merkle_root,
+            total_cap,
+            per_user_cap,
+            starts_at,
+            ends_at,
+        ),
+        TreasuryInstruction::ClaimCampaign {
+            campaign_id,
+            amount,
+            allocation,
+            proof,
+        } => process_claim_campaign(program_id, accounts, campaign_id, amount, allocation, proof),
+        TreasuryInstruction::ClaimSol { amount } => process_claim_sol(program_id, accounts, amount),
+        TreasuryInstruction::SetEligibilityMode { mode } => {
+            process_set_eligibility_mode(program_id, accounts, mode)
+        }
+        TreasuryInstruction::ForceCloseUserClaim { user } => {
+            process_force_close_user_claim(program_id, accounts, user)
+        }
+        TreasuryInstruction::GaslessClaim {
+            amount,
+            authorization,
+        } => process_claim_authorized(
+            program_id,
+            accounts,
+            amount,
+            authorization,
+            ClaimMode::Gasless,
+        ),
+        TreasuryInstruction::LogState => process_log_state(program_id, accounts),
+        TreasuryInstruction::SetupCampaign {
+            campaign_id,
+            merkle_root,
+            total_cap,
+            per_user_cap,
+            starts_at,
+            ends_at,
+            create_nonce_page,
+        } => process_setup_campaign(
+            program_id,
+            accounts,
+            campaign_id,
+            merkle_root,
+            total_cap,
+            per_user_cap,
+            starts_at,
+            ends_at,
+            create_nonce_page,
+        ),
+        TreasuryInstruction::ClaimWithReferral { amount } => {
+            process_claim_with_referral(program_id, accounts, amount)
+        }
+        TreasuryInstruction::SetGlobalUserCap { cap } => {
+            process_set_global_user_cap(program_id, accounts, cap)
+        }
+        TreasuryInstruction::AirdropBatchCapped {
+            amounts,
+            skip_over_cap,
+        } => process_airdrop_batch_capped(program_id, accounts, amounts, skip_over_cap),
+        TreasuryInstruction::SetDelegate { delegate, budget } => {
+            process_set_delegate(program_id, accounts, delegate, budget)
+        }
+        TreasuryInstruction::ClaimWithStake { amount } => {
+            process_claim_with_stake(program_id, accounts, amount)
+        }
+        TreasuryInstruction::Unstake => process_unstake(program_id, accounts),
+        TreasuryInstruction::ClaimWithReference { amount, reference } => {
+            process_claim_with_reference(program_id, accounts, amount, reference)
+        }
+        TreasuryInstruction::SetMinReserve {
+            min_reserve,
+            allow_over_balance,
+        } => process_set_min_reserve(program_id, accounts, min_reserve, allow_over_balance),
+        TreasuryInstruction::CreateVoucher { code_hash, amount } => {
+            process_create_voucher(program_id, accounts, code_hash, amount)
+        }
+        TreasuryInstruction::RedeemVoucher { code } => {
+            process_redeem_voucher(program_id, accounts, code)
+        }
+        TreasuryInstruction::Versioned {
+            client_version,
+            instruction,
+        } => process_versioned(program_id, accounts, client_version, &instruction),
+        TreasuryInstruction::InitializeStats => process_initialize_stats(program_id, accounts),
+        TreasuryInstruction::SimulateBatchCapped {
+            amounts,
+            skip_over_cap,
+        } => process_simulate_batch_capped(program_id, accounts, amounts, skip_over_cap),
+    }
+}
+
+/// `next_account_info` that logs which positional account was missing, so integrators see
+/// `Missing account: config_account` instead of a bare `NotEnoughAccountKeys`.
+fn next_account<'a, 'b, I: Iterator<Item = &'a AccountInfo<'b>>>(
+    iter: &mut I,
+    name: &str,
+) -> Result<I::Item, ProgramError> {
+    next_account_info(iter).inspect_err(|_| msg!("Missing account: {}", name))
+}
+
+/// Create a program-owned PDA of `space` bytes, skipping accounts that already exist.
+/// An address that already holds lamports (pre-funded, e.g. by a griefer) cannot go through
+/// `create_account`, so it is topped up to rent exemption, allocated and assigned instead.
+/// Returns `true` when the account was created by this call.
+fn create_pda_account<'a>(
+    payer: &AccountInfo<'a>,
+    account: &AccountInfo<'a>,
+    system_program: &AccountInfo<'a>,
+    program_id: &Pubkey,
+    space: usize,
+    seeds: &[&[u8]],
+) -> Result<bool, ProgramError> {
+    if account.owner == program_id && account.data_len() >= space {
+        return Ok(false);
+    }
+
+    let rent = Rent::get()?;
+    let required_lamports = rent.minimum_balance(space);
+
+    if account.lamports() == 0 {
+        let create_ix = system_instruction::create_account(
+            payer.key,
+            account.key,
+            required_lamports,
+            space as u64,
+            program_id,
+        );
+        invoke_signed(
+            &create_ix,
+            &[payer.clone(), account.clone(), system_program.clone()],
+            &[seeds],
+        )?;
+        return Ok(true);
+    }
+
+    // ✅ Only a plain system account can be taken over; anything else is not ours to reuse
+    if *account.owner != system_program::id() {
+        return Err(ProgramError::IllegalOwner);
+    }
+
+    let shortfall = required_lamports.saturating_sub(account.lamports());
+    if shortfall > 0 {
+        invoke(
+            &system_instruction::transfer(payer.key, account.key, shortfall),
+            &[payer.clone(), account.clone(), system_program.clone()],
+        )?;
+    }
+    invoke_signed(
+        &system_instruction::allocate(account.key, space as u64),
+        &[account.clone(), system_program.clone()],
+        &[seeds],
+    )?;
+    invoke_signed(
+        &system_instruction::assign(account.key, program_id),
+        &[account.clone(), system_program.clone()],
+        &[seeds],
+    )?;
+
+    Ok(true)
+}
+
+/// Read decimals from an SPL mint account.
+fn read_mint_decimals(mint: &AccountInfo) -> Result<u8, ProgramError> {
+    if *mint.owner != spl_token::id() {
+        return Err(ProgramError::IncorrectProgramId);
+    }
+    let mint_info =
+        Mint::unpack(&mint.try_borrow_data()?).map_err(|_| ProgramError::InvalidAccountData)?;
+    Ok(mint_info.decimals)
+}
+
+/// Close a program-owned account: zero its data, shrink it to zero length, hand it back to
+/// the system program and move all of its lamports to `recipient`.
+fn close_account(account: &AccountInfo, recipient: &AccountInfo) -> ProgramResult {
+    if account.key == recipient.key {
+        return Err(ProgramError::InvalidArgument);
+    }
+
+    account.try_borrow_mut_data()?.fill(0);
+
+    let lamports = account.lamports();
+    **recipient.try_borrow_mut_lamports()? = recipient
+        .lamports()
+        .checked_add(lamports)
+        .ok_or(ProgramError::ArithmeticOverflow)?;
+    **account.try_borrow_mut_lamports()? = 0;
+
+    account.realloc(0, false)?;
+    account.assign(&system_program::id());
+
+    Ok(())
+}
+
+/// Load the mint registry after checking it is the canonical, program-owned account.
+fn load_registry(
+    program_id: &Pubkey,
+    registry_account: &AccountInfo,
+) -> Result<MintRegistry, ProgramError> {
+    if registry_account.owner != program_id {
+        return Err(ProgramError::UninitializedAccount);
+    }
+    let registry = MintRegistry::decode(&registry_account.try_borrow_data()?)?;
+    let expected_registry_pda =
+        Pubkey::create_program_address(&[REGISTRY_SEED, &[registry.bump]], program_id)
+            .map_err(|_| ProgramError::InvalidSeeds)?;
+    if expected_registry_pda != *registry_account.key {
+        return Err(ProgramError::InvalidSeeds);
+    }
+    Ok(registry)
+}
+
+/// Load a user-claim PDA after checking its seeds and that it belongs to `user`.
+fn load_user_claim(
+    program_id: &Pubkey,
+    user_claim_account: &AccountInfo,
+    user: &Pubkey,
+) -> Result<UserClaim, ProgramError> {
+    if user_claim_account.owner != program_id {
+        return Err(ProgramError::UninitializedAccount);
+    }
+    let user_claim = UserClaim::decode(&user_claim_account.try_borrow_data()?)?;
+    let expected_user_claim_pda = Pubkey::create_program_address(
+        &[USER_CLAIM_SEED, user.as_ref(), &[user_claim.bump]],
+        program_id,
+    )
+    .map_err(|_| ProgramError::InvalidSeeds)?;
+    if expected_user_claim_pda != *user_claim_account.key || user_claim.user != *user {
+        return Err(ProgramError::InvalidSeeds);
+    }
+    Ok(user_claim)
+}
+
+/// Load a campaign PDA after checking its seeds and that it is `campaign_id`.
+fn load_campaign(
+    program_id: &Pubkey,
+    campaign_account: &AccountInfo,
+    campaign_id: u64,
+) -> Result<Campaign, ProgramError> {
+    if campaign_account.owner != program_id {
+        return Err(ProgramError::UninitializedAccount);
+    }
+    let campaign = Campaign::decode(&campaign_account.try_borrow_data()?)?;
+    let expected_campaign_pda = Pubkey::create_program_address(
+        &[CAMPAIGN_SEED, &campaign_id.to_le_bytes(), &[campaign.bump]],
+        program_id,
+    )
+    .map_err(|_| ProgramError::InvalidSeeds)?;
+    if expected_campaign_pda != *campaign_account.key || campaign.campaign_id != campaign_id {
+        return Err(ProgramError::InvalidSeeds);
+    }
+    Ok(campaign)
+}
+
+/// Load `user`'s claim PDA, creating an empty one paid for by `payer` if it does not exist.
+fn load_or_create_user_claim<'a>(
+    program_id: &Pubkey,
+    payer: &AccountInfo<'a>,
+    user_claim_account: &AccountInfo<'a>,
+    system_program: &AccountInfo<'a>,
+    user: &Pubkey,
+) -> Result<UserClaim, ProgramError> {
+    if user_claim_account.owner == program_id {
+        return load_user_claim(program_id, user_claim_account, user);
+    }
+
+    let (expected_user_claim_pda, bump) =
+        Pubkey::find_program_address(&[USER_CLAIM_SEED, user.as_ref()], program_id);
+    if expected_user_claim_pda != *user_claim_account.key {
+        return Err(ProgramError::InvalidSeeds);
+    }
+    create_pda_account(
+        payer,
+        user_claim_account,
+        system_program,
+        program_id,
+        8 + std::mem::size_of::<UserClaim>(),
+        &[USER_CLAIM_SEED, user.as_ref(), &[bump]],
+    )?;
+
+    Ok(UserClaim {
+        user: *user,
+        total: 0,
+        claimed: 0,
+        vesting_start: 0,
+        vesting_duration: 0,
+        bump,
+        claim_count: 0,
+    })
+}
+
+/// Load the config PDA after checking it is the canonical, initialized account.
+fn load_config(
+    program_id: &Pubkey,
+    config_account: &AccountInfo,
+) -> Result<TreasuryConfig, ProgramError> {
+    let (expected_config_pda, _) = Pubkey::find_program_address(&[CONFIG_SEED], program_id);
+    if expected_config_pda != *config_account.key {
+        return Err(ProgramError::InvalidSeeds);
+    }
+    if config_account.lamports() == 0 {
+        return Err(ProgramError::UninitializedAccount);
+    }
+    if config_account.owner != program_id {
+        return Err(ProgramError::IllegalOwner);
+    }
+
+    TreasuryConfig::decode(&config_account.try_borrow_data()?)
+}
+
+/// `load_config` for handlers that rewrite the config.
+fn load_config_mut(
+    program_id: &Pubkey,
+    config_account: &AccountInfo,
+) -> Result<TreasuryConfig, ProgramError> {
+    assert_writable(config_account, "config")?;
+    load_config(program_id, config_account)
+}
+
+/// Fail early, naming the account, when a client marks an account we mutate as read-only.
+fn assert_writable(account: &AccountInfo, name: &str) -> ProgramResult {
+    if !account.is_writable {
+        msg!("Account {} ({}) must be writable", name, account.key);
+        return Err(TreasuryError::AccountNotWritable.into());
+    }
+    Ok(())
+}
+
+/// Fail with a clear error before a transfer that would hit a frozen token account.
+fn assert_not_frozen(info: &TokenAccount, account: &AccountInfo) -> ProgramResult {
+    if info.is_frozen() {
+        msg!(
+            "Token account {} is frozen; thaw it before retrying",
+            account.key
+        );
+        return Err(TreasuryError::AccountFrozen.into());
+    }
+    Ok(())
+}
+
+/// Unpack an SPL token account and check it is the kind of account the caller expects:
+/// owned by the token program, holding `expected_mint` and, when given, controlled by
+/// `expected_owner`. Returns the unpacked account so callers can read balances.
+fn assert_token_account(
+    info: &AccountInfo,
+    expected_owner: Option<&Pubkey>,
+    expected_mint: &Pubkey,
+) -> Result<TokenAccount, ProgramError> {
+    if *info.owner != spl_token::id() {
+        msg!(
+            "Token account {} is not owned by the token program",
+            info.key
+        );
+        return Err(ProgramError::IncorrectProgramId);
+    }
+    let token_account = TokenAccount::unpack(&info.try_borrow_data()?)
+        .map_err(|_| ProgramError::InvalidAccountData)?;
+    if token_account.mint != *expected_mint {
+        msg!(
+            "Token account {} holds mint {}, expected {}",
+            info.key,
+            token_account.mint,
+            expected_mint
+        );
+        return Err(ProgramError::InvalidAccountData);
+    }
+    if let Some(expected_owner) = expected_owner {
+        if token_account.owner != *expected_owner {
+            msg!(
+                "Token account {} is controlled by {}, expected {}",
+                info.key,
+                token_account.owner,
+                expected_owner
+            );
+            return Err(ProgramError::IllegalOwner);
+        }
+    }
+    Ok(token_account)
+}
+
+/// Stamp `updated_at` and write the config back. Every config-mutating handler saves
+/// through here so auditors can see when the config last changed.
+fn store_config(config: &mut TreasuryConfig, config_account: &AccountInfo) -> ProgramResult {
+    config.updated_at = Clock::get()?.unix_timestamp;
+    config.serialize(&mut &mut config_account.try_borrow_mut_data()?[..])?;
+    Ok(())
+}
+
+/// Verify `config_account` sits at the config PDA derived from its own stored bump.
+fn assert_config_pda(
+    program_id: &Pubkey,
+    config: &TreasuryConfig,
+    config_account: &AccountInfo,
+) -> ProgramResult {
+    let expected_config_pda =
+        Pubkey::create_program_address(&[CONFIG_SEED, &[config.config_bump]], program_id)
+            .map_err(|_| ProgramError::InvalidSeeds)?;
+    if expected_config_pda != *config_account.key {
+        return Err(ProgramError::InvalidSeeds);
+    }
+    Ok(())
+}
+
+/// Verify `treasury_pda` against the bump stored in the config, and that the state it holds
+/// has been created. A config without state (a partial setup) fails with
+/// `StateNotInitialized` rather than an opaque owner or decode error.
+fn assert_treasury_pda(
+    program_id: &Pubkey,
+    config: &TreasuryConfig,
+    treasury_pda: &AccountInfo,
+) -> ProgramResult {
+    let expected_treasury_pda =
+        Pubkey::create_program_address(&[TREASURY_SEED, &[config.treasury_bump]], program_id)
+            .map_err(|_| ProgramError::InvalidSeeds)?;
+    if expected_treasury_pda != *treasury_pda.key {
+        return Err(ProgramError::InvalidSeeds);
+    }
+    if treasury_pda.lamports() == 0 || treasury_pda.data_is_empty() {
+        msg!(
+            "Treasury state {} is not initialized; run InitializeState",
+            treasury_pda.key
+        );
+        return Err(TreasuryError::StateNotInitialized.into());
+    }
+    if treasury_pda.owner != program_id {
+        return Err(ProgramError::IllegalOwner);
+    }
+    Ok(())
+}
+
+/// Verify `history_account` against the bump stored in the config.
+fn assert_history_pda(
+    program_id: &Pubkey,
+    config: &TreasuryConfig,
+    history_account: &AccountInfo,
+) -> ProgramResult {
+    let expected_history_pda =
+        Pubkey::create_program_address(&[HISTORY_SEED, &[config.history_bump]], program_id)
+            .map_err(|_| ProgramError::InvalidSeeds)?;
+    if expected_history_pda != *history_account.key || history_account.owner != program_id {
+        return Err(ProgramError::InvalidSeeds);
+    }
+    Ok(())
+}
+
+/// Add `amount` to the user's receipt for `campaign_id`, creating it on the first claim.
+/// Returns `true` when the receipt account was created by this call.
+#[allow(clippy::too_many_arguments)]
+fn record_receipt<'a>(
+    program_id: &Pubkey,
+    payer: &AccountInfo<'a>,
+    receipt_account: &AccountInfo<'a>,
+    system_program: &AccountInfo<'a>,
+    campaign_id: u64,
+    user: &Pubkey,
+    amount: u64,
+    now: i64,
+) -> Result<bool, ProgramError> {
+    let campaign_seed = campaign_id.to_le_bytes();
+
+    let (mut receipt, created) = if receipt_account.owner == program_id {
+        let receipt = ClaimReceipt::decode(&receipt_account.try_borrow_data()?)?;
+        let expected_receipt_pda = Pubkey::create_program_address(
+            &[RECEIPT_SEED, &campaign_seed, user.as_ref(), &[receipt.bump]],
+            program_id,
+        )
+        .map_err(|_| ProgramError::InvalidSeeds)?;
+        if expected_receipt_pda != *receipt_account.key
+            || receipt.campaign_id != campaign_id
+            || receipt.user != *user
+        {
+            return Err(ProgramError::InvalidSeeds);
+        }
+        (receipt, false)
+    } else {
+        let (expected_receipt_pda, bump) = Pubkey::find_program_address(
+            &[RECEIPT_SEED, &campaign_seed, user.as_ref()],
+            program_id,
+        );
+        if expected_receipt_pda != *receipt_account.key {
+            return Err(ProgramError::InvalidSeeds);
+        }
+        create_pda_account(
+            payer,
+            receipt_account,
+            system_program,
+            program_id,
+            8 + std::mem::size_of::<ClaimReceipt>(),
+            &[RECEIPT_SEED, &campaign_seed, user.as_ref(), &[bump]],
+        )?;
+        let receipt = ClaimReceipt {
+            campaign_id,
+            user: *user,
+            total_claimed: 0,
+            first_claimed_at: now,
+            last_claimed_at: now,
+            bump,
+        };
+        (receipt, true)
+    };
+
+    receipt.total_claimed = receipt
+        .total_claimed
+        .checked_add(amount)
+        .ok_or(ProgramError::ArithmeticOverflow)?;
+    receipt.last_claimed_at = now;
+
+    receipt.serialize(&mut &mut receipt_account.try_borrow_mut_data()?[..])?;
+
+    Ok(created)
+}
+
+/// The trailing accounts every payout path takes once `InitializeStats` ran: the stats PDA,
+/// the system program, then one claimant marker per user paid, in payment order.
+struct StatsAccounts<'s, 'a> {
+    stats: &'s AccountInfo<'a>,
+    system_program: &'s AccountInfo<'a>,
+    markers: &'s [AccountInfo<'a>],
+}
+
+/// Check the claim-stats accounts at the start of `tail` for a payout to `claimants` users.
+/// Returns `None` when the config has no stats, in which case `tail` is ignored.
+fn load_stats_accounts<'s, 'a>(
+    program_id: &Pubkey,
+    config: &TreasuryConfig,
+    tail: &'s [AccountInfo<'a>],
+    claimants: usize,
+) -> Result<Option<StatsAccounts<'s, 'a>>, ProgramError> {
+    let bump = match config.stats_bump {
+        Some(bump) => bump,
+        None => return Ok(None),
+    };
+    if tail.len() < 2 + claimants {
+        msg!(
+            "Claim stats need {} trailing accounts, got {}",
+            2 + claimants,
+            tail.len()
+        );
+        return Err(ProgramError::NotEnoughAccountKeys);
+    }
+
+    let stats = &tail[0];
+    let expected_stats_pda = Pubkey::create_program_address(&[STATS_SEED, &[bump]], program_id)
+        .map_err(|_| ProgramError::InvalidSeeds)?;
+    if expected_stats_pda != *stats.key || stats.owner != program_id {
+        msg!(
+            "Claim stats mismatch: expected={} got={}",
+            expected_stats_pda,
+            stats.key
+        );
+        return Err(ProgramError::InvalidSeeds);
+    }
+    assert_writable(stats, "stats")?;
+
+    let system_program = &tail[1];
+    if *system_program.key != system_program::id() {
+        return Err(ProgramError::IncorrectProgramId);
+    }
+
+    let markers = &tail[2..2 + claimants];
+    for marker in markers {
+        assert_writable(marker, "claimant_marker")?;
+    }
+
+    Ok(Some(StatsAccounts {
+        stats,
+        system_program,
+        markers,
+    }))
+}
+
+/// Check `user`'s claimant marker before any transfer. Returns the bump to create it with on
+/// the user's first payout, or `None` when the marker already exists.
+fn check_claimant_marker(
+    program_id: &Pubkey,
+    marker: &AccountInfo,
+    user: &Pubkey,
+) -> Result<Option<u8>, ProgramError> {
+    if marker.owner == program_id {
+        let existing = ClaimantMarker::decode(&marker.try_borrow_data()?)?;
+        let expected_marker_pda = Pubkey::create_program_address(
+            &[CLAIMANT_SEED, user.as_ref(), &[existing.bump]],
+            program_id,
+        )
+        .map_err(|_| ProgramError::InvalidSeeds)?;
+        if expected_marker_pda != *marker.key || existing.user != *user {
+            return Err(ProgramError::InvalidSeeds);
+        }
+        return Ok(None);
+    }
+
+    let (expected_marker_pda, bump) =
+        Pubkey::find_program_address(&[CLAIMANT_SEED, user.as_ref()], program_id);
+    if expected_marker_pda != *marker.key {
+        return Err(ProgramError::InvalidSeeds);
+    }
+    Ok(Some(bump))
+}
+
+/// Add one payout to `user` to the claim stats. `new_marker` is what `check_claimant_marker`
+/// returned for `markers[marker_index]`; the marker is created (paid for by `payer`) on the
+/// user's first payout, and only that counts towards `unique_claimants`. `tokens` is the
+/// treasury-mint amount paid; SOL and registry-mint payouts pass 0 so units never mix.
+#[allow(clippy::too_many_arguments)]
+fn record_stats<'a>(
+    program_id: &Pubkey,
+    payer: &AccountInfo<'a>,
+    stats_accounts: &StatsAccounts<'_, 'a>,
+    marker_index: usize,
+    user: &Pubkey,
+    new_marker: Option<u8>,
+    tokens: u64,
+    now: i64,
+) -> ProgramResult {
+    // 🔹 A user paid twice in one batch shares one marker, so only its creation counts
+    let first_payout = match new_marker {
+        Some(bump) => {
+            let marker = &stats_accounts.markers[marker_index];
+            let created = create_pda_account(
+                payer,
+                marker,
+                stats_accounts.system_program,
+                program_id,
+                8 + std::mem::size_of::<ClaimantMarker>(),
+                &[CLAIMANT_SEED, user.as_ref(), &[bump]],
+            )?;
+            if created {
+                let claimant = ClaimantMarker {
+                    user: *user,
+                    first_paid_at: now,
+                    bump,
+                };
+                claimant.serialize(&mut &mut marker.try_borrow_mut_data()?[..])?;
+            }
+            created
+        }
+        None => false,
+    };
+
+    let mut stats = ClaimStats::decode(&stats_accounts.stats.try_borrow_data()?)?;
+    stats.total_claimed = stats.total_claimed.saturating_add(tokens);
+    stats.total_claims = stats.total_claims.saturating_add(1);
+    stats.unique_claimants = stats.unique_claimants.saturating_add(first_payout as u64);
+    stats.last_claim_ts = now;
+    stats.serialize(&mut &mut stats_accounts.stats.try_borrow_mut_data()?[..])?;
+
+    Ok(())
+}
+
+/// Log a structured `CLAIM_REJECTED` event and hand back `error` for the caller to return.
+/// The return code is unchanged; the log only adds which check failed and, at
+/// `ERROR_VERBOSITY_FULL`, the values involved. Less verbose levels log fewer bytes.
+fn claim_rejected(
+    verbosity: u8,
+    error: impl Into<ProgramError>,
+    reason: &str,
+    detail: String,
+) -> ProgramError {
+    let error = error.into();
+    match verbosity {
+        ERROR_VERBOSITY_FULL => msg!(
+            "CLAIM_REJECTED reason={} error={} {}",
+            reason,
+            error,
+            detail
+        ),
+        ERROR_VERBOSITY_REASON => msg!("CLAIM_REJECTED reason={} error={}", reason, error),
+        _ => msg!("CLAIM_REJECTED error={}", error),
+    }
+    error
+}
+
+/// `error_verbosity`: log the failed check and the values involved (the default).
+pub const ERROR_VERBOSITY_FULL: u8 = 0;
+/// `error_verbosity`: log the failed check and error, without values.
+pub const ERROR_VERBOSITY_REASON: u8 = 1;
+/// `error_verbosity`: log only the error.
+pub const ERROR_VERBOSITY_CODE: u8 = 2;
+
+/// Move `amount` tokens out of a treasury token account, signed by the treasury PDA.
+/// `decimals` must match `mint`; for the primary mint this is the cached `config.decimals`.
+#[allow(clippy::too_many_arguments)]
+fn transfer_from_treasury<'a>(
+    config: &TreasuryConfig,
+    token_program: &AccountInfo<'a>,
+    treasury_token_account: &AccountInfo<'a>,
+    mint: &AccountInfo<'a>,
+    destination: &AccountInfo<'a>,
+    treasury_pda: &AccountInfo<'a>,
+    amount: u64,
+    decimals: u8,
+) -> ProgramResult {
+    let transfer_ix = token_instruction::transfer_checked(
+        token_program.key,
+        treasury_token_account.key,
+        mint.key,
+        destination.key,
+        treasury_pda.key,
+        &[],
+        amount,
+        decimals,
+    )?;
+
+    invoke_signed(
+        &transfer_ix,
+        &[
+            treasury_token_account.clone(),
+            mint.clone(),
+            destination.clone(),
+            treasury_pda.clone(),
+            token_program.clone(),
+        ],
+        &[&[TREASURY_SEED, &[config.treasury_bump]]],
+    )
+}
+
+/// Ensure `owner` is the stored treasury owner and has signed.
+fn assert_owner(config: &TreasuryConfig, owner: &AccountInfo) -> ProgramResult {
+    // ✅ A zeroed owner means nobody may act as owner, not that the zero key may
+    if config.owner == Pubkey::default() {
+        return Err(TreasuryError::OwnershipRenounced.into());
+    }
+    if config.owner != *owner.key {
+        return Err(ProgramError::IllegalOwner);
+    }
+    if !owner.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    Ok(())
+}
+
+/// Ensure `signer` signed and is either the owner or an authority holding `role`.
+fn assert_role(config: &TreasuryConfig, signer: &AccountInfo, role: u8) -> ProgramResult {
+    if !signer.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    if config.owner == *signer.key {
+        if config.owner == Pubkey::default() {
+            return Err(TreasuryError::OwnershipRenounced.into());
+        }
+        return Ok(());
+    }
+    let granted = config
+        .authorities
+        .iter()
+        .any(|authority| authority.key == *signer.key && authority.roles & role == role);
+    if !granted {
+        return Err(TreasuryError::MissingRole.into());
+    }
+    Ok(())
+}
+
+/// Basis-point denominator: 10_000 bps = 100%.
+pub const BPS_DENOMINATOR: u64 = 10_000;
+
+/// Claim multiplier applied by default: 1x.
+pub const DEFAULT_MULTIPLIER_BPS: u16 = BPS_DENOMINATOR as u16;
+
+/// Compute `amount * bps / 10_000` with a `u128` intermediate so large amounts cannot
+/// overflow mid-calculation. Fails with `ArithmeticOverflow` if the result exceeds `u64`,
+/// which is possible when `bps` is above 10_000 (e.g. multipliers).
+pub fn apply_bps(amount: u64, bps: u64) -> Result<u64, ProgramError> {
+    apply_bps_rounded(amount, bps, ROUNDING_FLOOR)
+}
+
+/// Claim eligibility: anyone the claim authority signs for (the default).
+pub const ELIGIBILITY_OPEN: u8 = 0;
+/// Claim eligibility: the user must have a user-claim PDA, i.e. be on the owner's list.
+pub const ELIGIBILITY_ALLOWLIST: u8 = 1;
+/// Claim eligibility: `Claim` is disabled; users prove membership through `ClaimCampaign`.
+pub const ELIGIBILITY_MERKLE: u8 = 2;
+/// Claim eligibility: payouts are bounded by and debited from the user's unlocked allocation.
+pub const ELIGIBILITY_ALLOCATION: u8 = 3;
+
+/// Round fractional results down (the default).
+pub const ROUNDING_FLOOR: u8 = 0;
+/// Round fractional results up.
+pub const ROUNDING_CEIL: u8 = 1;
+/// Round fractional results to the nearest unit, halves up.
+pub const ROUNDING_NEAREST: u8 = 2;
+
+/// `apply_bps` with an explicit `ROUNDING_*` mode for the fractional part.
+pub fn apply_bps_rounded(amount: u64, bps: u64, rounding: u8) -> Result<u64, ProgramError> {
+    let product = (amount as u128) * (bps as u128);
+    let denominator = BPS_DENOMINATOR as u128;
+    let scaled = match rounding {
+        ROUNDING_FLOOR => product / denominator,
+        ROUNDING_CEIL => product.div_ceil(denominator),
+        ROUNDING_NEAREST => (product + denominator / 2) / denominator,
+        _ => return Err(ProgramError::InvalidArgument),
+    };
+    u64::try_from(scaled).map_err(|_| ProgramError::ArithmeticOverflow)
+}
+
+/// Strict-mode sanity check on a raw claim amount. Zero and anything below one whole token
+/// (`10^decimals` base units) are rejected, since those almost always come from a client
+/// that forgot to scale a UI value by the mint decimals.
+fn validate_strict_amount(amount: u64, decimals: u8) -> ProgramResult {
+    let one_token = 10u64
+        .checked_pow(decimals as u32)
+        .ok_or(ProgramError::InvalidAccountData)?;
+    if amount == 0 || amount < one_token {
+        msg!(
+            "Amount {} is below one whole token ({} base units)",
+            amount,
+            one_token
+        );
+        return Err(TreasuryError::MalformedAmount.into());
+    }
+    Ok(())
+}
+
+pub fn process_initialize(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let payer = next_account(account_info_iter, "payer")?; // Funds account creation
+    let treasury_pda = next_account(account_info_iter, "treasury_pda")?; // Treasury PDA
+    let system_program = next_account(account_info_iter, "system_program")?;
+    let config_account = next_account(account_info_iter, "config_account")?; // Config PDA storing deployer key
+    let history_account = next_account(account_info_iter, "history_account")?; // Claim history PDA
+    let owner = next_account(account_info_iter, "owner")?; // Treasury owner (may differ from payer)
+    let mint = next_account(account_info_iter, "mint")?; // Mint the treasury will hold
+
+    // ✅ Ensure the owner explicitly consents to controlling the treasury
+    if !owner.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+
+    // ✅ Validate Treasury PDA
+    let (expected_treasury_pda, treasury_bump) =
+        Pubkey::find_program_address(&[TREASURY_SEED], program_id);
+    if expected_treasury_pda != *treasury_pda.key {
+        return Err(ProgramError::InvalidSeeds);
+    }
+
+    // ✅ Validate Config PDA
+    let (expected_config_pda, config_bump) =
+        Pubkey::find_program_address(&[CONFIG_SEED], program_id);
+    if expected_config_pda != *config_account.key {
+        return Err(ProgramError::InvalidSeeds);
+    }
+
+    // ✅ Validate History PDA
+    let (expected_history_pda, history_bump) =
+        Pubkey::find_program_address(&[HISTORY_SEED], program_id);
+    if expected_history_pda != *history_account.key {
+        return Err(ProgramError::InvalidSeeds);
+    }
+
+    let clock = Clock::get()?;
+    let decimals = read_mint_decimals(mint)?;
+
+    // 🔹 Only the discriminator marks the config as initialized. A half-created account at
+    // the address is completed, and a pre-funded system account is taken over by
+    // create_pda_account (top-up, allocate, assign), rather than either being rejected
+    if config_account.owner == program_id
+        && TreasuryConfig::is_initialized(&config_account.try_borrow_data()?)
+    {
+        return Err(ProgramError::AccountAlreadyInitialized);
+    }
+
+    // 🔹 Each PDA is created only if missing, so a previously interrupted setup can be
+    // completed by calling Initialize again. On success all three accounts exist and the
+    // config holds both bumps.
+    let treasury_space = 8 + std::mem::size_of::<TreasuryState>(); // Correct struct
+    create_pda_account(
+        payer,
+        treasury_pda,
+        system_program,
+        program_id,
+        treasury_space,
+        &[TREASURY_SEED, &[treasury_bump]], // Treasury PDA Seed
+    )?;
+
+    let config_space = 8 + std::mem::size_of::<TreasuryConfig>();
+    create_pda_account(
+        payer,
+        config_account,
+        system_program,
+        program_id,
+        config_space,
+        &[CONFIG_SEED, &[config_bump]], // Config PDA Seed
+    )?;
+
+    // 🔹 Zeroed data is an empty ring buffer
+    create_pda_account(
+        payer,
+        history_account,
+        system_program,
+        program_id,
+        HISTORY_SPACE,
+        &[HISTORY_SEED, &[history_bump]], // History PDA Seed
+    )?;
+
+    // ✅ Both PDAs must now belong to this program, whether created here or earlier
+    if config_account.owner != program_id || treasury_pda.owner != program_id {
+        return Err(ProgramError::IllegalOwner);
+    }
+
+    // ✅ Store the owner's public key and bumps in Config PDA
+    let mut config_data = config_account.try_borrow_mut_data()?;
+    let config = TreasuryConfig {
+        discriminator: CONFIG_DISCRIMINATOR,
+        owner: *owner.key, // Store owner's key, not the payer's
+        created_at: clock.unix_timestamp,
+        flat_fee_lamports: 0,
+        fee_vault: Pubkey::default(),
+        treasury_bump,
+        config_bump,
+        history_bump,
+        mint: *mint.key,
+        decimals,
+        owner_timelock_seconds: 0,
+        pending_owner: None,
+        owner_change_effective_at: 0,
+        claims_paused: false,
+        deposits_paused: false,
+        authorities: [Authority::default(); MAX_AUTHORITIES],
+        strict_amounts: false,
+        campaign_id: 0,
+        multiplier_bps: DEFAULT_MULTIPLIER_BPS,
+        claim_deadline: 0,
+        grace_period_seconds: 0,
+        late_fee_bps: 0,
+        enforce_treasury_ata: false,
+        gate_mint: None,
+        allocation_cap: 0,
+        max_claims_per_user: 0,
+        cosigner: None,
+        default_mint_fee_bps: 0,
+        uri: [0u8; MAX_URI_LEN],
+        rounding: ROUNDING_FLOOR,
+        updated_at: clock.unix_timestamp,
+        emergency_stop: false,
+        inactivity_pause_seconds: 0,
+        min_claim_interval: 0,
+        eligibility_mode: ELIGIBILITY_OPEN,
+        ramp_duration: 0,
+        initial_cap: 0,
+        final_cap: 0,
+        decay_start: 0,
+        decay_end: 0,
+        referral_bps: 0,
+        per_user_cap: 0,
+        withdraw_cooldown_seconds: 0,
+        require_ata: false,
+        delegate: None,
+        delegate_budget: 0,
+        stake_bps: 0,
+        stake_lock_seconds: 0,
+        error_verbosity: ERROR_VERBOSITY_FULL,
+        vault: None,
+        min_reserve: 0,
+        stats_bump: None,
+    };
+    config.serialize(&mut &mut config_data[..])?;
+
+    Ok(())
+}
+
+/// Claim `amount` tokens from the treasury to the user's token account.
+///
+/// Every account is borrowed and decoded exactly once, PDAs are re-derived from the bumps
+/// stored in the config instead of searched for, and all validation happens before any CPI.
+pub fn process_claim(program_id: &Pubkey, accounts: &[AccountInfo], amount: u64) -> ProgramResult {
+    execute_claim(
+        program_id,
+        accounts,
+        amount,
+        ClaimMode::Authority,
+        ClaimOptions::default(),
+    )
+    .map(|_| ())
+}
+
+/// Compute units a claim may be estimated at before it is refused up front; the default
+/// per-instruction limit.
+pub const CLAIM_COMPUTE_BUDGET: u32 = 200_000;
+
+/// Rough compute cost of a claim with the features this config and instruction turn on,
+/// plus the names of those features for the rejection log. The figures are conservative
+/// estimates, not measurements; they exist to fail with a clear reason instead of running
+/// out mid-CPI. Only heavy combinations trip the budget: a `GaslessClaim` with a flat fee,
+/// gate mint, co-signer, allocation mode, per-user cap and stats that creates both its receipt
+/// and its nonce page is estimated at 205_000 units and refused, and at 235_000 when it also
+/// creates the user's claimant marker.
+fn claim_compute_estimate(
+    config: &TreasuryConfig,
+    mode: ClaimMode,
+    options: &ClaimOptions,
+    creates_receipt: bool,
+    creates_marker: bool,
+) -> (u32, Vec<&'static str>) {
+    let mut units = 60_000; // PDA checks, vault transfer, state, history, receipt update
+    let mut features = Vec::new();
+    let mut add = |enabled: bool, cost: u32, name: &'static str| {
+        if enabled {
+            units += cost;
+            features.push(name);
+        }
+    };
+    add(config.flat_fee_lamports > 0, 8_000, "flat_fee");
+    add(config.gate_mint.is_some(), 10_000, "gate_mint");
+    add(config.cosigner.is_some(), 3_000, "cosigner");
+    add(
+        mode == ClaimMode::User
+            || matches!(
+                config.eligibility_mode,
+                ELIGIBILITY_ALLOWLIST | ELIGIBILITY_ALLOCATION
+            ),
+        12_000,
+        "user_claim",
+    );
+    add(config.per_user_cap > 0, 4_000, "per_user_cap");
+    add(creates_receipt, 30_000, "receipt_creation");
+    add(config.stats_bump.is_some(), 3_000, "claim_stats");
+    add(creates_marker, 30_000, "claimant_marker");
+    add(
+        matches!(mode, ClaimMode::Authorized | ClaimMode::Gasless),
+        45_000,
+        "signed_authorization",
+    );
+    add(options.referral_bps > 0, 25_000, "referral_bonus");
+    add(options.stake_bps > 0, 8_000, "stake");
+    add(
+        options.new_accounts > 0,
+        30_000 * options.new_accounts as u32,
+        "handler_accounts",
+    );
+    (units, features)
+}
+
+/// Who vouches for a `Claim`-shaped instruction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ClaimMode {
+    /// The owner or a `ROLE_CAN_CLAIM` authority signs the transaction.
+    Authority,
+    /// The owner signed off-chain; the authority slot is any signer paying for the receipt.
+    Authorized,
+    /// As `Authorized`, and the payer also covers the flat fee so the user never signs. The
+    /// payout still only goes to a token account the user controls.
+    Gasless,
+    /// The user signs for themselves and may take only what their own user-claim schedule
+    /// has unlocked, in any eligibility mode; the authority slot is any signer paying for the
+    /// receipt, usually the user.
+    User,
+}
+
+/// Per-instruction extras for `execute_claim`; the default is a plain `Claim`.
+#[derive(Debug, Clone, Copy, Default)]
+struct ClaimOptions {
+    /// Share of the payout withheld in the vault and locked instead of transferred.
+    stake_bps: u16,
+    /// Audit code logged with the `CLAIMED` event.
+    reference: Option<[u8; 16]>,
+    /// Share of the payout paid on top to a referrer. `execute_claim` checks and debits it
+    /// with the payout; the caller transfers it.
+    referral_bps: u16,
+    /// PDAs the calling handler created for this claim (nonce page, stake position), counted
+    /// in the compute estimate next to the receipt.
+    new_accounts: u8,
+    /// The user token account slot holds a destination the user named. It only has to hold
+    /// the treasury mint, and `require_ata` does not apply.
+    named_destination: bool,
+}
+
+/// What `execute_claim` paid, for handlers that act on it afterwards.
+#[derive(Debug, Clone, Copy)]
+struct ClaimOutcome {
+    payout: u64,
+    staked: u64, // Part of `payout` withheld for staking
+    bonus: u64,  // Referral bonus already debited from the tracked balance, not yet sent
+    seq: u64,    // Sequence number of the `CLAIMED` event
+}
+
+/// Shared body of every `Claim`-shaped instruction. Outside `ClaimMode::Authority` the owner
+/// has already authorized the claim through a verified ed25519 signature, or in
+/// `ClaimMode::User` the user's own signature and schedule do, so the authority slot only
+/// needs to be a signer paying for the receipt (e.g. a relayer).
+fn execute_claim(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    amount: u64,
+    mode: ClaimMode,
+    options: ClaimOptions,
+) -> Result<ClaimOutcome, ProgramError> {
+    let account_info_iter = &mut accounts.iter();
+    let reference = options
+        .reference
+        .as_ref()
+        .map(claim_reference)
+        .transpose()?;
+
+    let user = next_account(account_info_iter, "user")?;
+    let user_token_account = next_account(account_info_iter, "user_token_account")?;
+    let treasury_token_account = next_account(account_info_iter, "treasury_token_account")?;
+    let token_program = next_account(account_info_iter, "token_program")?;
+    let treasury_pda = next_account(account_info_iter, "treasury_pda")?;
+    let owner = next_account(account_info_iter, "owner")?; // Owner account (must sign)
+    let config_account = next_account(account_info_iter, "config_account")?; // Config PDA storing deployer
+    let history_account = next_account(account_info_iter, "history_account")?; // Claim history PDA
+    let fee_vault = next_account(account_info_iter, "fee_vault")?; // Receives the flat claim fee
+    let system_program = next_account(account_info_iter, "system_program")?;
+    let mint = next_account(account_info_iter, "mint")?; // Treasury mint, for transfer_checked
+    let receipt_account = next_account(account_info_iter, "receipt_account")?; // Receipt PDA for (campaign, user)
+
+    // ✅ Catch read-only account metas before any check that could mask them
+    for (account, name) in [
+        (user_token_account, "user_token_account"),
+        (treasury_token_account, "treasury_token_account"),
+        (treasury_pda, "treasury_pda"),
+        (history_account, "history"),
+        (receipt_account, "receipt"),
+    ] {
+        assert_writable(account, name).map_err(|e| {
+            claim_rejected(
+                ERROR_VERBOSITY_FULL,
+                e,
+                "not_writable",
+                format!("account={}", name),
+            )
+        })?;
+    }
+
+    // ✅ Ensure Config PDA is initialized and decode it once
+    if config_account.lamports() == 0 {
+        return Err(claim_rejected(
+            ERROR_VERBOSITY_FULL,
+            ProgramError::UninitializedAccount,
+            "config_uninitialized",
+            format!("config={}", config_account.key),
+        ));
+    }
+    if config_account.owner != program_id {
+        return Err(claim_rejected(
+            ERROR_VERBOSITY_FULL,
+            ProgramError::IllegalOwner,
+            "config_owner",
+            format!("config_owner={}", config_account.owner),
+        ));
+    }
+    let mut config = TreasuryConfig::decode(&config_account.try_borrow_data()?)?;
+
+    // ✅ Verify config PDA using the stored bump, as for the treasury PDA below
+    assert_config_pda(program_id, &config, config_account).map_err(|e| {
+        claim_rejected(
+            config.error_verbosity,
+            e,
+            "config_pda",
+            format!("config={}", config_account.key),
+        )
+    })?;
+
+    if config.claims_paused {
+        return Err(claim_rejected(
+            config.error_verbosity,
+            TreasuryError::ClaimsPaused,
+            "claims_paused",
+            String::new(),
+        ));
+    }
+
+    // 🔹 An owner who has gone quiet should not leave claims running unattended
+    let now = Clock::get()?.unix_timestamp;
+    if config.inactivity_pause_seconds > 0
+        && now.saturating_sub(config.updated_at) > config.inactivity_pause_seconds
+    {
+        return Err(claim_rejected(
+            config.error_verbosity,
+            TreasuryError::ClaimsPaused,
+            "inactive",
+            format!(
+                "updated_at={} inactivity_pause_seconds={}",
+                config.updated_at, config.inactivity_pause_seconds
+            ),
+        ));
+    }
+
+    if config.strict_amounts {
+        validate_strict_amount(amount, config.decimals).map_err(|e| {
+            claim_rejected(
+                config.error_verbosity,
+                e,
+                "malformed_amount",
+                format!("amount={} decimals={}", amount, config.decimals),
+            )
+        })?;
+    }
+
+    // ✅ Verify treasury PDA using the stored bump
+    assert_treasury_pda(program_id, &config, treasury_pda).map_err(|e| {
+        claim_rejected(
+            config.error_verbosity,
+            e,
+            "treasury_pda",
+            format!("treasury={}", treasury_pda.key),
+        )
+    })?;
+
+    // ✅ Catch a misplaced PDA in the owner slot before the generic signer check
+    if owner.key == treasury_pda.key || owner.key == config_account.key {
+        return Err(claim_rejected(
+            config.error_verbosity,
+            TreasuryError::AuthorityIsProgramAccount,
+            "owner_is_program_account",
+            format!("owner={}", owner.key),
+        ));
+    }
+
+    // ✅ Ensure only the owner, a claim authority or the budgeted delegate can execute
+    let delegated = mode == ClaimMode::Authority
+        && owner.is_signer
+        && config.owner != *owner.key
+        && config.delegate == Some(*owner.key);
+    if delegated {
+        assert_writable(config_account, "config_account").map_err(|e| {
+            claim_rejected(
+                config.error_verbosity,
+                e,
+                "not_writable",
+                "account=config_account".into(),
+            )
+        })?;
+    }
+    if mode == ClaimMode::User && !user.is_signer {
+        return Err(claim_rejected(
+            config.error_verbosity,
+            ProgramError::MissingRequiredSignature,
+            "user_not_signer",
+            format!("user={}", user.key),
+        ));
+    }
+    if mode != ClaimMode::Authority {
+        if !owner.is_signer {
+            return Err(claim_rejected(
+                config.error_verbosity,
+                ProgramError::MissingRequiredSignature,
+                "payer_not_signer",
+                format!("payer={}", owner.key),
+            ));
+        }
+    } else if !delegated {
+        assert_role(&config, owner, ROLE_CAN_CLAIM).map_err(|e| {
+            claim_rejected(
+                config.error_verbosity,
+                e,
+                "unauthorized",
+                format!("signer={} is_signer={}", owner.key, owner.is_signer),
+            )
+        })?;
+    }
+
+    // ✅ Validate the flat claim fee can be charged to the user (or the relayer when gasless)
+    let fee_payer = if mode == ClaimMode::Gasless {
+        owner
+    } else {
+        user
+    };
+    if config.flat_fee_lamports > 0 {
+        if config.fee_vault != *fee_vault.key {
+            return Err(claim_rejected(
+                config.error_verbosity,
+                ProgramError::InvalidAccountData,
+                "fee_vault_mismatch",
+                format!("expected={} got={}", config.fee_vault, fee_vault.key),
+            ));
+        }
+        if !fee_payer.is_signer {
+            return Err(claim_rejected(
+                config.error_verbosity,
+                ProgramError::MissingRequiredSignature,
+                "fee_payer_not_signer",
+                format!("fee_payer={}", fee_payer.key),
+            ));
+        }
+        for (account, name) in [(fee_payer, "fee_payer"), (fee_vault, "fee_vault")] {
+            assert_writable(account, name).map_err(|e| {
+                claim_rejected(
+                    config.error_verbosity,
+                    e,
+                    "not_writable",
+                    format!("account={}", name),
+                )
+            })?;
+        }
+        if fee_payer.lamports() < config.flat_fee_lamports {
+            return Err(claim_rejected(
+                config.error_verbosity,
+                ProgramError::InsufficientFunds,
+                "fee_unpaid",
+                format!(
+                    "fee={} fee_payer_lamports={}",
+                    config.flat_fee_lamports,
+                    fee_payer.lamports()
+                ),
+            ));
+        }
+    }
+
+    // ✅ Verify treasury token account's owner is treasury PDA
+    let treasury_token_account_info =
+        assert_token_account(treasury_token_account, Some(treasury_pda.key), &config.mint)
+            .map_err(|e| {
+                claim_rejected(
+                    config.error_verbosity,
+                    e,
+                    "vault_account",
+                    format!("vault={}", treasury_token_account.key),
+                )
+            })?;
+    assert_not_frozen(&treasury_token_account_info, treasury_token_account).map_err(|e| {
+        claim_rejected(
+            config.error_verbosity,
+            e,
+            "vault_frozen",
+            format!("vault={}", treasury_token_account.key),
+        )
+    })?;
+    if config.enforce_treasury_ata {
+        let expected_vault = get_associated_token_address(treasury_pda.key, &config.mint);
+        if expected_vault != *treasury_token_account.key {
+            return Err(claim_rejected(
+                config.error_verbosity,
+                ProgramError::InvalidSeeds,
+                "vault_not_ata",
+                format!(
+                    "expected={} got={}",
+                    expected_vault, treasury_token_account.key
+                ),
+            ));
+        }
+    }
+
+    // ✅ Once recorded, only the config's vault may fund a claim, not any treasury-owned account
+    if let Some(vault) = config.vault {
+        if vault != *treasury_token_account.key {
+            return Err(claim_rejected(
+                config.error_verbosity,
+                ProgramError::InvalidAccountData,
+                "vault_mismatch",
+                format!("expected={} got={}", vault, treasury_token_account.key),
+            ));
+        }
+    }
+
+    // ✅ Only the claimer's canonical ATA may receive the payout, unless they named another
+    if config.require_ata && !options.named_destination {
+        let expected_destination = get_associated_token_address(user.key, &config.mint);
+        if expected_destination != *user_token_account.key {
+            return Err(claim_rejected(
+                config.error_verbosity,
+                ProgramError::InvalidAccountData,
+                "destination_not_ata",
+                format!(
+                    "expected={} got={}",
+                    expected_destination, user_token_account.key
+                ),
+            ));
+        }
+    }
+
+    // ✅ A signed authorization names the user, not the destination, so the submitter may
+    // only route the payout to a token account the user controls. A destination the user
+    // signed for themselves only has to hold the right mint
+    if options.named_destination {
+        assert_token_account(user_token_account, None, &config.mint).map_err(|e| {
+            claim_rejected(
+                config.error_verbosity,
+                e,
+                "destination_mint",
+                format!("destination={}", user_token_account.key),
+            )
+        })?;
+    } else if mode != ClaimMode::Authority {
+        assert_token_account(user_token_account, Some(user.key), &config.mint).map_err(|e| {
+            claim_rejected(
+                config.error_verbosity,
+                e,
+                "destination_owner",
+                format!("user={} destination={}", user.key, user_token_account.key),
+            )
+        })?;
+    }
+
+    // ✅ Token-gated claims: the user must present a funded account of the gate mint
+    if let Some(gate_mint) = config.gate_mint {
+        let gate_account = next_account_info(account_info_iter).map_err(|e| {
+            claim_rejected(
+                config.error_verbosity,
+                e,
+                "gate_account_missing",
+                format!("gate_mint={}", gate_mint),
+            )
+        })?;
+        let eligible = assert_token_account(gate_account, Some(user.key), &gate_mint)
+            .is_ok_and(|holding| holding.amount > 0);
+        if !eligible {
+            return Err(claim_rejected(
+                config.error_verbosity,
+                TreasuryError::NotEligible,
+                "not_eligible",
+                format!("user={} gate_account={}", user.key, gate_account.key),
+            ));
+        }
+    }
+
+    // ✅ Co-signed claims need both the user and the designated co-signer
+    if let Some(cosigner) = config.cosigner {
+        let cosigner_account = next_account_info(account_info_iter).map_err(|e| {
+            claim_rejected(
+                config.error_verbosity,
+                e,
+                "cosigner_missing",
+                format!("cosigner={}", cosigner),
+            )
+        })?;
+        let user_signed = user.is_signer || mode == ClaimMode::Gasless;
+        if *cosigner_account.key != cosigner || !cosigner_account.is_signer || !user_signed {
+            return Err(claim_rejected(
+                config.error_verbosity,
+                ProgramError::MissingRequiredSignature,
+                "cosigner_signature",
+                format!("cosigner={} user={}", cosigner, user.key),
+            ));
+        }
+    }
+
+    // ✅ Route eligibility by the configured mode; list-based modes take the user-claim PDA,
+    // as do user-signed claims, which are bounded by the user's own schedule in any mode
+    if config.eligibility_mode == ELIGIBILITY_MERKLE && mode != ClaimMode::User {
+        return Err(claim_rejected(
+            config.error_verbosity,
+            TreasuryError::NotEligible,
+            "merkle_mode",
+            format!("user={} use=ClaimCampaign", user.key),
+        ));
+    }
+    let vesting = mode == ClaimMode::User || config.eligibility_mode == ELIGIBILITY_ALLOCATION;
+    let mut listed = None;
+    if vesting || config.eligibility_mode == ELIGIBILITY_ALLOWLIST {
+        let user_claim_account = next_account_info(account_info_iter).map_err(|e| {
+            claim_rejected(
+                config.error_verbosity,
+                e,
+                "user_claim_missing",
+                format!("user={}", user.key),
+            )
+        })?;
+        let user_claim =
+            load_user_claim(program_id, user_claim_account, user.key).map_err(|_| {
+                claim_rejected(
+                    config.error_verbosity,
+                    TreasuryError::NotEligible,
+                    "not_listed",
+                    format!("user={} user_claim={}", user.key, user_claim_account.key),
+                )
+            })?;
+        // 🔹 Written back on success: every claim counts, vesting claims also debit
+        assert_writable(user_claim_account, "user_claim")?;
+        if config.max_claims_per_user > 0 && user_claim.claim_count >= config.max_claims_per_user {
+            return Err(claim_rejected(
+                config.error_verbosity,
+                TreasuryError::ClaimLimitReached,
+                "claim_limit",
+                format!(
+                    "claim_count={} max={}",
+                    user_claim.claim_count, config.max_claims_per_user
+                ),
+            ));
+        }
+        listed = Some((user_claim_account, user_claim));
+    }
+    let allocation = listed
+        .as_ref()
+        .map(|(_, user_claim)| user_claim)
+        .filter(|_| vesting);
+
+    // ✅ Once stats exist every claim must pass them, so the totals never miss a claim
+    let stats_accounts = load_stats_accounts(program_id, &config, account_info_iter.as_slice(), 1)
+        .map_err(|e| claim_rejected(config.error_verbosity, e, "stats_accounts", String::new()))?;
+    let new_marker = match &stats_accounts {
+        Some(stats_accounts) => {
+            check_claimant_marker(program_id, &stats_accounts.markers[0], user.key).map_err(
+                |e| {
+                    claim_rejected(
+                        config.error_verbosity,
+                        e,
+                        "claimant_marker",
+                        format!("got={}", stats_accounts.markers[0].key),
+                    )
+                },
+            )?
+        }
+        None => None,
+    };
+
+    // 🔹 Refuse up front rather than run out of compute halfway through the CPIs
+    let (estimate, features) = claim_compute_estimate(
+        &config,
+        mode,
+        &options,
+        receipt_account.owner != program_id,
+        new_marker.is_some(),
+    );
+    if estimate > CLAIM_COMPUTE_BUDGET {
+        return Err(claim_rejected(
+            config.error_verbosity,
+            TreasuryError::ComputeBudgetExceeded,
+            "compute_budget",
+            format!(
+                "estimate={} budget={} features={}; disable a feature or split the work",
+                estimate,
+                CLAIM_COMPUTE_BUDGET,
+                features.join(",")
+            ),
+        ));
+    }
+
+    // ✅ Decimals come from the config cache, so the mint only needs to match by key
+    if config.mint != *mint.key {
+        return Err(claim_rejected(
+            config.error_verbosity,
+            ProgramError::InvalidAccountData,
+            "mint_mismatch",
+            format!("expected={} got={}", config.mint, mint.key),
+        ));
+    }
+
+    // ✅ Apply the reward multiplier and make sure the vault can cover the payout
+    let mut payout = config.claim_payout(amount).map_err(|e| {
+        claim_rejected(
+            config.error_verbosity,
+            e,
+            "payout_overflow",
+            format!("amount={} multiplier_bps={}", amount, config.multiplier_bps),
+        )
+    })?;
+
+    // ✅ Past the deadline, claims pay the late fee until the grace period ends
+    if config.claim_deadline > 0 && now > config.claim_deadline {
+        let grace_end = config
+            .claim_deadline
+            .saturating_add(config.grace_period_seconds);
+        if now > grace_end {
+            return Err(claim_rejected(
+                config.error_verbosity,
+                TreasuryError::ClaimWindowClosed,
+                "claim_window_closed",
+                format!("now={} grace_end={}", now, grace_end),
+            ));
+        }
+        let late_fee = apply_bps(payout, config.late_fee_bps as u64)?;
+        payout -= late_fee;
+        msg!("Late claim: {} withheld as late fee", late_fee);
+    }
+
+    // 🔹 Early claimers may take more: the per-claim cap decays over the configured window
+    if let Some(cap) = config.claim_cap_at(now) {
+        if payout > cap {
+            return Err(claim_rejected(
+                config.error_verbosity,
+                TreasuryError::ExceedsClaimCap,
+                "claim_cap",
+                format!("payout={} cap={}", payout, cap),
+            ));
+        }
+    }
+
+    // 🔹 Anti-dump: one claim may take at most the ramped share of the allocation
+    if let Some((_, user_claim)) = listed.as_ref().filter(|_| config.ramp_duration > 0) {
+        let ceiling = vested_amount(
+            user_claim.total,
+            user_claim.vesting_start,
+            config.ramp_duration,
+            now,
+        );
+        if payout > ceiling {
+            return Err(claim_rejected(
+                config.error_verbosity,
+                TreasuryError::ExceedsRampCeiling,
+                "ramp_ceiling",
+                format!(
+                    "payout={} ceiling={} ramp_duration={}",
+                    payout, ceiling, config.ramp_duration
+                ),
+            ));
+        }
+    }
+
+    if let Some(user_claim) = allocation {
+        let claimable = user_claim.claimable(now);
+        if payout > claimable {
+            return Err(claim_rejected(
+                config.error_verbosity,
+                TreasuryError::ExceedsVested,
+                "exceeds_allocation",
+                format!("payout={} claimable={}", payout, claimable),
+            ));
+        }
+    }
+
+    // 🔹 The global per-user cap counts everything already on the user's campaign receipt
+    if config.per_user_cap > 0 {
+        let already_claimed = if receipt_account.owner == program_id {
+            ClaimReceipt::decode(&receipt_account.try_borrow_data()?)?.total_claimed
+        } else {
+            0
+        };
+        let remaining = config.per_user_cap.saturating_sub(already_claimed);
+        if payout > remaining {
+            return Err(claim_rejected(
+                config.error_verbosity,
+                TreasuryError::UserCapReached,
+                "user_cap",
+                format!(
+                    "payout={} claimed={} cap={}",
+                    payout, already_claimed, config.per_user_cap
+                ),
+            ));
+        }
+    }
+
+    if delegated && payout > config.delegate_budget {
+        return Err(claim_rejected(
+            config.error_verbosity,
+            TreasuryError::DelegateBudgetExhausted,
+            "delegate_budget",
+            format!(
+                "payout={} delegate_budget={}",
+                payout, config.delegate_budget
+            ),
+        ));
+    }
+
+    // 🔹 A referral bonus leaves the same vault, so every balance check below counts it
+    let bonus = apply_bps(payout, options.referral_bps as u64)?;
+    let outgoing = payout
+        .checked_add(bonus)
+        .ok_or(ProgramError::ArithmeticOverflow)?;
+    let staked = apply_bps(payout, options.stake_bps as u64)?;
+    let liquid = payout - staked;
+
+    // 🔹 The reserve is measured against the tracked balance, as SetMinReserve validates it.
+    // A staked share stays in that balance, so only what actually leaves is counted
+    if config.min_reserve > 0 {
+        let balance = TreasuryState::decode(&treasury_pda.try_borrow_data()?)?.balance;
+        let debited = liquid + bonus;
+        if balance.saturating_sub(debited) < config.min_reserve {
+            return Err(claim_rejected(
+                config.error_verbosity,
+                TreasuryError::BelowMinReserve,
+                "min_reserve",
+                format!(
+                    "debited={} balance={} min_reserve={}",
+                    debited, balance, config.min_reserve
+                ),
+            ));
+        }
+    }
+
+    if treasury_token_account_info.amount < outgoing {
+        return Err(claim_rejected(
+            config.error_verbosity,
+            TreasuryError::InsufficientTreasuryBalance,
+            "vault_balance",
+            format!(
+                "payout={} bonus={} vault_amount={}",
+                payout, bonus, treasury_token_account_info.amount
+            ),
+        ));
+    }
+
+    // ✅ Verify history PDA using the stored bump
+    assert_history_pda(program_id, &config, history_account).map_err(|e| {
+        claim_rejected(
+            config.error_verbosity,
+            e,
+            "history_pda",
+            format!("history={}", history_account.key),
+        )
+    })?;
+
+    // ✅ The rate limit and stake lock are state checks too, so they run before any CPI
+    let mut state = TreasuryState::decode(&treasury_pda.try_borrow_data()?)?;
+    state
+        .note_claim_time(now, config.min_claim_interval)
+        .map_err(|e| {
+            claim_rejected(
+                config.error_verbosity,
+                e,
+                "rate_limited",
+                format!("min_claim_interval={}", config.min_claim_interval),
+            )
+        })?;
+    // ✅ Vesting payouts come out of the user's own lock; others only from liquid
+    if allocation.is_some() {
+        state.total_allocated = state.total_allocated.saturating_sub(payout);
+        state.unlock(payout).map_err(|e| {
+            claim_rejected(
+                config.error_verbosity,
+                e,
+                "allocation_lock",
+                format!("payout={} locked_balance={}", payout, state.locked_balance),
+            )
+        })?;
+    }
+    let liquid_balance = state.liquid_balance();
+    state.pay_out(liquid).map_err(|e| {
+        claim_rejected(
+            config.error_verbosity,
+            e,
+            "liquid_balance",
+            format!("payout={} liquid_balance={}", liquid, liquid_balance),
+        )
+    })?;
+    state.pay_out(bonus).map_err(|e| {
+        claim_rejected(
+            config.error_verbosity,
+            e,
+            "liquid_balance",
+            format!("bonus={} liquid_balance={}", bonus, state.liquid_balance()),
+        )
+    })?;
+    state.lock(staked).map_err(|e| {
+        claim_rejected(
+            config.error_verbosity,
+            e,
+            "stake_lock",
+            format!("staked={}", staked),
+        )
+    })?;
+    let seq = state.next_claim_seq()?;
+
+    // 🔹 All checks passed, move funds
+    if config.flat_fee_lamports > 0 {
+        let fee_ix =
+            system_instruction::transfer(fee_payer.key, fee_vault.key, config.flat_fee_lamports);
+        invoke(
+            &fee_ix,
+            &[fee_payer.clone(), fee_vault.clone(), system_program.clone()],
+        )?;
+    }
+
+    // ✅ Transfer tokens from treasury PDA to user, less any staked share
+    if liquid > 0 {
+        transfer_from_treasury(
+            &config,
+            token_program,
+            treasury_token_account,
+            mint,
+            user_token_account,
+            treasury_pda,
+            liquid,
+            config.decimals,
+        )?;
+    }
+
+    // ✅ Keep the tracked balance in step with the vault
+    state.serialize(&mut &mut treasury_pda.try_borrow_mut_data()?[..])?;
+
+    // ✅ Record the claim in the history ring buffer
+    let record = ClaimRecord {
+        user: *user.key,
+        amount: payout,
+        timestamp: now,
+    };
+    append_history(&mut history_account.try_borrow_mut_data()?, &record)?;
+
+    if let Some((user_claim_account, mut user_claim)) = listed {
+        if vesting {
+            user_claim.claimed = user_claim
+                .claimed
+                .checked_add(payout)
+                .ok_or(ProgramError::ArithmeticOverflow)?;
+        }
+        user_claim.claim_count = user_claim
+            .claim_count
+            .checked_add(1)
+            .ok_or(ProgramError::ArithmeticOverflow)?;
+        user_claim.serialize(&mut &mut user_claim_account.try_borrow_mut_data()?[..])?;
+    }
+
+    // 🔹 Written without store_config: delegate activity must not count as the owner's
+    if delegated {
+        config.delegate_budget -= payout;
+        config.serialize(&mut &mut config_account.try_borrow_mut_data()?[..])?;
+    }
+
+    // ✅ Update the user's receipt for the current campaign, paid for by the signer
+    record_receipt(
+        program_id,
+        owner,
+        receipt_account,
+        system_program,
+        config.campaign_id,
+        user.key,
+        payout,
+        now,
+    )?;
+
+    // 🔹 The referral bonus leaves the vault with this claim, so it is counted with it
+    if let Some(stats_accounts) = &stats_accounts {
+        record_stats(
+            program_id,
+            owner,
+            stats_accounts,
+            0,
+            user.key,
+            new_marker,
+            outgoing,
+            now,
+        )?;
+    }
+
+    emit_claimed(user.key, &config.mint, payout, seq, reference);
+
+    Ok(ClaimOutcome {
+        payout,
+        staked,
+        bonus,
+        seq,
+    })
+}
+
+/// Claim from a wSOL treasury. Accounts are the same as `Claim`; when `unwrap` is set the
+/// user must also sign so their wSOL token account can be closed into lamports.
+pub fn process_claim_native(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    amount: u64,
+    unwrap: bool,
+) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+
+    let user = next_account(account_info_iter, "user")?;
+    let user_token_account = next_account(account_info_iter, "user_token_account")?;
+    let treasury_token_account = next_account(account_info_iter, "treasury_token_account")?;
+    let token_program = next_account(account_info_iter, "token_program")?;
+
+    // ✅ Native claims only make sense when both sides hold wSOL
+    assert_token_account(treasury_token_account, None, &native_mint::id())?;
+    let user_token_account_info =
+        assert_token_account(user_token_account, None, &native_mint::id())?;
+
+    // 🔹 Closing the token account needs the user's signature, check before moving funds
+    if unwrap {
+        if user_token_account_info.owner != *user.key {
+            return Err(ProgramError::IllegalOwner);
+        }
+        if !user.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+    }
+
+    process_claim(program_id, accounts, amount)?;
+
+    if unwrap {
+        // ✅ Close the wSOL account so the user receives plain lamports
+        let close_ix = token_instruction::close_account(
+            token_program.key,
+            user_token_account.key,
+            user.key,
+            user.key,
+            &[],
+        )?;
+
+        invoke(
+            &close_ix,
+            &[
+                user_token_account.clone(),
+                user.clone(),
+                token_program.clone(),
+            ],
+        )?;
+    }
+
+    Ok(())
+}
+
+/// Pay `amount` lamports held directly by the treasury PDA to `recipient`. The PDA must stay
+/// rent-exempt for its data size afterwards, so a claim can never close the state account.
+/// Once claim stats exist, the stats accounts follow and the authority pays for the
+/// recipient's claimant marker.
+pub fn process_claim_sol(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    amount: u64,
+) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let authority = next_account(account_info_iter, "authority")?; // Owner or ROLE_CAN_CLAIM (must sign)
+    let config_account = next_account(account_info_iter, "config_account")?; // Config PDA
+    let treasury_pda = next_account(account_info_iter, "treasury_pda")?; // Treasury PDA holding the SOL
+    let recipient = next_account(account_info_iter, "recipient")?; // Receives the lamports
+
+    assert_writable(treasury_pda, "treasury_pda")?;
+    assert_writable(recipient, "recipient")?;
+
+    let config = load_config(program_id, config_account)?;
+    if config.claims_paused {
+        return Err(TreasuryError::ClaimsPaused.into());
+    }
+    assert_role(&config, authority, ROLE_CAN_CLAIM)?;
+    assert_treasury_pda(program_id, &config, treasury_pda)?;
+    if treasury_pda.owner != program_id {
+        return Err(ProgramError::IllegalOwner);
+    }
+    if amount == 0 {
+        return Err(ProgramError::InvalidArgument);
+    }
+
+    // ✅ Only lamports above the rent-exempt minimum are claimable
+    let rent_floor = Rent::get()?.minimum_balance(treasury_pda.data_len());
+    let claimable = treasury_pda.lamports().saturating_sub(rent_floor);
+    if amount > claimable {
+        msg!(
+            "ClaimSol of {} would breach the rent floor of {}; at most {} is claimable",
+            amount,
+            rent_floor,
+            claimable
+        );
+        return Err(TreasuryError::BelowRentFloor.into());
+    }
+
+    // ✅ Once stats exist every payout must pass them, so the totals never miss one
+    let stats_accounts = load_stats_accounts(program_id, &config, account_info_iter.as_slice(), 1)?;
+    let new_marker = match &stats_accounts {
+        Some(stats_accounts) => {
+            check_claimant_marker(program_id, &stats_accounts.markers[0], recipient.key)?
+        }
+        None => None,
+    };
+
+    let now = Clock::get()?.unix_timestamp;
+    let mut state = TreasuryState::decode(&treasury_pda.try_borrow_data()?)?;
+    state.note_claim_time(now, config.min_claim_interval)?;
+    let seq = state.next_claim_seq()?;
+    state.serialize(&mut &mut treasury_pda.try_borrow_mut_data()?[..])?;
+
+    **treasury_pda.try_borrow_mut_lamports()? -= amount;
+    **recipient.try_borrow_mut_lamports()? = recipient
+        .lamports()
+        .checked_add(amount)
+        .ok_or(ProgramError::ArithmeticOverflow)?;
+
+    // 🔹 Lamports are counted as a payout but kept out of the token total
+    if let Some(stats_accounts) = &stats_accounts {
+        record_stats(
+            program_id,
+            authority,
+            stats_accounts,
+            0,
+            recipient.key,
+            new_marker,
+            0,
+            now,
+        )?;
+    }
+
+    emit_claimed(recipient.key, &native_mint::id(), amount, seq, None);
+
+    Ok(())
+}
+
+pub fn process_set_config(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    params: ConfigParams,
+) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let owner = next_account(account_info_iter, "owner")?; // Owner account (must sign)
+    let config_account = next_account(account_info_iter, "config_account")?; // Config PDA
+
+    let mut config = load_config_mut(program_id, config_account)?;
+    assert_role(&config, owner, ROLE_CAN_CONFIG)?;
+
+    params.validate()?;
+    params.apply(&mut config);
+
+    store_config(&mut config, config_account)?;
+
+    Ok(())
+}
+
+/// Record `new_owner` as the pending owner, effective once the configured timelock elapses.
+/// Rescheduling replaces any pending change and restarts the timelock.
+pub fn process_schedule_owner_change(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    new_owner: Pubkey,
+) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let owner = next_account(account_info_iter, "owner")?; // Owner account (must sign)
+    let config_account = next_account(account_info_iter, "config_account")?; // Config PDA
+
+    let mut config = load_config_mut(program_id, config_account)?;
+    assert_owner(&config, owner)?;
+
+    if new_owner == Pubkey::default() {
+        return Err(ProgramError::InvalidArgument);
+    }
+
+    let now = Clock::get()?.unix_timestamp;
+    config.pending_owner = Some(new_owner);
+    config.owner_change_effective_at = now
+        .checked_add(config.owner_timelock_seconds)
+        .ok_or(ProgramError::ArithmeticOverflow)?;
+
+    store_config(&mut config, config_account)?;
+
+    Ok(())
+}
+
+/// Apply a scheduled owner change. Permissionless, since the change was already
+/// authorized by the owner when scheduled; it only succeeds after the timelock.
+pub fn process_finalize_owner_change(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let config_account = next_account(account_info_iter, "config_account")?; // Config PDA
+
+    let mut config = load_config_mut(program_id, config_account)?;
+
+    let new_owner = config
+        .pending_owner
+        .ok_or(ProgramError::InvalidAccountData)?;
+
+    // ✅ Enforce the timelock
+    if Clock::get()?.unix_timestamp < config.owner_change_effective_at {
+        return Err(ProgramError::InvalidArgument);
+    }
+
+    config.owner = new_owner;
+    config.pending_owner = None;
+    config.owner_change_effective_at = 0;
+
+    store_config(&mut config, config_account)?;
+
+    Ok(())
+}
+
+pub fn process_cancel_owner_change(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let owner = next_account(account_info_iter, "owner")?; // Owner account (must sign)
+    let config_account = next_account(account_info_iter, "config_account")?; // Config PDA
+
+    let mut config = load_config_mut(program_id, config_account)?;
+    assert_owner(&config, owner)?;
+
+    if config.pending_owner.is_none() {
+        return Err(ProgramError::InvalidAccountData);
+    }
+
+    config.pending_owner = None;
+    config.owner_change_effective_at = 0;
+
+    store_config(&mut config, config_account)?;
+
+    Ok(())
+}
+
+/// Refresh the cached mint decimals. Permissionless, since the value is read from the mint.
+pub fn process_sync_decimals(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let config_account = next_account(account_info_iter, "config_account")?; // Config PDA
+    let mint = next_account(account_info_iter, "mint")?; // Treasury mint
+
+    let mut config = load_config_mut(program_id, config_account)?;
+    if config.mint != *mint.key {
+        return Err(ProgramError::InvalidAccountData);
+    }
+
+    config.decimals = read_mint_decimals(mint)?;
+
+    store_config(&mut config, config_account)?;
+
+    Ok(())
+}
+
+/// Distribute `amounts[i]` to the i-th `(recipient token account, receipt PDA)` pair in the
+/// remaining accounts. Every recipient must be a token account of the treasury mint, and
+/// receipts are keyed by that token account's owner for the current campaign.
+///
+/// Missing receipts (at most `MAX_NEW_ACCOUNTS_PER_TX`, claimant markers included) are
+/// created lazily and paid for by the signer. All recipients are checked before any transfer
+/// is made. Once claim stats exist, the stats accounts follow the pairs, with one claimant
+/// marker per amount.
+pub fn process_airdrop_batch(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    amounts: Vec<u64>,
+) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let owner = next_account(account_info_iter, "owner")?; // Owner or claim authority (must sign)
+    let config_account = next_account(account_info_iter, "config_account")?; // Config PDA
+    let treasury_pda = next_account(account_info_iter, "treasury_pda")?;
+    let treasury_token_account = next_account(account_info_iter, "treasury_token_account")?;
+    let mint = next_account(account_info_iter, "mint")?;
+    let token_program = next_account(account_info_iter, "token_program")?;
+    let system_program = next_account(account_info_iter, "system_program")?;
+    let remaining_accounts = account_info_iter.as_slice();
+
+    if amounts.is_empty() || remaining_accounts.len() < amounts.len() * 2 {
+        return Err(ProgramError::NotEnoughAccountKeys);
+    }
+    // (token account, receipt) per amount, then the stats accounts
+    let (recipients, stats_tail) = remaining_accounts.split_at(amounts.len() * 2);
+    assert_writable(treasury_pda, "treasury_pda")?;
+    assert_writable(treasury_token_account, "treasury_token_account")?;
+    for pair in recipients.chunks_exact(2) {
+        assert_writable(&pair[0], "recipient_token_account")?;
+        assert_writable(&pair[1], "receipt")?;
+    }
+
+    let config = load_config(program_id, config_account)?;
+    assert_role(&config, owner, ROLE_CAN_CLAIM)?;
+    assert_treasury_pda(program_id, &config, treasury_pda)?;
+
+    if config.claims_paused {
+        return Err(TreasuryError::ClaimsPaused.into());
+    }
+    if config.mint != *mint.key {
+        return Err(ProgramError::InvalidAccountData);
+    }
+
+    // ✅ Verify the vault and that it can cover the whole batch
+    let treasury_token_account_info =
+        assert_token_account(treasury_token_account, Some(treasury_pda.key), &config.mint)?;
+    assert_not_frozen(&treasury_token_account_info, treasury_token_account)?;
+
+    let total = amounts
+        .iter()
+        .try_fold(0u64, |sum, amount| sum.checked_add(*amount))
+        .ok_or(ProgramError::ArithmeticOverflow)?;
+    if treasury_token_account_info.amount < total {
+        return Err(TreasuryError::InsufficientTreasuryBalance.into());
+    }
+
+    let stats_accounts = load_stats_accounts(program_id, &config, stats_tail, amounts.len())?;
+
+    // 🔹 Bound lazy receipt and marker creation so the batch stays within runtime limits
+    let new_accounts = recipients
+        .chunks_exact(2)
+        .map(|pair| &pair[1])
+        .chain(stats_accounts.iter().flat_map(|stats| stats.markers))
+        .filter(|account| account.owner != program_id)
+        .count();
+    if new_accounts > MAX_NEW_ACCOUNTS_PER_TX {
+        msg!(
+            "Batch would create {} accounts, limit is {}; split the batch",
+            new_accounts,
+            MAX_NEW_ACCOUNTS_PER_TX
+        );
+        return Err(TreasuryError::TooManyNewAccounts.into());
+    }
+
+    // ✅ Every recipient must hold the treasury mint
+    let mut recipient_owners = Vec::with_capacity(amounts.len());
+    let mut new_markers = Vec::with_capacity(amounts.len());
+    for (index, pair) in recipients.chunks_exact(2).enumerate() {
+        let recipient_info = assert_token_account(&pair[0], None, &config.mint)?;
+        new_markers.push(match &stats_accounts {
+            Some(stats_accounts) => check_claimant_marker(
+                program_id,
+                &stats_accounts.markers[index],
+                &recipient_info.owner,
+            )?,
+            None => None,
+        });
+        recipient_owners.push(recipient_info.owner);
+    }
+
+    // ✅ The batch may only spend the liquid balance, not funds locked for others
+    let mut state = TreasuryState::decode(&treasury_pda.try_borrow_data()?)?;
+    state.pay_out(total)?;
+
+    let now = Clock::get()?.unix_timestamp;
+    for (index, ((pair, amount), user)) in recipients
+        .chunks_exact(2)
+        .zip(amounts.iter())
+        .zip(recipient_owners.iter())
+        .enumerate()
+    {
+        transfer_from_treasury(
+            &config,
+            token_program,
+            treasury_token_account,
+            mint,
+            &pair[0],
+            treasury_pda,
+            *amount,
+            config.decimals,
+        )?;
+
+        record_receipt(
+            program_id,
+            owner,
+            &pair[1],
+            system_program,
+            config.campaign_id,
+            user,
+            *amount,
+            now,
+        )?;
+
+        if let Some(stats_accounts) = &stats_accounts {
+            record_stats(
+                program_id,
+                owner,
+                stats_accounts,
+                index,
+                user,
+                new_markers[index],
+                *amount,
+                now,
+            )?;
+        }
+    }
+
+    state.serialize(&mut &mut treasury_pda.try_borrow_mut_data()?[..])?;
+
+    Ok(())
+}
+
+/// Close the treasury state and history PDAs, returning their rent to `recipient`. Refused
+/// while the tracked balance, the locked balance or the vault still hold anything, since a
+/// recreated state would start at zero and lose what users are owed. The vault is
+/// `config.vault` when recorded, otherwise the treasury PDA's associated token account; it
+/// may not exist yet.
+pub fn process_close_treasury(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let owner = next_account(account_info_iter, "owner")?; // Owner account (must sign)
+    let config_account = next_account(account_info_iter, "config_account")?; // Config PDA
+    let treasury_pda = next_account(account_info_iter, "treasury_pda")?;
+    let history_account = next_account(account_info_iter, "history_account")?;
+    let recipient = next_account(account_info_iter, "recipient")?; // Receives reclaimed rent
+    let treasury_token_account = next_account(account_info_iter, "treasury_token_account")?; // Must be empty
+
+    let config = load_config(program_id, config_account)?;
+    assert_owner(&config, owner)?;
+    assert_treasury_pda(program_id, &config, treasury_pda)?;
+
+    assert_history_pda(program_id, &config, history_account)?;
+
+    // ✅ Nothing may be left in the books or the vault when the state disappears
+    let expected_vault = config
+        .vault
+        .unwrap_or_else(|| get_associated_token_address(treasury_pda.key, &config.mint));
+    if expected_vault != *treasury_token_account.key {
+        return Err(ProgramError::InvalidAccountData);
+    }
+    let vault_amount = if treasury_token_account.data_is_empty() {
+        0
+    } else {
+        assert_token_account(treasury_token_account, Some(treasury_pda.key), &config.mint)?.amount
+    };
+    let state = TreasuryState::decode(&treasury_pda.try_borrow_data()?)?;
+    if state.balance > 0 || state.locked_balance > 0 || vault_amount > 0 {
+        msg!(
+            "Treasury still holds funds: balance={} locked={} vault={}",
+            state.balance,
+            state.locked_balance,
+            vault_amount
+        );
+        return Err(TreasuryError::TreasuryStillOpen.into());
+    }
+
+    close_account(treasury_pda, recipient)?;
+    close_account(history_account, recipient)?;
+
+    Ok(())
+}
+
+/// Close the config PDA, returning its rent to `recipient`. The treasury must already be
+/// closed, otherwise it would be left without an owner record.
+pub fn process_close_config(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let owner = next_account(account_info_iter, "owner")?; // Owner account (must sign)
+    let config_account = next_account(account_info_iter, "config_account")?; // Config PDA
+    let treasury_pda = next_account(account_info_iter, "treasury_pda")?; // Must already be closed
+    let recipient = next_account(account_info_iter, "recipient")?; // Receives reclaimed rent
+
+    let config = load_config(program_id, config_account)?;
+    assert_owner(&config, owner)?;
+
+    // ✅ Enforce teardown order: treasury first, then config
+    let expected_treasury_pda =
+        Pubkey::create_program_address(&[TREASURY_SEED, &[config.treasury_bump]], program_id)
+            .map_err(|_| ProgramError::InvalidSeeds)?;
+    if expected_treasury_pda != *treasury_pda.key {
+        return Err(ProgramError::InvalidSeeds);
+    }
+    if treasury_pda.lamports() > 0 || treasury_pda.owner == program_id {
+        return Err(TreasuryError::TreasuryStillOpen.into());
+    }
+
+    close_account(config_account, recipient)?;
+
+    Ok(())
+}
+
+/// Extend the config account by `additional_bytes` of zeroed headroom, topping up rent from
+/// the owner. Existing data is left untouched.
+pub fn process_grow_config(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    additional_bytes: u32,
+) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let owner = next_account(account_info_iter, "owner")?; // Owner account (must sign, pays rent)
+    let config_account = next_account(account_info_iter, "config_account")?; // Config PDA
+    let system_program = next_account(account_info_iter, "system_program")?;
+
+    let config = load_config(program_id, config_account)?;
+    assert_owner(&config, owner)?;
+
+    // 🔹 A single instruction may only grow an account by MAX_PERMITTED_DATA_INCREASE
+    let additional_bytes = additional_bytes as usize;
+    if additional_bytes == 0 || additional_bytes > MAX_PERMITTED_DATA_INCREASE {
+        return Err(ProgramError::InvalidArgument);
+    }
+
+    let new_len = config_account
+        .data_len()
+        .checked_add(additional_bytes)
+        .ok_or(ProgramError::ArithmeticOverflow)?;
+
+    let required_lamports = Rent::get()?.minimum_balance(new_len);
+    let top_up = required_lamports.saturating_sub(config_account.lamports());
+    if top_up > 0 {
+        invoke(
+            &system_instruction::transfer(owner.key, config_account.key, top_up),
+            &[
+                owner.clone(),
+                config_account.clone(),
+                system_program.clone(),
+            ],
+        )?;
+    }
+
+    config_account.realloc(new_len, true)?;
+
+    Ok(())
+}
+
+pub fn process_set_paused(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    claims: bool,
+    deposits: bool,
+) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let owner = next_account(account_info_iter, "owner")?; // Owner account (must sign)
+    let config_account = next_account(account_info_iter, "config_account")?; // Config PDA
+
+    let mut config = load_config_mut(program_id, config_account)?;
+    assert_role(&config, owner, ROLE_CAN_PAUSE)?;
+
+    config.claims_paused = claims;
+    config.deposits_paused = deposits;
+
+    store_config(&mut config, config_account)?;
+
+    Ok(())
+}
+
+/// Move `amount` tokens from the depositor into the treasury token account and credit the
+/// tracked balance.
+pub fn process_deposit(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    amount: u64,
+) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let depositor = next_account(account_info_iter, "depositor")?; // Must sign
+    let depositor_token_account = next_account(account_info_iter, "depositor_token_account")?;
+    let treasury_token_account = next_account(account_info_iter, "treasury_token_account")?;
+    let treasury_pda = next_account(account_info_iter, "treasury_pda")?;
+    let config_account = next_account(account_info_iter, "config_account")?; // Config PDA
+    let mint = next_account(account_info_iter, "mint")?;
+    let token_program = next_account(account_info_iter, "token_program")?;
+
+    assert_writable(depositor_token_account, "depositor_token_account")?;
+    assert_writable(treasury_token_account, "treasury_token_account")?;
+    assert_writable(treasury_pda, "treasury_pda")?;
+
+    let config = load_config(program_id, config_account)?;
+    if config.deposits_paused {
+        return Err(TreasuryError::DepositsPaused.into());
+    }
+
+    assert_treasury_pda(program_id, &config, treasury_pda)?;
+    if config.mint != *mint.key {
+        return Err(ProgramError::InvalidAccountData);
+    }
+
+    // ✅ Deposits must land in a vault controlled by the treasury PDA
+    let treasury_token_account_info =
+        assert_token_account(treasury_token_account, Some(treasury_pda.key), &config.mint)?;
+    assert_not_frozen(&treasury_token_account_info, treasury_token_account)?;
+
+    if !depositor.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+
+    let transfer_ix = token_instruction::transfer_checked(
+        token_program.key,
+        depositor_token_account.key,
+        mint.key,
+        treasury_token_account.key,
+        depositor.key,
+        &[],
+        amount,
+        config.decimals,
+    )?;
+
+    invoke(
+        &transfer_ix,
+        &[
+            depositor_token_account.clone(),
+            mint.clone(),
+            treasury_token_account.clone(),
+            depositor.clone(),
+            token_program.clone(),
+        ],
+    )?;
+
+    let mut state = TreasuryState::decode(&treasury_pda.try_borrow_data()?)?;
+    state.balance = state
+        .balance
+        .checked_add(amount)
+        .ok_or(ProgramError::ArithmeticOverflow)?;
+    state.serialize(&mut &mut treasury_pda.try_borrow_mut_data()?[..])?;
+
+    Ok(())
+}
+
+/// Add `roles` to `authority`, taking a free slot if it holds no roles yet.
+pub fn process_grant_role(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    authority: Pubkey,
+    roles: u8,
+) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let owner = next_account(account_info_iter, "owner")?; // Owner account (must sign)
+    let config_account = next_account(account_info_iter, "config_account")?; // Config PDA
+
+    let mut config = load_config_mut(program_id, config_account)?;
+    assert_owner(&config, owner)?;
+
+    if roles == 0 || roles & !ALL_ROLES != 0 || authority == Pubkey::default() {
+        return Err(ProgramError::InvalidArgument);
+    }
+
+    let slot = match config
+        .authorities
+        .iter()
+        .position(|entry| entry.key == authority)
+    {
+        Some(index) => index,
+        None => config
+            .authorities
+            .iter()
+            .position(|entry| entry.key == Pubkey::default())
+            .ok_or(TreasuryError::AuthoritiesFull)?,
+    };
+
+    config.authorities[slot].key = authority;
+    config.authorities[slot].roles |= roles;
+
+    store_config(&mut config, config_account)?;
+
+    Ok(())
+}
+
+/// Remove `roles` from `authority`, freeing its slot once no roles remain.
+pub fn process_revoke_role(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    authority: Pubkey,
+    roles: u8,
+) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let owner = next_account(account_info_iter, "owner")?; // Owner account (must sign)
+    let config_account = next_account(account_info_iter, "config_account")?; // Config PDA
+
+    let mut config = load_config_mut(program_id, config_account)?;
+    assert_owner(&config, owner)?;
+
+    let entry = config
+        .authorities
+        .iter_mut()
+        .find(|entry| entry.key == authority && authority != Pubkey::default())
+        .ok_or(ProgramError::InvalidArgument)?;
+
+    entry.roles &= !roles;
+    if entry.roles == 0 {
+        *entry = Authority::default();
+    }
+
+    store_config(&mut config, config_account)?;
+
+    Ok(())
+}
+
+#[cfg(feature = "test-helpers")]
+fn process_test_instruction(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    instruction_data: &[u8],
+) -> ProgramResult {
+    let instruction = TestInstruction::try_from_slice(instruction_data)
+        .map_err(|_| ProgramError::InvalidInstructionData)?;
+
+    match instruction {
+        TestInstruction::SetStateForTest {
+            balance,
+            created_at,
+            owner_change_effective_at,
+        } => {
+            let account_info_iter = &mut accounts.iter();
+            let owner = next_account(account_info_iter, "owner")?; // Owner account (must sign)
+            let config_account = next_account(account_info_iter, "config_account")?; // Config PDA
+            let treasury_pda = next_account(account_info_iter, "treasury_pda")?;
+
+            let mut config = load_config_mut(program_id, config_account)?;
+            assert_owner(&config, owner)?;
+            assert_treasury_pda(program_id, &config, treasury_pda)?;
+
+            if let Some(created_at) = created_at {
+                config.created_at = created_at;
+            }
+            if let Some(effective_at) = owner_change_effective_at {
+                config.owner_change_effective_at = effective_at;
+            }
+            store_config(&mut config, config_account)?;
+
+            if let Some(balance) = balance {
+                let mut state = TreasuryState::decode(&treasury_pda.try_borrow_data()?)?;
+                state.balance = balance;
+                state.serialize(&mut &mut treasury_pda.try_borrow_mut_data()?[..])?;
+            }
+
+            Ok(())
+        }
+    }
+}
+
+/// Add a mint and its treasury-owned vault to the registry, creating the registry on first use.
+pub fn process_register_mint(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let owner = next_account(account_info_iter, "owner")?; // Owner account (must sign, pays rent)
+    let config_account = next_account(account_info_iter, "config_account")?; // Config PDA
+    let registry_account = next_account(account_info_iter, "registry_account")?; // Registry PDA
+    let treasury_pda = next_account(account_info_iter, "treasury_pda")?;
+    let mint = next_account(account_info_iter, "mint")?;
+    let vault = next_account(account_info_iter, "vault")?; // Token account owned by the treasury PDA
+    let system_program = next_account(account_info_iter, "system_program")?;
+
+    let config = load_config(program_id, config_account)?;
+    assert_owner(&config, owner)?;
+    assert_treasury_pda(program_id, &config, treasury_pda)?;
+
+    // ✅ Validate Registry PDA and create it if this is the first mint
+    let (expected_registry_pda, registry_bump) =
+        Pubkey::find_program_address(&[REGISTRY_SEED], program_id);
+    if expected_registry_pda != *registry_account.key {
+        return Err(ProgramError::InvalidSeeds);
+    }
+    create_pda_account(
+        owner,
+        registry_account,
+        system_program,
+        program_id,
+        8 + std::mem::size_of::<MintRegistry>(),
+        &[REGISTRY_SEED, &[registry_bump]],
+    )?;
+
+    let mut registry = MintRegistry::decode(&registry_account.try_borrow_data()?)?;
+    registry.bump = registry_bump;
+
+    if registry.find(mint.key).is_some() {
+        return Err(TreasuryError::MintAlreadyRegistered.into());
+    }
+
+    // ✅ The vault must hold this mint and be controlled by the treasury PDA
+    let vault_info = assert_token_account(vault, Some(treasury_pda.key), mint.key)?;
+
+    let slot = registry
+        .entries
+        .iter()
+        .position(|entry| entry.mint == Pubkey::default())
+        .ok_or(TreasuryError::RegistryFull)?;
+
+    registry.entries[slot] = MintEntry {
+        mint: *mint.key,
+        vault: *vault.key,
+        balance: vault_info.amount,
+        decimals: read_mint_decimals(mint)?,
+        fee_bps: None,
+    };
+
+    registry.serialize(&mut &mut registry_account.try_borrow_mut_data()?[..])?;
+
+    Ok(())
+}
+
+/// Remove a mint from the registry. Its vault must already be empty.
+pub fn process_deregister_mint(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    mint: Pubkey,
+) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let owner = next_account(account_info_iter, "owner")?; // Owner account (must sign)
+    let config_account = next_account(account_info_iter, "config_account")?; // Config PDA
+    let registry_account = next_account(account_info_iter, "registry_account")?; // Registry PDA
+    let vault = next_account(account_info_iter, "vault")?; // Registered vault for `mint`
+
+    let config = load_config(program_id, config_account)?;
+    assert_owner(&config, owner)?;
+
+    let mut registry = load_registry(program_id, registry_account)?;
+    let slot = registry
+        .find(&mint)
+        .ok_or(TreasuryError::MintNotRegistered)?;
+
+    if registry.entries[slot].vault != *vault.key {
+        return Err(ProgramError::InvalidAccountData);
+    }
+    let vault_info = assert_token_account(vault, None, &mint)?;
+    if vault_info.amount > 0 {
+        return Err(TreasuryError::MintHasBalance.into());
+    }
+
+    registry.entries[slot] = MintEntry::default();
+
+    registry.serialize(&mut &mut registry_account.try_borrow_mut_data()?[..])?;
+
+    Ok(())
+}
+
+/// Claim `amount` of a registered mint from its vault to `destination`. Once claim stats
+/// exist, the stats accounts follow and the authority pays for the destination owner's
+/// claimant marker.
+pub fn process_claim_mint(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    mint: Pubkey,
+    amount: u64,
+) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let authority = next_account(account_info_iter, "authority")?; // Owner or claim authority (must sign)
+    let config_account = next_account(account_info_iter, "config_account")?; // Config PDA
+    let treasury_pda = next_account(account_info_iter, "treasury_pda")?;
+    let registry_account = next_account(account_info_iter, "registry_account")?; // Registry PDA
+    let vault = next_account(account_info_iter, "vault")?; // Registered vault for `mint`
+    let mint_account = next_account(account_info_iter, "mint_account")?;
+    let destination = next_account(account_info_iter, "destination")?; // Recipient token account
+    let token_program = next_account(account_info_iter, "token_program")?;
+
+    assert_writable(treasury_pda, "treasury_pda")?;
+    assert_writable(registry_account, "registry")?;
+    assert_writable(vault, "vault")?;
+    assert_writable(destination, "destination")?;
+
+    let config = load_config(program_id, config_account)?;
+    if config.claims_paused {
+        return Err(TreasuryError::ClaimsPaused.into());
+    }
+    assert_role(&config, authority, ROLE_CAN_CLAIM)?;
+    assert_treasury_pda(program_id, &config, treasury_pda)?;
+
+    let mut registry = load_registry(program_id, registry_account)?;
+    let slot = registry
+        .find(&mint)
+        .ok_or(TreasuryError::MintNotRegistered)?;
+    let entry = registry.entries[slot];
+
+    // ✅ Look up the right vault for this mint
+    if entry.vault != *vault.key || *mint_account.key != mint {
+        return Err(ProgramError::InvalidAccountData);
+    }
+
+    let vault_info = assert_token_account(vault, Some(treasury_pda.key), &mint)?;
+    assert_not_frozen(&vault_info, vault)?;
+    if vault_info.amount < amount {
+        return Err(TreasuryError::InsufficientTreasuryBalance.into());
+    }
+
+    let destination_info = assert_token_account(destination, None, &mint)?;
+
+    // ✅ Once stats exist every payout must pass them, so the totals never miss one
+    let stats_accounts = load_stats_accounts(program_id, &config, account_info_iter.as_slice(), 1)?;
+    let new_marker = match &stats_accounts {
+        Some(stats_accounts) => check_claimant_marker(
+            program_id,
+            &stats_accounts.markers[0],
+            &destination_info.owner,
+        )?,
+        None => None,
+    };
+
+    // 🔹 The mint's own fee wins over the default; the fee stays in the vault
+    let fee_bps = entry.fee_bps.unwrap_or(config.default_mint_fee_bps);
+    let fee = apply_bps(amount, fee_bps as u64)?;
+    let payout = amount - fee;
+
+    let now = Clock::get()?.unix_timestamp;
+    let mut state = TreasuryState::decode(&treasury_pda.try_borrow_data()?)?;
+    state.note_claim_time(now, config.min_claim_interval)?;
+    // ✅ A registered primary mint spends the tracked balance, so only its liquid part
+    if mint == config.mint {
+        state.pay_out(payout)?;
+    }
+    let seq = state.next_claim_seq()?;
+
+    transfer_from_treasury(
+        &config,
+        token_program,
+        vault,
+        mint_account,
+        destination,
+        treasury_pda,
+        payout,
+        entry.decimals,
+    )?;
+
+    registry.entries[slot].balance = vault_info.amount - payout;
+
+    registry.serialize(&mut &mut registry_account.try_borrow_mut_data()?[..])?;
+
+    state.serialize(&mut &mut treasury_pda.try_borrow_mut_data()?[..])?;
+
+    // 🔹 Only the treasury mint adds to the token total; other mints would mix units
+    if let Some(stats_accounts) = &stats_accounts {
+        record_stats(
+            program_id,
+            authority,
+            stats_accounts,
+            0,
+            &destination_info.owner,
+            new_marker,
+            if mint == config.mint { payout } else { 0 },
+            now,
+        )?;
+    }
+
+    emit_claimed(&destination_info.owner, &mint, payout, seq, None);
+
+    Ok(())
+}
+
+/// Hand over ownership and replace `ConfigParams` in one step. Everything is validated before
+/// either change is applied, so observers never see one without the other. Any scheduled
+/// owner change is discarded.
+pub fn process_set_owner_and_config(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    new_owner: Pubkey,
+    params: ConfigParams,
+) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let owner = next_account(account_info_iter, "owner")?; // Current owner (must sign)
+    let config_account = next_account(account_info_iter, "config_account")?; // Config PDA
+
+    let mut config = load_config_mut(program_id, config_account)?;
+    assert_owner(&config, owner)?;
+
+    if new_owner == Pubkey::default() {
+        return Err(ProgramError::InvalidArgument);
+    }
+    params.validate()?;
+
+    params.apply(&mut config);
+    config.owner = new_owner;
+    config.pending_owner = None;
+    config.owner_change_effective_at = 0;
+
+    store_config(&mut config, config_account)?;
+
+    Ok(())
+}
+
+/// Set `user`'s allocation and vesting schedule, creating their user-claim PDA if needed.
+/// The new total may not drop below what the user has already claimed.
+pub fn process_set_vesting(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    user: Pubkey,
+    start: i64,
+    duration: i64,
+    total: u64,
+) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let owner = next_account(account_info_iter, "owner")?; // Owner account (must sign, pays rent)
+    let config_account = next_account(account_info_iter, "config_account")?; // Config PDA
+    let user_claim_account = next_account(account_info_iter, "user_claim_account")?; // User-claim PDA
+    let system_program = next_account(account_info_iter, "system_program")?;
+    let treasury_pda = next_account(account_info_iter, "treasury_pda")?; // Tracks total_allocated
+
+    let config = load_config(program_id, config_account)?;
+    assert_owner(&config, owner)?;
+    assert_treasury_pda(program_id, &config, treasury_pda)?;
+
+    let mut user_claim =
+        load_or_create_user_claim(program_id, owner, user_claim_account, system_program, &user)?;
+
+    if total < user_claim.claimed {
+        return Err(ProgramError::InvalidArgument);
+    }
+    // ✅ Reject schedules whose end cannot be represented; zero duration unlocks at `start`
+    if duration < 0 || start.checked_add(duration).is_none() {
+        msg!(
+            "Invalid vesting schedule start={} duration={}",
+            start,
+            duration
+        );
+        return Err(ProgramError::InvalidArgument);
+    }
+
+    // ✅ Keep outstanding promises within what the treasury can pay
+    let mut state = TreasuryState::decode(&treasury_pda.try_borrow_data()?)?;
+    state.reallocate(
+        user_claim.total - user_claim.claimed,
+        total - user_claim.claimed,
+        config.allocation_cap,
+    )?;
+    state.serialize(&mut &mut treasury_pda.try_borrow_mut_data()?[..])?;
+
+    user_claim.total = total;
+    user_claim.vesting_start = start;
+    user_claim.vesting_duration = duration;
+
+    user_claim.serialize(&mut &mut user_claim_account.try_borrow_mut_data()?[..])?;
+
+    Ok(())
+}
+
+/// User-signed claim of up to the amount unlocked by their own vesting schedule. Takes the
+/// same accounts as `Claim`, with the user signing and paying for the receipt from the owner
+/// slot and their user-claim PDA after the optional gate and co-signer accounts, and runs
+/// every `Claim` check on the way.
+pub fn process_claim_vested(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    amount: u64,
+) -> ProgramResult {
+    execute_claim(
+        program_id,
+        accounts,
+        amount,
+        ClaimMode::User,
+        ClaimOptions::default(),
+    )
+    .map(|_| ())
+}
+
+/// Read-only dry run of `AirdropBatch`, meant for `simulateTransaction`. Logs one
+/// `SIMULATED` line per recipient with the amount the batch would send and whether the vault
+/// still covers it at that point, then a `SIMULATED_TOTAL` line. Nothing is transferred.
+pub fn process_simulate_batch(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    amounts: Vec<u64>,
+) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let config_account = next_account(account_info_iter, "config_account")?; // Config PDA
+    let treasury_pda = next_account(account_info_iter, "treasury_pda")?;
+    let treasury_token_account = next_account(account_info_iter, "treasury_token_account")?;
+    let recipients = account_info_iter.as_slice(); // Token account per amount
+
+    if amounts.is_empty() || recipients.len() != amounts.len() {
+        return Err(ProgramError::NotEnoughAccountKeys);
+    }
+
+    let config = load_config(program_id, config_account)?;
+    assert_treasury_pda(program_id, &config, treasury_pda)?;
+
+    let treasury_token_account_info =
+        assert_token_account(treasury_token_account, Some(treasury_pda.key), &config.mint)?;
+
+    let mut remaining = treasury_token_account_info.amount;
+    let mut total: u64 = 0;
+    for (index, (recipient, amount)) in recipients.iter().zip(amounts.iter()).enumerate() {
+        let recipient_info = assert_token_account(recipient, None, &config.mint)?;
+
+        let covered = *amount <= remaining;
+        remaining = remaining.saturating_sub(*amount);
+        total = total
+            .checked_add(*amount)
+            .ok_or(ProgramError::ArithmeticOverflow)?;
+
+        msg!(
+            "SIMULATED index={} recipient={} amount={} covered={}",
+            index,
+            recipient_info.owner,
+            amount,
+            covered
+        );
+    }
+
+    msg!(
+        "SIMULATED_TOTAL total={} vault={} paused={}",
+        total,
+        treasury_token_account_info.amount,
+        config.claims_paused
+    );
+
+    Ok(())
+}
+
+/// Create the treasury PDA's associated token account for the configured mint, so setup
+/// does not depend on an externally created vault, and record it as `config.vault`.
+/// Idempotent; the owner pays the rent. Also records an ATA created elsewhere.
+pub fn process_create_treasury_token_account(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let owner = next_account(account_info_iter, "owner")?; // Owner account (must sign, pays rent)
+    let config_account = next_account(account_info_iter, "config_account")?; // Config PDA
+    let treasury_pda = next_account(account_info_iter, "treasury_pda")?;
+    let treasury_token_account = next_account(account_info_iter, "treasury_token_account")?; // ATA to create
+    let mint = next_account(account_info_iter, "mint")?;
+    let system_program = next_account(account_info_iter, "system_program")?;
+    let token_program = next_account(account_info_iter, "token_program")?;
+    let associated_token_program = next_account(account_info_iter, "associated_token_program")?;
+
+    let mut config = load_config_mut(program_id, config_account)?;
+    assert_owner(&config, owner)?;
+    assert_treasury_pda(program_id, &config, treasury_pda)?;
+
+    if config.mint != *mint.key {
+        return Err(ProgramError::InvalidAccountData);
+    }
+    if get_associated_token_address(treasury_pda.key, mint.key) != *treasury_token_account.key {
+        return Err(ProgramError::InvalidSeeds);
+    }
+
+    let create_ix = create_associated_token_account_idempotent(
+        owner.key,
+        treasury_pda.key,
+        mint.key,
+        token_program.key,
+    );
+    invoke(
+        &create_ix,
+        &[
+            owner.clone(),
+            treasury_token_account.clone(),
+            treasury_pda.clone(),
+            mint.clone(),
+            system_program.clone(),
+            token_program.clone(),
+            associated_token_program.clone(),
+        ],
+    )?;
+
+    // ✅ Confirm the vault is wired to the treasury PDA and the configured mint
+    assert_token_account(treasury_token_account, Some(treasury_pda.key), &config.mint)?;
+
+    if config.vault != Some(*treasury_token_account.key) {
+        config.vault = Some(*treasury_token_account.key);
+        store_config(&mut config, config_account)?;
+    }
+
+    Ok(())
+}
+
+/// Earmark `amount` of the unallocated treasury balance into the sub-pool `pool`, taking a
+/// free slot if the label is new.
+pub fn process_allocate_pool(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    pool: [u8; 16],
+    amount: u64,
+) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let owner = next_account(account_info_iter, "owner")?; // Owner account (must sign)
+    let config_account = next_account(account_info_iter, "config_account")?; // Config PDA
+    let treasury_pda = next_account(account_info_iter, "treasury_pda")?;
+
+    let config = load_config(program_id, config_account)?;
+    assert_owner(&config, owner)?;
+    assert_treasury_pda(program_id, &config, treasury_pda)?;
+
+    if pool == [0u8; 16] {
+        return Err(ProgramError::InvalidArgument);
+    }
+
+    let mut state = TreasuryState::decode(&treasury_pda.try_borrow_data()?)?;
+    let unallocated = state.balance.saturating_sub(state.allocated());
+    if amount > unallocated {
+        msg!(
+            "Cannot allocate {} to pool, unallocated balance is {}",
+            amount,
+            unallocated
+        );
+        return Err(TreasuryError::PoolInsufficientBalance.into());
+    }
+
+    let index = match state.find_pool(&pool) {
+        Some(index) => index,
+        None => state
+            .find_pool(&[0u8; 16])
+            .ok_or(TreasuryError::PoolsFull)?,
+    };
+    state.pools[index].label = pool;
+    state.pools[index].balance = state.pools[index]
+        .balance
+        .checked_add(amount)
+        .ok_or(ProgramError::ArithmeticOverflow)?;
+
+    state.serialize(&mut &mut treasury_pda.try_borrow_mut_data()?[..])?;
+
+    Ok(())
+}
+
+/// `Claim` funded from the sub-pool `pool`. Takes the same accounts as `Claim`; the payout
+/// actually sent (after the multiplier and any late fee) is debited from the pool as well as
+/// the treasury balance.
+pub fn process_claim_pool(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    pool: [u8; 16],
+    amount: u64,
+) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let _user = next_account(account_info_iter, "user")?;
+    let _user_token_account = next_account(account_info_iter, "user_token_account")?;
+    let _treasury_token_account = next_account(account_info_iter, "treasury_token_account")?;
+    let _token_program = next_account(account_info_iter, "token_program")?;
+    let treasury_pda = next_account(account_info_iter, "treasury_pda")?;
+    let _owner = next_account(account_info_iter, "owner")?;
+    let config_account = next_account(account_info_iter, "config_account")?; // Config PDA
+
+    let config = load_config(program_id, config_account)?;
+    assert_treasury_pda(program_id, &config, treasury_pda)?;
+
+    // ✅ The pool must exist before anything moves
+    let state = TreasuryState::decode(&treasury_pda.try_borrow_data()?)?;
+    let index = state.find_pool(&pool).ok_or(TreasuryError::PoolNotFound)?;
+
+    // 🔹 Only execute_claim knows the late fee, so the pool is checked against its payout;
+    // failing here still reverts the transfer
+    let payout = execute_claim(
+        program_id,
+        accounts,
+        amount,
+        ClaimMode::Authority,
+        ClaimOptions::default(),
+    )?
+    .payout;
+
+    let mut state = TreasuryState::decode(&treasury_pda.try_borrow_data()?)?;
+    if state.pools[index].balance < payout {
+        return Err(claim_rejected(
+            config.error_verbosity,
+            TreasuryError::PoolInsufficientBalance,
+            "pool_balance",
+            format!(
+                "payout={} pool_balance={}",
+                payout, state.pools[index].balance
+            ),
+        ));
+    }
+
+    state.pools[index].balance -= payout;
+    state.serialize(&mut &mut treasury_pda.try_borrow_mut_data()?[..])?;
+
+    Ok(())
+}
+
+/// Withdraw `total` from the vault split across several destination token accounts, one per
+/// entry in `amounts`, which must sum to `total`. When the owner is a multisig, its
+/// threshold approval is what produces the owner signature checked here. Withdrawals are
+/// spaced at least `withdraw_cooldown_seconds` apart.
+pub fn process_withdraw_split(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    total: u64,
+    amounts: Vec<u64>,
+) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let owner = next_account(account_info_iter, "owner")?; // Owner account (must sign)
+    let config_account = next_account(account_info_iter, "config_account")?; // Config PDA
+    let treasury_pda = next_account(account_info_iter, "treasury_pda")?;
+    let treasury_token_account = next_account(account_info_iter, "treasury_token_account")?;
+    let mint = next_account(account_info_iter, "mint")?;
+    let token_program = next_account(account_info_iter, "token_program")?;
+    let destinations = account_info_iter.as_slice(); // Token account per amount
+
+    if amounts.is_empty() || destinations.len() != amounts.len() {
+        return Err(ProgramError::NotEnoughAccountKeys);
+    }
+
+    let config = load_config(program_id, config_account)?;
+    assert_owner(&config, owner)?;
+    assert_treasury_pda(program_id, &config, treasury_pda)?;
+    assert_writable(treasury_pda, "treasury_pda")?;
+    assert_writable(treasury_token_account, "treasury_token_account")?;
+
+    if config.mint != *mint.key {
+        return Err(ProgramError::InvalidAccountData);
+    }
+
+    // ✅ The split must account for exactly the requested total
+    let sum = amounts
+        .iter()
+        .try_fold(0u64, |sum, amount| sum.checked_add(*amount))
+        .ok_or(ProgramError::ArithmeticOverflow)?;
+    if sum != total {
+        msg!("Split amounts sum to {}, expected {}", sum, total);
+        return Err(ProgramError::InvalidInstructionData);
+    }
+
+    let treasury_token_account_info =
+        assert_token_account(treasury_token_account, Some(treasury_pda.key), &config.mint)?;
+    assert_not_frozen(&treasury_token_account_info, treasury_token_account)?;
+    if treasury_token_account_info.amount < total {
+        return Err(TreasuryError::InsufficientTreasuryBalance.into());
+    }
+    // ✅ Withdrawals may not dip into locked funds
+    let mut state = TreasuryState::decode(&treasury_pda.try_borrow_data()?)?;
+    state.pay_out(total)?;
+    state.note_withdrawal(
+        Clock::get()?.unix_timestamp,
+        config.withdraw_cooldown_seconds,
+    )?;
+
+    for destination in destinations {
+        assert_writable(destination, "destination")?;
+        assert_token_account(destination, None, &config.mint)?;
+    }
+
+    for (destination, amount) in destinations.iter().zip(amounts.iter()) {
+        transfer_from_treasury(
+            &config,
+            token_program,
+            treasury_token_account,
+            mint,
+            destination,
+            treasury_pda,
+            *amount,
+            config.decimals,
+        )?;
+    }
+
+    state.serialize(&mut &mut treasury_pda.try_borrow_mut_data()?[..])?;
+
+    Ok(())
+}
+
+/// Set the allocation (`total`) of several users at once. Remaining accounts are the
+/// user-claim PDAs in the same order as `entries`; missing PDAs are created with an
+/// immediate unlock, existing ones keep their vesting schedule.
+pub fn process_set_allocation_batch(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    entries: Vec<(Pubkey, u64)>,
+) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let owner = next_account(account_info_iter, "owner")?; // Owner account (must sign, pays rent)
+    let config_account = next_account(account_info_iter, "config_account")?; // Config PDA
+    let system_program = next_account(account_info_iter, "system_program")?;
+    let treasury_pda = next_account(account_info_iter, "treasury_pda")?; // Tracks total_allocated
+    let user_claim_accounts = account_info_iter.as_slice(); // User-claim PDA per entry
+
+    if entries.is_empty() || user_claim_accounts.len() != entries.len() {
+        return Err(ProgramError::NotEnoughAccountKeys);
+    }
+    if entries.len() > MAX_ALLOCATION_BATCH {
+        msg!(
+            "Batch has {} allocations, limit is {}",
+            entries.len(),
+            MAX_ALLOCATION_BATCH
+        );
+        return Err(ProgramError::InvalidInstructionData);
+    }
+
+    let config = load_config(program_id, config_account)?;
+    assert_owner(&config, owner)?;
+    assert_treasury_pda(program_id, &config, treasury_pda)?;
+    let mut state = TreasuryState::decode(&treasury_pda.try_borrow_data()?)?;
+
+    // 🔹 Bound lazy PDA creation so the batch stays within runtime limits
+    let new_accounts = user_claim_accounts
+        .iter()
+        .filter(|account| account.owner != program_id)
+        .count();
+    if new_accounts > MAX_NEW_ACCOUNTS_PER_TX {
+        return Err(TreasuryError::TooManyNewAccounts.into());
+    }
+
+    for (user_claim_account, (user, total)) in user_claim_accounts.iter().zip(entries.iter()) {
+        assert_writable(user_claim_account, "user_claim")?;
+        let mut user_claim =
+            load_or_create_user_claim(program_id, owner, user_claim_account, system_program, user)?;
+        if *total < user_claim.claimed {
+            msg!(
+                "Allocation for {} is below the amount already claimed",
+                user
+            );
+            return Err(ProgramError::InvalidArgument);
+        }
+        state.reallocate(
+            user_claim.total - user_claim.claimed,
+            *total - user_claim.claimed,
+            config.allocation_cap,
+        )?;
+        user_claim.total = *total;
+        user_claim.serialize(&mut &mut user_claim_account.try_borrow_mut_data()?[..])?;
+    }
+
+    state.serialize(&mut &mut treasury_pda.try_borrow_mut_data()?[..])?;
+
+    Ok(())
+}
+
+/// User-signed claim of up to what the claimer's own schedule has unlocked, paid out to
+/// `destination` instead of their default token account. Takes the same accounts as `Claim`,
+/// with `destination` in the user token account slot, any signer paying for the receipt in
+/// the owner slot, and the claimer's user-claim PDA after the optional gate and co-signer
+/// accounts. `destination` only has to hold the treasury mint, even with `require_ata` set;
+/// receipts and history are still recorded against the claimer.
+pub fn process_claim_to(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    amount: u64,
+    destination: Pubkey,
+) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let _user = next_account(account_info_iter, "user")?; // Claimer (must sign)
+    let destination_account = next_account(account_info_iter, "destination_account")?; // Receives the tokens
+
+    // ✅ The signed instruction data, not just the account list, names the destination
+    if *destination_account.key != destination {
+        msg!(
+            "Destination account {} does not match requested {}",
+            destination_account.key,
+            destination
+        );
+        return Err(ProgramError::InvalidArgument);
+    }
+
+    execute_claim(
+        program_id,
+        accounts,
+        amount,
+        ClaimMode::User,
+        ClaimOptions {
+            named_destination: true,
+            ..ClaimOptions::default()
+        },
+    )
+    .map(|_| ())
+}
+
+/// Record the tracked treasury balance and the current time in the snapshots ring buffer,
+/// creating the buffer (paid for by the owner) on first use.
+pub fn process_snapshot(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let owner = next_account(account_info_iter, "owner")?; // Owner account (must sign, pays rent)
+    let config_account = next_account(account_info_iter, "config_account")?; // Config PDA
+    let treasury_pda = next_account(account_info_iter, "treasury_pda")?;
+    let snapshot_account = next_account(account_info_iter, "snapshot_account")?; // Snapshots PDA
+    let system_program = next_account(account_info_iter, "system_program")?;
+
+    let config = load_config(program_id, config_account)?;
+    assert_owner(&config, owner)?;
+    assert_treasury_pda(program_id, &config, treasury_pda)?;
+    assert_writable(snapshot_account, "snapshots")?;
+
+    let (expected_snapshot_pda, snapshot_bump) =
+        Pubkey::find_program_address(&[SNAPSHOT_SEED], program_id);
+    if expected_snapshot_pda != *snapshot_account.key {
+        return Err(ProgramError::InvalidSeeds);
+    }
+    // 🔹 Zeroed data is an empty ring buffer
+    create_pda_account(
+        owner,
+        snapshot_account,
+        system_program,
+        program_id,
+        SNAPSHOT_SPACE,
+        &[SNAPSHOT_SEED, &[snapshot_bump]],
+    )?;
+
+    let state = TreasuryState::decode(&treasury_pda.try_borrow_data()?)?;
+    let snapshot = BalanceSnapshot {
+        balance: state.balance,
+        timestamp: Clock::get()?.unix_timestamp,
+    };
+    append_snapshot(&mut snapshot_account.try_borrow_mut_data()?, &snapshot)?;
+
+    msg!(
+        "SNAPSHOT balance={} timestamp={}",
+        snapshot.balance,
+        snapshot.timestamp
+    );
+
+    Ok(())
+}
+
+/// Set or clear (`None`) the ClaimMint fee override for a registered mint.
+pub fn process_set_mint_fee(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    mint: Pubkey,
+    fee_bps: Option<u16>,
+) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let owner = next_account(account_info_iter, "owner")?; // Owner account (must sign)
+    let config_account = next_account(account_info_iter, "config_account")?; // Config PDA
+    let registry_account = next_account(account_info_iter, "registry_account")?; // Registry PDA
+
+    let config = load_config(program_id, config_account)?;
+    assert_owner(&config, owner)?;
+    assert_writable(registry_account, "registry")?;
+
+    if fee_bps.is_some_and(|bps| bps as u64 > BPS_DENOMINATOR) {
+        return Err(ProgramError::InvalidArgument);
+    }
+
+    let mut registry = load_registry(program_id, registry_account)?;
+    let slot = registry
+        .find(&mint)
+        .ok_or(TreasuryError::MintNotRegistered)?;
+    registry.entries[slot].fee_bps = fee_bps;
+
+    registry.serialize(&mut &mut registry_account.try_borrow_mut_data()?[..])?;
+
+    Ok(())
+}
+
+/// Switch the strategy `Claim` uses to decide eligibility; see the `ELIGIBILITY_*` constants.
+pub fn process_set_eligibility_mode(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    mode: u8,
+) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let owner = next_account(account_info_iter, "owner")?; // Owner account (must sign)
+    let config_account = next_account(account_info_iter, "config_account")?; // Config PDA
+
+    let mut config = load_config_mut(program_id, config_account)?;
+    assert_owner(&config, owner)?;
+
+    if mode > ELIGIBILITY_ALLOCATION {
+        msg!("Unknown eligibility mode {}", mode);
+        return Err(ProgramError::InvalidArgument);
+    }
+
+    config.eligibility_mode = mode;
+
+    store_config(&mut config, config_account)?;
+
+    msg!("ELIGIBILITY_MODE mode={}", mode);
+
+    Ok(())
+}
+
+/// Name the delegate allowed to sign `Claim` in the owner slot and set how much payout it
+/// may still sign for. Calling it again tops up (or cuts) the budget; `None` removes the
+/// delegate.
+pub fn process_set_delegate(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    delegate: Option<Pubkey>,
+    budget: u64,
+) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let owner = next_account(account_info_iter, "owner")?; // Owner account (must sign)
+    let config_account = next_account(account_info_iter, "config_account")?; // Config PDA
+
+    let mut config = load_config_mut(program_id, config_account)?;
+    assert_owner(&config, owner)?;
+
+    if delegate == Some(config.owner) {
+        msg!("The owner cannot be its own delegate");
+        return Err(ProgramError::InvalidArgument);
+    }
+    config.delegate = delegate;
+    config.delegate_budget = if delegate.is_some() { budget } else { 0 };
+
+    store_config(&mut config, config_account)?;
+
+    msg!(
+        "DELEGATE delegate={} budget={}",
+        dump_option(&config.delegate),
+        config.delegate_budget
+    );
+
+    Ok(())
+}
+
+/// Set the balance `Claim` must leave in the treasury. A reserve above the current tracked
+/// balance would block every claim at once, so it is rejected unless `allow_over_balance`
+/// says that is intended (e.g. ahead of a planned deposit).
+pub fn process_set_min_reserve(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    min_reserve: u64,
+    allow_over_balance: bool,
+) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let owner = next_account(account_info_iter, "owner")?; // Owner account (must sign)
+    let config_account = next_account(account_info_iter, "config_account")?; // Config PDA
+    let treasury_pda = next_account(account_info_iter, "treasury_pda")?; // Holds the balance
+
+    let mut config = load_config_mut(program_id, config_account)?;
+    assert_owner(&config, owner)?;
+    assert_treasury_pda(program_id, &config, treasury_pda)?;
+
+    let state = TreasuryState::decode(&treasury_pda.try_borrow_data()?)?;
+    if min_reserve > state.balance && !allow_over_balance {
+        msg!(
+            "Reserve {} exceeds the balance {} and would block all claims; pass allow_over_balance to force it",
+            min_reserve,
+            state.balance
+        );
+        return Err(ProgramError::InvalidArgument);
+    }
+
+    let previous = config.min_reserve;
+    config.min_reserve = min_reserve;
+
+    store_config(&mut config, config_account)?;
+
+    msg!(
+        "MIN_RESERVE previous={} min_reserve={} balance={}",
+        previous,
+        min_reserve,
+        state.balance
+    );
+
+    Ok(())
+}
+
+/// Set the per-user cap `Claim` enforces for the current campaign, without touching any
+/// per-user account. Raising it lets capped users claim again; lowering it below what a user
+/// already received simply leaves them nothing more to claim. Zero removes the cap.
+pub fn process_set_global_user_cap(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    cap: u64,
+) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let owner = next_account(account_info_iter, "owner")?; // Owner account (must sign)
+    let config_account = next_account(account_info_iter, "config_account")?; // Config PDA
+
+    let mut config = load_config_mut(program_id, config_account)?;
+    assert_owner(&config, owner)?;
+
+    let previous = config.per_user_cap;
+    config.per_user_cap = cap;
+
+    store_config(&mut config, config_account)?;
+
+    msg!("USER_CAP previous={} cap={}", previous, cap);
+
+    Ok(())
+}
+
+/// Set the treasury's off-chain metadata URI. Borsh already guarantees `uri` is UTF-8; it is
+/// stored zero-padded, so it must fit `MAX_URI_LEN` and contain no NUL bytes.
+pub fn process_set_uri(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    uri: String,
+) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let owner = next_account(account_info_iter, "owner")?; // Owner account (must sign)
+    let config_account = next_account(account_info_iter, "config_account")?; // Config PDA
+
+    let mut config = load_config_mut(program_id, config_account)?;
+    assert_owner(&config, owner)?;
+
+    if uri.len() > MAX_URI_LEN || uri.contains('\0') {
+        msg!(
+            "URI must be at most {} bytes without NUL characters",
+            MAX_URI_LEN
+        );
+        return Err(ProgramError::InvalidArgument);
+    }
+
+    config.uri = [0u8; MAX_URI_LEN];
+    config.uri[..uri.len()].copy_from_slice(uri.as_bytes());
+
+    store_config(&mut config, config_account)?;
+
+    Ok(())
+}
+
+/// Point the flat claim fee at a new vault. The account must be a system account, or a
+/// token account of the treasury mint; claim fees are paid in lamports either way.
+pub fn process_set_fee_vault(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    fee_vault: Pubkey,
+) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let owner = next_account(account_info_iter, "owner")?; // Owner account (must sign)
+    let config_account = next_account(account_info_iter, "config_account")?; // Config PDA
+    let fee_vault_account = next_account(account_info_iter, "fee_vault_account")?; // New fee vault
+
+    let mut config = load_config_mut(program_id, config_account)?;
+    assert_owner(&config, owner)?;
+
+    if *fee_vault_account.key != fee_vault || fee_vault == Pubkey::default() {
+        return Err(ProgramError::InvalidArgument);
+    }
+
+    // ✅ Only accounts that can actually hold the fees are accepted
+    if *fee_vault_account.owner == spl_token::id() {
+        assert_token_account(fee_vault_account, None, &config.mint)?;
+    } else if *fee_vault_account.owner != system_program::id() {
+        return Err(ProgramError::IllegalOwner);
+    }
+
+    config.fee_vault = fee_vault;
+
+    store_config(&mut config, config_account)?;
+
+    Ok(())
+}
+
+/// Owner-only move of `amount` between the liquid and locked balances. Unlocking never goes
+/// below what outstanding allocations require.
+pub fn process_move_locked(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    amount: u64,
+    lock: bool,
+) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let owner = next_account(account_info_iter, "owner")?; // Owner account (must sign)
+    let config_account = next_account(account_info_iter, "config_account")?; // Config PDA
+    let treasury_pda = next_account(account_info_iter, "treasury_pda")?;
+
+    let config = load_config(program_id, config_account)?;
+    assert_owner(&config, owner)?;
+    assert_treasury_pda(program_id, &config, treasury_pda)?;
+    assert_writable(treasury_pda, "treasury_pda")?;
+
+    let mut state = TreasuryState::decode(&treasury_pda.try_borrow_data()?)?;
+    if lock {
+        state.lock(amount)?;
+    } else {
+        state.unlock(amount)?;
+        if state.locked_balance < state.total_allocated {
+            msg!(
+                "Unlocking would leave {} locked for {} allocated",
+                state.locked_balance,
+                state.total_allocated
+            );
+            return Err(TreasuryError::OverAllocated.into());
+        }
+    }
+
+    state.serialize(&mut &mut treasury_pda.try_borrow_mut_data()?[..])?;
+
+    Ok(())
+}
+
+/// Why `AirdropBatchPartial` would skip a recipient, or `None` if it can be paid. Everything
+/// a transfer or receipt update could fail on is checked here, since a failed CPI would
+/// abort the whole transaction.
+fn partial_batch_skip_reason(
+    program_id: &Pubkey,
+    config: &TreasuryConfig,
+    recipient: &AccountInfo,
+    receipt_account: &AccountInfo,
+) -> Result<Pubkey, &'static str> {
+    if !recipient.is_writable || !receipt_account.is_writable {
+        return Err("not_writable");
+    }
+    // ✅ A token-shaped account of another program would only fail inside transfer_checked
+    if *recipient.owner != spl_token::id() {
+        return Err("recipient_not_token_account");
+    }
+    let recipient_info = recipient
+        .try_borrow_data()
+        .ok()
+        .and_then(|data| TokenAccount::unpack(&data).ok())
+        .ok_or("recipient_unreadable")?;
+    if recipient_info.mint != config.mint {
+        return Err("mint_mismatch");
+    }
+    if recipient_info.is_frozen() {
+        return Err("recipient_frozen");
+    }
+
+    let campaign_seed = config.campaign_id.to_le_bytes();
+    let user = recipient_info.owner;
+    let receipt_ok = if receipt_account.owner == program_id {
+        receipt_account
+            .try_borrow_data()
+            .ok()
+            .and_then(|data| ClaimReceipt::decode(&data).ok())
+            .is_some_and(|receipt| {
+                receipt.campaign_id == config.campaign_id
+                    && receipt.user == user
+                    && Pubkey::create_program_address(
+                        &[RECEIPT_SEED, &campaign_seed, user.as_ref(), &[receipt.bump]],
+                        program_id,
+                    )
+                    .is_ok_and(|expected| expected == *receipt_account.key)
+            })
+    } else {
+        // 🔹 create_pda_account can only take over a system account, pre-funded or not
+        if *receipt_account.owner != system_program::id() {
+            return Err("receipt_not_creatable");
+        }
+        let (expected, _) = Pubkey::find_program_address(
+            &[RECEIPT_SEED, &campaign_seed, user.as_ref()],
+            program_id,
+        );
+        expected == *receipt_account.key
+    };
+    if !receipt_ok {
+        return Err("receipt_mismatch");
+    }
+
+    Ok(user)
+}
+
+/// `AirdropBatch` that skips recipients it cannot pay instead of aborting. Each recipient
+/// is logged as `BATCH_RESULT index= status=`, followed by a `BATCH_SUMMARY`. Fails only
+/// when no recipient could be paid. Once claim stats exist, the stats accounts follow the
+/// pairs, with one claimant marker per amount.
+pub fn process_airdrop_batch_partial(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    amounts: Vec<u64>,
+) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let owner = next_account(account_info_iter, "owner")?; // Owner or claim authority (must sign)
+    let config_account = next_account(account_info_iter, "config_account")?; // Config PDA
+    let treasury_pda = next_account(account_info_iter, "treasury_pda")?;
+    let treasury_token_account = next_account(account_info_iter, "treasury_token_account")?;
+    let mint = next_account(account_info_iter, "mint")?;
+    let token_program = next_account(account_info_iter, "token_program")?;
+    let system_program = next_account(account_info_iter, "system_program")?;
+    let remaining_accounts = account_info_iter.as_slice();
+
+    if amounts.is_empty() || remaining_accounts.len() < amounts.len() * 2 {
+        return Err(ProgramError::NotEnoughAccountKeys);
+    }
+    // (token account, receipt) per amount, then the stats accounts
+    let (recipients, stats_tail) = remaining_accounts.split_at(amounts.len() * 2);
+    // 🔹 Bound per-recipient work so the batch stays within the compute budget
+    if amounts.len() > MAX_PARTIAL_BATCH {
+        msg!(
+            "Batch has {} recipients, limit is {}",
+            amounts.len(),
+            MAX_PARTIAL_BATCH
+        );
+        return Err(ProgramError::InvalidInstructionData);
+    }
+    assert_writable(treasury_pda, "treasury_pda")?;
+    assert_writable(treasury_token_account, "treasury_token_account")?;
+
+    let config = load_config(program_id, config_account)?;
+    assert_role(&config, owner, ROLE_CAN_CLAIM)?;
+    assert_treasury_pda(program_id, &config, treasury_pda)?;
+
+    if config.claims_paused {
+        return Err(TreasuryError::ClaimsPaused.into());
+    }
+    if config.mint != *mint.key {
+        return Err(ProgramError::InvalidAccountData);
+    }
+
+    let treasury_token_account_info =
+        assert_token_account(treasury_token_account, Some(treasury_pda.key), &config.mint)?;
+    assert_not_frozen(&treasury_token_account_info, treasury_token_account)?;
+
+    let stats_accounts = load_stats_accounts(program_id, &config, stats_tail, amounts.len())?;
+
+    let now = Clock::get()?.unix_timestamp;
+    let mut state = TreasuryState::decode(&treasury_pda.try_borrow_data()?)?;
+    let mut remaining = treasury_token_account_info.amount;
+    let mut new_accounts = 0usize;
+    let mut paid_total: u64 = 0;
+    let mut succeeded = 0usize;
+
+    for (index, (pair, amount)) in recipients.chunks_exact(2).zip(amounts.iter()).enumerate() {
+        let checked =
+            partial_batch_skip_reason(program_id, &config, &pair[0], &pair[1]).and_then(|user| {
+                if *amount > remaining {
+                    return Err("vault_balance");
+                }
+                if *amount > state.liquid_balance() {
+                    return Err("liquid_balance");
+                }
+                let new_marker = match &stats_accounts {
+                    Some(stats_accounts) => {
+                        check_claimant_marker(program_id, &stats_accounts.markers[index], &user)
+                            .map_err(|_| "claimant_marker")?
+                    }
+                    None => None,
+                };
+                // 🔹 Counted as requested, so a user paid twice still stays within the bound
+                let creates =
+                    (pair[1].owner != program_id) as usize + new_marker.is_some() as usize;
+                if new_accounts + creates > MAX_NEW_ACCOUNTS_PER_TX {
+                    return Err("too_many_new_accounts");
+                }
+                Ok((user, new_marker, creates))
+            });
+        let (user, new_marker, creates) = match checked {
+            Ok(checked) => checked,
+            Err(reason) => {
+                msg!(
+                    "BATCH_RESULT index={} status=skipped reason={}",
+                    index,
+                    reason
+                );
+                continue;
+            }
+        };
+
+        state.pay_out(*amount)?;
+        transfer_from_treasury(
+            &config,
+            token_program,
+            treasury_token_account,
+            mint,
+            &pair[0],
+            treasury_pda,
+            *amount,
+            config.decimals,
+        )?;
+
+        record_receipt(
+            program_id,
+            owner,
+            &pair[1],
+            system_program,
+            config.campaign_id,
+            &user,
+            *amount,
+            now,
+        )?;
+        new_accounts += creates;
+
+        if let Some(stats_accounts) = &stats_accounts {
+            record_stats(
+                program_id,
+                owner,
+                stats_accounts,
+                index,
+                &user,
+                new_marker,
+                *amount,
+                now,
+            )?;
+        }
+
+        remaining -= *amount;
+        paid_total += *amount;
+        succeeded += 1;
+        msg!("BATCH_RESULT index={} status=paid amount={}", index, amount);
+    }
+
+    msg!(
+        "BATCH_SUMMARY succeeded={} skipped={} total={}",
+        succeeded,
+        amounts.len() - succeeded,
+        paid_total
+    );
+    if succeeded == 0 {
+        return Err(ProgramError::InvalidAccountData);
+    }
+
+    state.serialize(&mut &mut treasury_pda.try_borrow_mut_data()?[..])?;
+
+    Ok(())
+}
+
+/// `AirdropBatch` that caps each recipient at the unclaimed allocation in their user-claim
+/// PDA. Remaining accounts are `(token account, receipt, user claim)` triples; missing
+/// receipts and user claims are created lazily, the latter with an empty allocation.
+///
+/// A recipient over their cap aborts the batch with `RecipientCapExceeded`, or is skipped
+/// and logged as `BATCH_RESULT` when `skip_over_cap` is set. Paid amounts are debited from
+/// the allocation exactly as an allocation-mode `Claim` would. Once claim stats exist, the
+/// stats accounts follow the triples, with one claimant marker per amount.
+pub fn process_airdrop_batch_capped(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    amounts: Vec<u64>,
+    skip_over_cap: bool,
+) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let owner = next_account(account_info_iter, "owner")?; // Owner or claim authority (must sign, pays rent)
+    let config_account = next_account(account_info_iter, "config_account")?; // Config PDA
+    let treasury_pda = next_account(account_info_iter, "treasury_pda")?;
+    let treasury_token_account = next_account(account_info_iter, "treasury_token_account")?;
+    let mint = next_account(account_info_iter, "mint")?;
+    let token_program = next_account(account_info_iter, "token_program")?;
+    let system_program = next_account(account_info_iter, "system_program")?;
+    let remaining_accounts = account_info_iter.as_slice();
+
+    if amounts.is_empty() || remaining_accounts.len() < amounts.len() * 3 {
+        return Err(ProgramError::NotEnoughAccountKeys);
+    }
+    // (token account, receipt, user claim) per amount, then the stats accounts
+    let (recipients, stats_tail) = remaining_accounts.split_at(amounts.len() * 3);
+    if amounts.len() > MAX_PARTIAL_BATCH {
+        msg!(
+            "Batch has {} recipients, limit is {}",
+            amounts.len(),
+            MAX_PARTIAL_BATCH
+        );
+        return Err(ProgramError::InvalidInstructionData);
+    }
+    assert_writable(treasury_pda, "treasury_pda")?;
+    assert_writable(treasury_token_account, "treasury_token_account")?;
+    for triple in recipients.chunks_exact(3) {
+        assert_writable(&triple[0], "recipient_token_account")?;
+        assert_writable(&triple[1], "receipt")?;
+        assert_writable(&triple[2], "user_claim")?;
+    }
+
+    let config = load_config(program_id, config_account)?;
+    assert_role(&config, owner, ROLE_CAN_CLAIM)?;
+    assert_treasury_pda(program_id, &config, treasury_pda)?;
+
+    if config.claims_paused {
+        return Err(TreasuryError::ClaimsPaused.into());
+    }
+    if config.mint != *mint.key {
+        return Err(ProgramError::InvalidAccountData);
+    }
+
+    let treasury_token_account_info =
+        assert_token_account(treasury_token_account, Some(treasury_pda.key), &config.mint)?;
+    assert_not_frozen(&treasury_token_account_info, treasury_token_account)?;
+
+    let stats_accounts = load_stats_accounts(program_id, &config, stats_tail, amounts.len())?;
+
+    // 🔹 Receipts, user claims and claimant markers all count towards the lazy-creation bound
+    let new_accounts = recipients
+        .chunks_exact(3)
+        .flat_map(|triple| &triple[1..])
+        .chain(stats_accounts.iter().flat_map(|stats| stats.markers))
+        .filter(|account| account.owner != program_id)
+        .count();
+    if new_accounts > MAX_NEW_ACCOUNTS_PER_TX {
+        msg!(
+            "Batch would create {} accounts, limit is {}; split the batch",
+            new_accounts,
+            MAX_NEW_ACCOUNTS_PER_TX
+        );
+        return Err(TreasuryError::TooManyNewAccounts.into());
+    }
+
+    let now = Clock::get()?.unix_timestamp;
+    let mut state = TreasuryState::decode(&treasury_pda.try_borrow_data()?)?;
+    let mut remaining = treasury_token_account_info.amount;
+    let mut paid_total: u64 = 0;
+    let mut succeeded = 0usize;
+
+    for (index, (triple, amount)) in recipients.chunks_exact(3).zip(amounts.iter()).enumerate() {
+        let recipient_info = assert_token_account(&triple[0], None, &config.mint)?;
+        let user = recipient_info.owner;
+        let mut user_claim =
+            load_or_create_user_claim(program_id, owner, &triple[2], system_program, &user)?;
+
+        // ✅ The user-claim PDA bounds what this batch may pay the recipient
+        let cap = user_claim.total.saturating_sub(user_claim.claimed);
+        if *amount > cap {
+            if !skip_over_cap {
+                msg!(
+                    "Recipient {} ({}) is owed at most {}, batch pays {}",
+                    index,
+                    user,
+                    cap,
+                    amount
+                );
+                return Err(TreasuryError::RecipientCapExceeded.into());
+            }
+            user_claim.serialize(&mut &mut triple[2].try_borrow_mut_data()?[..])?;
+            msg!(
+                "BATCH_RESULT index={} status=skipped reason=over_cap cap={}",
+                index,
+                cap
+            );
+            continue;
+        }
+        if *amount > remaining {
+            return Err(TreasuryError::InsufficientTreasuryBalance.into());
+        }
+        let new_marker = match &stats_accounts {
+            Some(stats_accounts) => {
+                check_claimant_marker(program_id, &stats_accounts.markers[index], &user)?
+            }
+            None => None,
+        };
+        // ✅ Paid out of the recipient's allocation lock, so other locks stay covered
+        state.total_allocated = state.total_allocated.saturating_sub(*amount);
+        state.pay_out_locked(*amount)?;
+
+        transfer_from_treasury(
+            &config,
+            token_program,
+            treasury_token_account,
+            mint,
+            &triple[0],
+            treasury_pda,
+            *amount,
+            config.decimals,
+        )?;
+
+        record_receipt(
+            program_id,
+            owner,
+            &triple[1],
+            system_program,
+            config.campaign_id,
+            &user,
+            *amount,
+            now,
+        )?;
+
+        user_claim.claimed = user_claim
+            .claimed
+            .checked_add(*amount)
+            .ok_or(ProgramError::ArithmeticOverflow)?;
+        user_claim.serialize(&mut &mut triple[2].try_borrow_mut_data()?[..])?;
+
+        if let Some(stats_accounts) = &stats_accounts {
+            record_stats(
+                program_id,
+                owner,
+                stats_accounts,
+                index,
+                &user,
+                new_marker,
+                *amount,
+                now,
+            )?;
+        }
+
+        remaining -= *amount;
+        paid_total += *amount;
+        succeeded += 1;
+        msg!("BATCH_RESULT index={} status=paid amount={}", index, amount);
+    }
+
+    msg!(
+        "BATCH_SUMMARY succeeded={} skipped={} total={}",
+        succeeded,
+        amounts.len() - succeeded,
+        paid_total
+    );
+    if succeeded == 0 {
+        return Err(TreasuryError::RecipientCapExceeded.into());
+    }
+
+    state.serialize(&mut &mut treasury_pda.try_borrow_mut_data()?[..])?;
+
+    Ok(())
+}
+
+/// Create (or resize) only the treasury state PDA, leaving the config untouched. Used after
+/// a state-schema change; refuses to touch a state account that already holds data.
+pub fn process_initialize_state(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let owner = next_account(account_info_iter, "owner")?; // Owner account (must sign, pays rent)
+    let config_account = next_account(account_info_iter, "config_account")?; // Config PDA
+    let treasury_pda = next_account(account_info_iter, "treasury_pda")?;
+    let system_program = next_account(account_info_iter, "system_program")?;
+
+    let config = load_config(program_id, config_account)?;
+    assert_owner(&config, owner)?;
+    assert_writable(treasury_pda, "treasury_pda")?;
+
+    let expected_treasury_pda =
+        Pubkey::create_program_address(&[TREASURY_SEED, &[config.treasury_bump]], program_id)
+            .map_err(|_| ProgramError::InvalidSeeds)?;
+    if expected_treasury_pda != *treasury_pda.key {
+        return Err(ProgramError::InvalidSeeds);
+    }
+
+    let treasury_space = 8 + std::mem::size_of::<TreasuryState>();
+    if treasury_pda.owner == program_id {
+        // ✅ Never overwrite a populated state
+        if treasury_pda
+            .try_borrow_data()?
+            .iter()
+            .any(|byte| *byte != 0)
+        {
+            return Err(ProgramError::AccountAlreadyInitialized);
+        }
+
+        // 🔹 An empty account from an older layout is grown in place
+        let current_len = treasury_pda.data_len();
+        if current_len < treasury_space {
+            if treasury_space - current_len > MAX_PERMITTED_DATA_INCREASE {
+                return Err(ProgramError::InvalidRealloc);
+            }
+            let top_up = Rent::get()?
+                .minimum_balance(treasury_space)
+                .saturating_sub(treasury_pda.lamports());
+            if top_up > 0 {
+                invoke(
+                    &system_instruction::transfer(owner.key, treasury_pda.key, top_up),
+                    &[owner.clone(), treasury_pda.clone(), system_program.clone()],
+                )?;
+            }
+            treasury_pda.realloc(treasury_space, true)?;
+        }
+    } else {
+        create_pda_account(
+            owner,
+            treasury_pda,
+            system_program,
+            program_id,
+            treasury_space,
+            &[TREASURY_SEED, &[config.treasury_bump]],
+        )?;
+    }
+
+    Ok(())
+}
+
+/// Size of one ed25519 signature offsets record in the ed25519 program's instruction data.
+const ED25519_OFFSETS_LEN: usize = 14;
+
+/// Check that the instruction right before the current one is an ed25519 verification of
+/// `message` by `signer`, with all data carried inside that instruction.
+fn verify_ed25519_authorization(
+    instructions_sysvar: &AccountInfo,
+    signer: &Pubkey,
+    message: &[u8],
+) -> ProgramResult {
+    let current_index = load_current_index_checked(instructions_sysvar)?;
+    if current_index == 0 {
+        return Err(TreasuryError::InvalidAuthorization.into());
+    }
+    let verify_ix = load_instruction_at_checked(current_index as usize - 1, instructions_sysvar)?;
+    if verify_ix.program_id != ed25519_program::id() {
+        return Err(TreasuryError::InvalidAuthorization.into());
+    }
+
+    check_ed25519_offsets(&verify_ix.data, signer, message)
+}
+
+/// Check that ed25519 program instruction `data` verifies exactly one signature of `message`
+/// by `signer`, with the key, signature and message all read from that instruction.
+fn check_ed25519_offsets(data: &[u8], signer: &Pubkey, message: &[u8]) -> ProgramResult {
+    if data.len() < 2 + ED25519_OFFSETS_LEN || data[0] != 1 {
+        return Err(TreasuryError::InvalidAuthorization.into());
+    }
+    let read_u16 = |at: usize| u16::from_le_bytes([data[at], data[at + 1]]);
+    let signature_ix_index = read_u16(4);
+    let public_key_offset = read_u16(6) as usize;
+    let public_key_ix_index = read_u16(8);
+    let message_offset = read_u16(10) as usize;
+    let message_size = read_u16(12) as usize;
+    let message_ix_index = read_u16(14);
+
+    // 🔹 u16::MAX means "this instruction"; anything else could point at attacker data
+    if signature_ix_index != u16::MAX
+        || public_key_ix_index != u16::MAX
+        || message_ix_index != u16::MAX
+    {
+        return Err(TreasuryError::InvalidAuthorization.into());
+    }
+
+    let signed_key = data.get(public_key_offset..public_key_offset + 32);
+    let signed_message = data.get(message_offset..message_offset + message_size);
+    if signed_key != Some(signer.as_ref()) || signed_message != Some(message) {
+        return Err(TreasuryError::InvalidAuthorization.into());
+    }
+
+    Ok(())
+}
+
+/// `Claim` authorized by an owner signature instead of an on-chain owner signer. Accounts
+/// are the instructions sysvar, the nonce page PDA for `authorization.nonce`, then the usual
+/// `Claim` accounts, where the authority slot is whoever submits and pays. The claim must be
+/// for the authorized user, paid to a token account that user controls, within the authorized
+/// amount, before expiry and with an unused nonce. `GaslessClaim` shares this path with `ClaimMode::Gasless`: the relayer in the
+/// authority slot also pays the flat fee, so the user never signs.
+pub fn process_claim_authorized(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    amount: u64,
+    authorization: ClaimAuthorization,
+    mode: ClaimMode,
+) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let instructions_sysvar = next_account(account_info_iter, "instructions_sysvar")?;
+    let nonce_page_account = next_account(account_info_iter, "nonce_page_account")?; // Nonce page PDA
+    let claim_accounts = account_info_iter.as_slice();
+    let claim_iter = &mut claim_accounts.iter();
+    let user = next_account(claim_iter, "user")?;
+    let _user_token_account = next_account(claim_iter, "user_token_account")?;
+    let _treasury_token_account = next_account(claim_iter, "treasury_token_account")?;
+    let _token_program = next_account(claim_iter, "token_program")?;
+    let _treasury_pda = next_account(claim_iter, "treasury_pda")?;
+    let payer = next_account(claim_iter, "payer")?;
+    let config_account = next_account(claim_iter, "config_account")?; // Config PDA
+    let _history_account = next_account(claim_iter, "history_account")?;
+    let _fee_vault = next_account(claim_iter, "fee_vault")?;
+    let system_program = next_account(claim_iter, "system_program")?;
+
+    let config = load_config(program_id, config_account)?;
+    if config.owner == Pubkey::default() {
+        return Err(TreasuryError::OwnershipRenounced.into());
+    }
+
+    let message = authorization.try_to_vec()?;
+    verify_ed25519_authorization(instructions_sysvar, &config.owner, &message)?;
+
+    // ✅ A relayer may submit the authorization but not stretch it
+    if authorization.user != *user.key {
+        return Err(claim_rejected(
+            config.error_verbosity,
+            TreasuryError::InvalidAuthorization,
+            "authorization_user",
+            format!("authorized={} claimer={}", authorization.user, user.key),
+        ));
+    }
+    if amount > authorization.amount {
+        return Err(claim_rejected(
+            config.error_verbosity,
+            TreasuryError::InvalidAuthorization,
+            "authorization_amount",
+            format!("amount={} authorized={}", amount, authorization.amount),
+        ));
+    }
+    if authorization.expires_at != 0 && Clock::get()?.unix_timestamp > authorization.expires_at {
+        return Err(claim_rejected(
+            config.error_verbosity,
+            TreasuryError::InvalidAuthorization,
+            "authorization_expired",
+            format!("expires_at={}", authorization.expires_at),
+        ));
+    }
+
+    // ✅ Each authorization pays out once, however many times it is resubmitted
+    let creates_nonce_page = nonce_page_account.owner != program_id;
+    consume_nonce(
+        program_id,
+        payer,
+        nonce_page_account,
+        system_program,
+        authorization.nonce,
+        config.error_verbosity,
+    )?;
+
+    execute_claim(
+        program_id,
+        claim_accounts,
+        amount,
+        mode,
+        ClaimOptions {
+            new_accounts: creates_nonce_page as u8,
+            ..ClaimOptions::default()
+        },
+    )
+    .map(|_| ())
+}
+
+/// Mark `nonce` as used in its page, creating the page on first use. Rejects a nonce that
+/// has already been consumed.
+fn consume_nonce<'a>(
+    program_id: &Pubkey,
+    payer: &AccountInfo<'a>,
+    nonce_page_account: &AccountInfo<'a>,
+    system_program: &AccountInfo<'a>,
+    nonce: u64,
+    verbosity: u8,
+) -> ProgramResult {
+    let mut nonce_page = load_or_create_nonce_page(
+        program_id,
+        payer,
+        nonce_page_account,
+        system_program,
+        nonce / NONCES_PER_PAGE,
+    )?;
+
+    if nonce_page.is_used(nonce) {
+        return Err(claim_rejected(
+            verbosity,
+            TreasuryError::InvalidAuthorization,
+            "authorization_replayed",
+            format!("nonce={}", nonce),
+        ));
+    }
+    nonce_page.mark_used(nonce);
+    nonce_page.serialize(&mut &mut nonce_page_account.try_borrow_mut_data()?[..])?;
+
+    Ok(())
+}
+
+/// Load nonce page `page`, creating an empty one paid for by `payer` if it does not exist.
+fn load_or_create_nonce_page<'a>(
+    program_id: &Pubkey,
+    payer: &AccountInfo<'a>,
+    nonce_page_account: &AccountInfo<'a>,
+    system_program: &AccountInfo<'a>,
+    page: u64,
+) -> Result<NoncePage, ProgramError> {
+    assert_writable(nonce_page_account, "nonce_page")?;
+    let page_seed = page.to_le_bytes();
+
+    if nonce_page_account.owner == program_id {
+        let nonce_page = NoncePage::decode(&nonce_page_account.try_borrow_data()?)?;
+        let expected_page_pda = Pubkey::create_program_address(
+            &[NONCE_SEED, &page_seed, &[nonce_page.bump]],
+            program_id,
+        )
+        .map_err(|_| ProgramError::InvalidSeeds)?;
+        if expected_page_pda != *nonce_page_account.key || nonce_page.page != page {
+            return Err(ProgramError::InvalidSeeds);
+        }
+        Ok(nonce_page)
+    } else {
+        let (expected_page_pda, bump) =
+            Pubkey::find_program_address(&[NONCE_SEED, &page_seed], program_id);
+        if expected_page_pda != *nonce_page_account.key {
+            return Err(ProgramError::InvalidSeeds);
+        }
+        create_pda_account(
+            payer,
+            nonce_page_account,
+            system_program,
+            program_id,
+            8 + std::mem::size_of::<NoncePage>(),
+            &[NONCE_SEED, &page_seed, &[bump]],
+        )?;
+        Ok(NoncePage {
+            page,
+            used: [0u8; 32],
+            bump,
+        })
+    }
+}
+
+/// Move the entire vault balance to `new_treasury_token_account` (typically owned by a
+/// redeployed program's PDA) in one step. Claims and deposits must both be paused first so
+/// nothing races the drain. A recorded `config.vault` is repointed at the new account, which
+/// then needs the config passed writable.
+pub fn process_migrate_treasury(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    new_treasury_token_account: Pubkey,
+) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let owner = next_account(account_info_iter, "owner")?; // Owner account (must sign)
+    let config_account = next_account(account_info_iter, "config_account")?; // Config PDA
+    let treasury_pda = next_account(account_info_iter, "treasury_pda")?;
+    let treasury_token_account = next_account(account_info_iter, "treasury_token_account")?;
+    let destination = next_account(account_info_iter, "destination")?; // New treasury token account
+    let mint = next_account(account_info_iter, "mint")?;
+    let token_program = next_account(account_info_iter, "token_program")?;
+
+    let mut config = load_config(program_id, config_account)?;
+    assert_owner(&config, owner)?;
+    assert_treasury_pda(program_id, &config, treasury_pda)?;
+    assert_writable(treasury_pda, "treasury_pda")?;
+    assert_writable(treasury_token_account, "treasury_token_account")?;
+    assert_writable(destination, "destination")?;
+
+    if !config.emergency_stop && (!config.claims_paused || !config.deposits_paused) {
+        msg!("Pause claims and deposits before migrating the treasury");
+        return Err(ProgramError::InvalidAccountData);
+    }
+    if *destination.key != new_treasury_token_account
+        || *destination.key == *treasury_token_account.key
+    {
+        return Err(ProgramError::InvalidArgument);
+    }
+    if config.mint != *mint.key {
+        return Err(ProgramError::InvalidAccountData);
+    }
+
+    let treasury_token_account_info =
+        assert_token_account(treasury_token_account, Some(treasury_pda.key), &config.mint)?;
+    assert_not_frozen(&treasury_token_account_info, treasury_token_account)?;
+
+    assert_token_account(destination, None, &config.mint)?;
+
+    let drained = treasury_token_account_info.amount;
+    if drained > 0 {
+        transfer_from_treasury(
+            &config,
+            token_program,
+            treasury_token_account,
+            mint,
+            destination,
+            treasury_pda,
+            drained,
+            config.decimals,
+        )?;
+    }
+
+    // ✅ Nothing is left to account for in this treasury
+    let mut state = TreasuryState::decode(&treasury_pda.try_borrow_data()?)?;
+    state.balance = 0;
+    state.locked_balance = 0;
+    state.total_allocated = 0;
+    for pool in state.pools.iter_mut() {
+        pool.balance = 0;
+    }
+    state.serialize(&mut &mut treasury_pda.try_borrow_mut_data()?[..])?;
+
+    // 🔹 Claims follow the recorded vault, so it moves with the funds
+    if config.vault.is_some() {
+        assert_writable(config_account, "config_account")?;
+        config.vault = Some(*destination.key);
+        store_config(&mut config, config_account)?;
+    }
+
+    msg!(
+        "MIGRATED amount={} destination={}",
+        drained,
+        destination.key
+    );
+
+    Ok(())
+}
+
+/// Lower `user`'s allocation to `new_allocation`, never below what they already claimed.
+/// The released amount is unlocked and removed from `total_allocated`.
+pub fn process_reduce_allocation(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    user: Pubkey,
+    new_allocation: u64,
+) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let owner = next_account(account_info_iter, "owner")?; // Owner account (must sign)
+    let config_account = next_account(account_info_iter, "config_account")?; // Config PDA
+    let user_claim_account = next_account(account_info_iter, "user_claim_account")?; // User-claim PDA
+    let treasury_pda = next_account(account_info_iter, "treasury_pda")?; // Tracks total_allocated
+
+    let config = load_config(program_id, config_account)?;
+    assert_owner(&config, owner)?;
+    assert_treasury_pda(program_id, &config, treasury_pda)?;
+    assert_writable(user_claim_account, "user_claim")?;
+    assert_writable(treasury_pda, "treasury_pda")?;
+
+    let mut user_claim = load_user_claim(program_id, user_claim_account, &user)?;
+    if new_allocation < user_claim.claimed {
+        msg!(
+            "New allocation {} is below the {} already claimed",
+            new_allocation,
+            user_claim.claimed
+        );
+        return Err(ProgramError::InvalidArgument);
+    }
+    if new_allocation > user_claim.total {
+        msg!("ReduceAllocation cannot raise an allocation; use SetVesting");
+        return Err(ProgramError::InvalidArgument);
+    }
+
+    let mut state = TreasuryState::decode(&treasury_pda.try_borrow_data()?)?;
+    state.reallocate(
+        user_claim.total - user_claim.claimed,
+        new_allocation - user_claim.claimed,
+        config.allocation_cap,
+    )?;
+    state.serialize(&mut &mut treasury_pda.try_borrow_mut_data()?[..])?;
+
+    user_claim.total = new_allocation;
+    user_claim.serialize(&mut &mut user_claim_account.try_borrow_mut_data()?[..])?;
+
+    Ok(())
+}
+
+/// Set or lift the emergency stop. The owner or a pause guardian (`ROLE_CAN_PAUSE`) may
+/// stop the treasury; only the owner may lift the stop.
+pub fn process_set_emergency_stop(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    stopped: bool,
+) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let signer = next_account(account_info_iter, "signer")?; // Owner or guardian (must sign)
+    let config_account = next_account(account_info_iter, "config_account")?; // Config PDA
+
+    let mut config = load_config_mut(program_id, config_account)?;
+    if stopped {
+        assert_role(&config, signer, ROLE_CAN_PAUSE)?;
+    } else {
+        assert_owner(&config, signer)?;
+    }
+
+    config.emergency_stop = stopped;
+
+    store_config(&mut config, config_account)?;
+
+    msg!("EMERGENCY_STOP stopped={} by={}", stopped, signer.key);
+
+    Ok(())
+}
+
+/// Create the campaign PDA for `campaign_id`, paid for by the owner. Each campaign keeps its
+/// own root, caps and window while paying out of the one treasury vault.
+#[allow(clippy::too_many_arguments)]
+pub fn process_create_campaign(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    campaign_id: u64,
+    merkle_root: [u8; 32],
+    total_cap: u64,
+    per_user_cap: u64,
+    starts_at: i64,
+    ends_at: i64,
+) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let owner = next_account(account_info_iter, "owner")?; // Owner account (must sign, pays rent)
+    let config_account = next_account(account_info_iter, "config_account")?; // Config PDA
+    let campaign_account = next_account(account_info_iter, "campaign_account")?; // Campaign PDA to create
+    let system_program = next_account(account_info_iter, "system_program")?; // System program
+
+    let config = load_config(program_id, config_account)?;
+    assert_owner(&config, owner)?;
+    assert_writable(campaign_account, "campaign")?;
+
+    if merkle_root == [0u8; 32] || total_cap == 0 {
+        msg!("A campaign needs a merkle root and a non-zero total cap");
+        return Err(ProgramError::InvalidArgument);
+    }
+    if ends_at != 0 && ends_at < starts_at {
+        msg!(
+            "Campaign ends at {} before it starts at {}",
+            ends_at,
+            starts_at
+        );
+        return Err(ProgramError::InvalidArgument);
+    }
+
+    let campaign_seed = campaign_id.to_le_bytes();
+    let (expected_campaign_pda, bump) =
+        Pubkey::find_program_address(&[CAMPAIGN_SEED, &campaign_seed], program_id);
+    if expected_campaign_pda != *campaign_account.key {
+        return Err(ProgramError::InvalidSeeds);
+    }
+    if campaign_account.owner == program_id {
+        return Err(ProgramError::AccountAlreadyInitialized);
+    }
+
+    create_pda_account(
+        owner,
+        campaign_account,
+        system_program,
+        program_id,
+        8 + std::mem::size_of::<Campaign>(),
+        &[CAMPAIGN_SEED, &campaign_seed, &[bump]],
+    )?;
+
+    let campaign = Campaign {
+        campaign_id,
+        merkle_root,
+        total_cap,
+        per_user_cap,
+        claimed: 0,
+        starts_at,
+        ends_at,
+        bump,
+    };
+    campaign.serialize(&mut &mut campaign_account.try_borrow_mut_data()?[..])?;
+
+    msg!(
+        "CAMPAIGN_CREATED campaign_id={} total_cap={} per_user_cap={} starts_at={} ends_at={}",
+        campaign_id,
+        total_cap,
+        per_user_cap,
+        starts_at,
+        ends_at
+    );
+
+    Ok(())
+}
+
+/// Claim `amount` from campaign `campaign_id` by proving the signer's `allocation` against
+/// the campaign root. The user's running total lives in the receipt for `campaign_id`.
+///
+/// Accounts: user (signer, pays for the receipt), user token account, treasury token account,
+/// token program, treasury PDA, config, campaign PDA, receipt PDA, mint, system program, then
+/// the claim-stats accounts once they exist.
+pub fn process_claim_campaign(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    campaign_id: u64,
+    amount: u64,
+    allocation: u64,
+    proof: Vec<[u8; 32]>,
+) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let user = next_account(account_info_iter, "user")?; // Claimer (must sign)
+    let user_token_account = next_account(account_info_iter, "user_token_account")?; // User's token account
+    let treasury_token_account = next_account(account_info_iter, "treasury_token_account")?; // Shared treasury vault
+    let token_program = next_account(account_info_iter, "token_program")?; // SPL token program
+    let treasury_pda = next_account(account_info_iter, "treasury_pda")?; // Treasury PDA
+    let config_account = next_account(account_info_iter, "config_account")?; // Config PDA
+    let campaign_account = next_account(account_info_iter, "campaign_account")?; // Campaign PDA
+    let receipt_account = next_account(account_info_iter, "receipt_account")?; // Receipt for (campaign, user)
+    let mint = next_account(account_info_iter, "mint")?; // Treasury mint
+    let system_program = next_account(account_info_iter, "system_program")?; // System program
+
+    assert_writable(user_token_account, "user_token_account")?;
+    assert_writable(treasury_token_account, "treasury_token_account")?;
+    assert_writable(treasury_pda, "treasury_pda")?;
+    assert_writable(campaign_account, "campaign")?;
+    assert_writable(receipt_account, "receipt")?;
+
+    let config = load_config(program_id, config_account)?;
+    if config.claims_paused {
+        return Err(TreasuryError::ClaimsPaused.into());
+    }
+    assert_treasury_pda(program_id, &config, treasury_pda)?;
+    if !user.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    if *mint.key != config.mint {
+        return Err(ProgramError::InvalidAccountData);
+    }
+    if amount == 0 {
+        return Err(ProgramError::InvalidArgument);
+    }
+
+    let mut campaign = load_campaign(program_id, campaign_account, campaign_id)?;
+    let now = Clock::get()?.unix_timestamp;
+    if !campaign.is_open(now) {
+        msg!(
+            "Campaign {} is open from {} to {}; now is {}",
+            campaign_id,
+            campaign.starts_at,
+            campaign.ends_at,
+            now
+        );
+        return Err(TreasuryError::ClaimWindowClosed.into());
+    }
+
+    // ✅ The signer must be in the campaign tree with exactly this allocation
+    if proof.len() > MAX_MERKLE_PROOF_LEN
+        || !verify_merkle_proof(
+            &proof,
+            &campaign.merkle_root,
+            campaign_leaf(user.key, allocation),
+        )
+    {
+        return Err(TreasuryError::InvalidMerkleProof.into());
+    }
+
+    // 🔹 Caps are per campaign: the user's share and the campaign total
+    let already_claimed = if receipt_account.owner == program_id {
+        ClaimReceipt::decode(&receipt_account.try_borrow_data()?)?.total_claimed
+    } else {
+        0
+    };
+    let user_limit = if campaign.per_user_cap > 0 {
+        allocation.min(campaign.per_user_cap)
+    } else {
+        allocation
+    };
+    let user_total = already_claimed
+        .checked_add(amount)
+        .ok_or(ProgramError::ArithmeticOverflow)?;
+    let campaign_total = campaign
+        .claimed
+        .checked_add(amount)
+        .ok_or(ProgramError::ArithmeticOverflow)?;
+    if user_total > user_limit || campaign_total > campaign.total_cap {
+        msg!(
+            "Campaign {} cap exceeded: user {}/{} campaign {}/{}",
+            campaign_id,
+            user_total,
+            user_limit,
+            campaign_total,
+            campaign.total_cap
+        );
+        return Err(TreasuryError::CampaignCapExceeded.into());
+    }
+
+    let treasury_token_account_info =
+        assert_token_account(treasury_token_account, Some(treasury_pda.key), &config.mint)?;
+    assert_not_frozen(&treasury_token_account_info, treasury_token_account)?;
+    if treasury_token_account_info.amount < amount {
+        return Err(TreasuryError::InsufficientTreasuryBalance.into());
+    }
+
+    // ✅ Once stats exist every payout must pass them, so the totals never miss one
+    let stats_accounts = load_stats_accounts(program_id, &config, account_info_iter.as_slice(), 1)?;
+    let new_marker = match &stats_accounts {
+        Some(stats_accounts) => {
+            check_claimant_marker(program_id, &stats_accounts.markers[0], user.key)?
+        }
+        None => None,
+    };
+
+    let mut state = TreasuryState::decode(&treasury_pda.try_borrow_data()?)?;
+    state.note_claim_time(now, config.min_claim_interval)?;
+    state.pay_out(amount)?;
+    let seq = state.next_claim_seq()?;
+
+    transfer_from_treasury(
+        &config,
+        token_program,
+        treasury_token_account,
+        mint,
+        user_token_account,
+        treasury_pda,
+        amount,
+        config.decimals,
+    )?;
+
+    state.serialize(&mut &mut treasury_pda.try_borrow_mut_data()?[..])?;
+
+    campaign.claimed = campaign_total;
+    campaign.serialize(&mut &mut campaign_account.try_borrow_mut_data()?[..])?;
+
+    record_receipt(
+        program_id,
+        user,
+        receipt_account,
+        system_program,
+        campaign_id,
+        user.key,
+        amount,
+        now,
+    )?;
+
+    if let Some(stats_accounts) = &stats_accounts {
+        record_stats(
+            program_id,
+            user,
+            stats_accounts,
+            0,
+            user.key,
+            new_marker,
+            amount,
+            now,
+        )?;
+    }
+
+    emit_claimed(user.key, &config.mint, amount, seq, None);
+    msg!(
+        "CAMPAIGN_CLAIMED campaign_id={} user={} amount={} campaign_claimed={}",
+        campaign_id,
+        user.key,
+        amount,
+        campaign_total
+    );
+
+    Ok(())
+}
+
+/// Close `user`'s claim PDA once the campaign is over, returning its rent to the treasury PDA
+/// or the owner. Refused until `claim_deadline` and its grace period have passed, so it can
+/// never reset a user's caps while claims are still possible. Any unclaimed allocation is
+/// released from `total_allocated`.
+pub fn process_force_close_user_claim(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    user: Pubkey,
+) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let owner = next_account(account_info_iter, "owner")?; // Owner account (must sign)
+    let config_account = next_account(account_info_iter, "config_account")?; // Config PDA
+    let user_claim_account = next_account(account_info_iter, "user_claim_account")?; // User-claim PDA
+    let treasury_pda = next_account(account_info_iter, "treasury_pda")?; // Tracks total_allocated
+    let recipient = next_account(account_info_iter, "recipient")?; // Treasury PDA or owner
+
+    let config = load_config(program_id, config_account)?;
+    assert_owner(&config, owner)?;
+    assert_treasury_pda(program_id, &config, treasury_pda)?;
+    assert_writable(user_claim_account, "user_claim")?;
+    assert_writable(treasury_pda, "treasury_pda")?;
+    assert_writable(recipient, "recipient")?;
+    if recipient.key != treasury_pda.key && recipient.key != owner.key {
+        msg!("Rent from a closed user claim may only go to the treasury PDA or the owner");
+        return Err(ProgramError::InvalidArgument);
+    }
+
+    // ✅ Only after the claim window has fully closed
+    let now = Clock::get()?.unix_timestamp;
+    let window_end = config
+        .claim_deadline
+        .saturating_add(config.grace_period_seconds);
+    if config.claim_deadline == 0 || now <= window_end {
+        msg!(
+            "User claims can be force-closed after {} (deadline plus grace); now is {}",
+            window_end,
+            now
+        );
+        return Err(TreasuryError::ClaimWindowOpen.into());
+    }
+
+    let user_claim = load_user_claim(program_id, user_claim_account, &user)?;
+
+    let mut state = TreasuryState::decode(&treasury_pda.try_borrow_data()?)?;
+    state.reallocate(
+        user_claim.total.saturating_sub(user_claim.claimed),
+        0,
+        config.allocation_cap,
+    )?;
+    state.serialize(&mut &mut treasury_pda.try_borrow_mut_data()?[..])?;
+
+    close_account(user_claim_account, recipient)?;
+
+    msg!(
+        "USER_CLAIM_CLOSED user={} released={} recipient={}",
+        user,
+        user_claim.total.saturating_sub(user_claim.claimed),
+        recipient.key
+    );
+
+    Ok(())
+}
+
+/// Log one `DUMP <section>.<key>=<value>` line for `LogState`.
+fn log_dump(section: &str, key: &str, value: impl std::fmt::Display) {
+    msg!("DUMP {}.{}={}", section, key, value);
+}
+
+/// `Option<Pubkey>` as logged by `LogState`: the key, or `none`.
+fn dump_option(value: &Option<Pubkey>) -> String {
+    value.map_or_else(|| "none".to_string(), |key| key.to_string())
+}
+
+/// Read-only diagnostics, meant for `simulateTransaction`: logs every field of the config and
+/// the treasury state as `DUMP config.<field>=<value>` / `DUMP state.<field>=<value>` lines.
+/// Array fields are logged per element as `<field>.<index>.<member>`.
+pub fn process_log_state(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let config_account = next_account(account_info_iter, "config_account")?; // Config PDA
+    let treasury_pda = next_account(account_info_iter, "treasury_pda")?; // Treasury PDA
+
+    let config = load_config(program_id, config_account)?;
+    assert_treasury_pda(program_id, &config, treasury_pda)?;
+    let state = TreasuryState::decode(&treasury_pda.try_borrow_data()?)?;
+
+    let c = "config";
+    log_dump(c, "owner", config.owner);
+    log_dump(c, "created_at", config.created_at);
+    log_dump(c, "flat_fee_lamports", config.flat_fee_lamports);
+    log_dump(c, "fee_vault", config.fee_vault);
+    log_dump(c, "treasury_bump", config.treasury_bump);
+    log_dump(c, "config_bump", config.config_bump);
+    log_dump(c, "history_bump", config.history_bump);
+    log_dump(c, "mint", config.mint);
+    log_dump(c, "decimals", config.decimals);
+    log_dump(c, "owner_timelock_seconds", config.owner_timelock_seconds);
+    log_dump(c, "pending_owner", dump_option(&config.pending_owner));
+    log_dump(
+        c,
+        "owner_change_effective_at",
+        config.owner_change_effective_at,
+    );
+    log_dump(c, "claims_paused", config.claims_paused);
+    log_dump(c, "deposits_paused", config.deposits_paused);
+    for (index, authority) in config.authorities.iter().enumerate() {
+        log_dump(c, &format!("authorities.{}.key", index), authority.key);
+        log_dump(c, &format!("authorities.{}.roles", index), authority.roles);
+    }
+    log_dump(c, "strict_amounts", config.strict_amounts);
+    log_dump(c, "campaign_id", config.campaign_id);
+    log_dump(c, "multiplier_bps", config.multiplier_bps);
+    log_dump(c, "claim_deadline", config.claim_deadline);
+    log_dump(c, "grace_period_seconds", config.grace_period_seconds);
+    log_dump(c, "late_fee_bps", config.late_fee_bps);
+    log_dump(c, "enforce_treasury_ata", config.enforce_treasury_ata);
+    log_dump(c, "gate_mint", dump_option(&config.gate_mint));
+    log_dump(c, "allocation_cap", config.allocation_cap);
+    log_dump(c, "max_claims_per_user", config.max_claims_per_user);
+    log_dump(c, "cosigner", dump_option(&config.cosigner));
+    log_dump(c, "default_mint_fee_bps", config.default_mint_fee_bps);
+    log_dump(c, "uri", config.uri());
+    log_dump(c, "rounding", config.rounding);
+    log_dump(c, "updated_at", config.updated_at);
+    log_dump(c, "emergency_stop", config.emergency_stop);
+    log_dump(
+        c,
+        "inactivity_pause_seconds",
+        config.inactivity_pause_seconds,
+    );
+    log_dump(c, "min_claim_interval", config.min_claim_interval);
+    log_dump(c, "eligibility_mode", config.eligibility_mode);
+    log_dump(c, "ramp_duration", config.ramp_duration);
+    log_dump(c, "initial_cap", config.initial_cap);
+    log_dump(c, "final_cap", config.final_cap);
+    log_dump(c, "decay_start", config.decay_start);
+    log_dump(c, "decay_end", config.decay_end);
+    log_dump(c, "referral_bps", config.referral_bps);
+    log_dump(
+        c,
+        "withdraw_cooldown_seconds",
+        config.withdraw_cooldown_seconds,
+    );
+    log_dump(c, "require_ata", config.require_ata);
+    log_dump(c, "delegate", dump_option(&config.delegate));
+    log_dump(c, "delegate_budget", config.delegate_budget);
+    log_dump(c, "stake_bps", config.stake_bps);
+    log_dump(c, "stake_lock_seconds", config.stake_lock_seconds);
+    log_dump(c, "error_verbosity", config.error_verbosity);
+    log_dump(c, "vault", dump_option(&config.vault));
+    log_dump(c, "min_reserve", config.min_reserve);
+    log_dump(
+        c,
+        "stats_bump",
+        config
+            .stats_bump
+            .map_or_else(|| "none".to_string(), |bump| bump.to_string()),
+    );
+    log_dump(c, "per_user_cap", config.per_user_cap);
+
+    let s = "state";
+    log_dump(s, "balance", state.balance);
+    log_dump(s, "claim_seq", state.claim_seq);
+    for (index, pool) in state.pools.iter().enumerate() {
+        let label_len = pool
+            .label
+            .iter()
+            .rposition(|byte| *byte != 0)
+            .map_or(0, |last| last + 1);
+        log_dump(
+            s,
+            &format!("pools.{}.label", index),
+            String::from_utf8_lossy(&pool.label[..label_len]),
+        );
+        log_dump(s, &format!("pools.{}.balance", index), pool.balance);
+    }
+    log_dump(s, "total_allocated", state.total_allocated);
+    log_dump(s, "locked_balance", state.locked_balance);
+    log_dump(s, "last_global_claim_ts", state.last_global_claim_ts);
+    log_dump(s, "last_withdraw_ts", state.last_withdraw_ts);
+
+    Ok(())
+}
+
+/// One-shot setup: `Initialize` (config, state and history PDAs), the treasury's associated
+/// token account, the first campaign and, when `create_nonce_page` is set, nonce page 0 for
+/// signed authorizations. Everything is created atomically and paid for by the owner.
+///
+/// Accounts: owner (signer, payer), treasury PDA, config PDA, history PDA, mint, treasury
+/// token account, campaign PDA, system program, token program, associated token program,
+/// then the nonce page PDA when `create_nonce_page` is set.
+#[allow(clippy::too_many_arguments)]
+pub fn process_setup_campaign(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    campaign_id: u64,
+    merkle_root: [u8; 32],
+    total_cap: u64,
+    per_user_cap: u64,
+    starts_at: i64,
+    ends_at: i64,
+    create_nonce_page: bool,
+) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let owner = next_account(account_info_iter, "owner")?; // Owner (must sign, pays rent)
+    let treasury_pda = next_account(account_info_iter, "treasury_pda")?;
+    let config_account = next_account(account_info_iter, "config_account")?;
+    let history_account = next_account(account_info_iter, "history_account")?;
+    let mint = next_account(account_info_iter, "mint")?;
+    let treasury_token_account = next_account(account_info_iter, "treasury_token_account")?;
+    let campaign_account = next_account(account_info_iter, "campaign_account")?;
+    let system_program = next_account(account_info_iter, "system_program")?;
+    let token_program = next_account(account_info_iter, "token_program")?;
+    let associated_token_program = next_account(account_info_iter, "associated_token_program")?;
+
+    process_initialize(
+        program_id,
+        &[
+            owner.clone(),
+            treasury_pda.clone(),
+            system_program.clone(),
+            config_account.clone(),
+            history_account.clone(),
+            owner.clone(),
+            mint.clone(),
+        ],
+    )?;
+
+    process_create_treasury_token_account(
+        program_id,
+        &[
+            owner.clone(),
+            config_account.clone(),
+            treasury_pda.clone(),
+            treasury_token_account.clone(),
+            mint.clone(),
+            system_program.clone(),
+            token_program.clone(),
+            associated_token_program.clone(),
+        ],
+    )?;
 
-// Declare program entrypoint
-entrypoint!(process_instruction);
+    process_create_campaign(
+        program_id,
+        &[
+            owner.clone(),
+            config_account.clone(),
+            campaign_account.clone(),
+            system_program.clone(),
+        ],
+        campaign_id,
+        merkle_root,
+        total_cap,
+        per_user_cap,
+        starts_at,
+        ends_at,
+    )?;
 
-#[derive(BorshDeserialize, BorshSchema, Debug)]
-enum TreasuryInstruction {
-    Initialize,
-    Claim { amount: u64 },
-}
+    if create_nonce_page {
+        let nonce_page_account = next_account(account_info_iter, "nonce_page_account")?;
+        let nonce_page =
+            load_or_create_nonce_page(program_id, owner, nonce_page_account, system_program, 0)?;
+        nonce_page.serialize(&mut &mut nonce_page_account.try_borrow_mut_data()?[..])?;
+    }
 
-#[derive(BorshSerialize, BorshDeserialize, Debug)]
-pub struct TreasuryConfig {
-    pub owner: Pubkey, // Store the original deployer's key
+    msg!(
+        "SETUP_COMPLETE campaign_id={} vault={} nonce_page={}",
+        campaign_id,
+        treasury_token_account.key,
+        create_nonce_page
+    );
+
+    Ok(())
 }
 
-#[derive(BorshSerialize, BorshDeserialize, Debug)]
-pub struct TreasuryState {
-    pub balance: u64, // Store the treasury balance
+/// `Claim` that also pays `referral_bps` of the user's payout to a referrer, from the same
+/// vault. Accounts are the referrer's token account followed by the usual `Claim` accounts.
+/// The referrer may not be the claimer. Payout and bonus are checked together against the
+/// vault, `min_reserve` and the liquid balance; the bonus is recorded in the history and
+/// logged as a `REFERRAL` event carrying the claim's `seq`.
+pub fn process_claim_with_referral(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    amount: u64,
+) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let referrer_token_account = next_account(account_info_iter, "referrer_token_account")?;
+    let claim_accounts = account_info_iter.as_slice();
+    let claim_iter = &mut claim_accounts.iter();
+    let user = next_account(claim_iter, "user")?;
+    let user_token_account = next_account(claim_iter, "user_token_account")?;
+    let treasury_token_account = next_account(claim_iter, "treasury_token_account")?;
+    let token_program = next_account(claim_iter, "token_program")?;
+    let treasury_pda = next_account(claim_iter, "treasury_pda")?;
+    let _owner = next_account(claim_iter, "owner")?;
+    let config_account = next_account(claim_iter, "config_account")?;
+    let history_account = next_account(claim_iter, "history_account")?;
+    let _fee_vault = next_account(claim_iter, "fee_vault")?;
+    let _system_program = next_account(claim_iter, "system_program")?;
+    let mint = next_account(claim_iter, "mint")?;
+
+    let config = load_config(program_id, config_account)?;
+    if config.referral_bps == 0 {
+        msg!("Referral rewards are disabled for this treasury");
+        return Err(ProgramError::InvalidArgument);
+    }
+
+    // ✅ A user cannot refer themselves, whether by token account or by wallet
+    assert_writable(referrer_token_account, "referrer_token_account")?;
+    let referrer_info = assert_token_account(referrer_token_account, None, &config.mint)?;
+    if referrer_info.owner == *user.key || referrer_token_account.key == user_token_account.key {
+        return Err(claim_rejected(
+            config.error_verbosity,
+            TreasuryError::SelfReferral,
+            "self_referral",
+            format!("user={} referrer={}", user.key, referrer_info.owner),
+        ));
+    }
+
+    // ✅ execute_claim checks payout and bonus together against the vault, min_reserve and
+    // the liquid balance before any CPI, and debits both from the tracked balance
+    let outcome = execute_claim(
+        program_id,
+        claim_accounts,
+        amount,
+        ClaimMode::Authority,
+        ClaimOptions {
+            referral_bps: config.referral_bps,
+            ..ClaimOptions::default()
+        },
+    )?;
+    if outcome.bonus == 0 {
+        return Ok(());
+    }
+
+    transfer_from_treasury(
+        &config,
+        token_program,
+        treasury_token_account,
+        mint,
+        referrer_token_account,
+        treasury_pda,
+        outcome.bonus,
+        config.decimals,
+    )?;
+
+    let record = ClaimRecord {
+        user: referrer_info.owner,
+        amount: outcome.bonus,
+        timestamp: Clock::get()?.unix_timestamp,
+    };
+    append_history(&mut history_account.try_borrow_mut_data()?, &record)?;
+
+    // 🔹 Tied to the user's CLAIMED event by its seq
+    msg!(
+        "REFERRAL user={} referrer={} mint={} amount={} seq={}",
+        user.key,
+        referrer_info.owner,
+        config.mint,
+        outcome.bonus,
+        outcome.seq
+    );
+
+    Ok(())
 }
 
-pub fn process_instruction(
+/// `Claim` that pays `stake_bps` of the payout into the user's stake PDA instead of their
+/// token account. Accounts are the stake PDA followed by the usual `Claim` accounts; a missing
+/// stake PDA is created, paid for by the signer in the owner slot. Every stake restarts the
+/// position's `stake_lock_seconds` lock.
+pub fn process_claim_with_stake(
     program_id: &Pubkey,
     accounts: &[AccountInfo],
-    instruction_data: &[u8],
+    amount: u64,
 ) -> ProgramResult {
-    let instruction = TreasuryInstruction::try_from_slice(instruction_data)
-        .map_err(|_| ProgramError::InvalidInstructionData)?;
+    let account_info_iter = &mut accounts.iter();
+    let stake_account = next_account(account_info_iter, "stake_account")?; // Stake PDA for the user
+    let claim_accounts = account_info_iter.as_slice();
+    let claim_iter = &mut claim_accounts.iter();
+    let user = next_account(claim_iter, "user")?;
+    let _user_token_account = next_account(claim_iter, "user_token_account")?;
+    let _treasury_token_account = next_account(claim_iter, "treasury_token_account")?;
+    let _token_program = next_account(claim_iter, "token_program")?;
+    let _treasury_pda = next_account(claim_iter, "treasury_pda")?;
+    let owner = next_account(claim_iter, "owner")?; // Signer, pays for a new stake PDA
+    let config_account = next_account(claim_iter, "config_account")?;
+    let _history_account = next_account(claim_iter, "history_account")?;
+    let _fee_vault = next_account(claim_iter, "fee_vault")?;
+    let system_program = next_account(claim_iter, "system_program")?;
 
-    match instruction {
-        TreasuryInstruction::Initialize => process_initialize(program_id, accounts),
-        TreasuryInstruction::Claim { amount } => process_claim(program_id, accounts, amount),
+    let config = load_config(program_id, config_account)?;
+    if config.stake_bps == 0 {
+        msg!("Staking is disabled for this treasury");
+        return Err(ProgramError::InvalidArgument);
     }
+    assert_writable(stake_account, "stake_account")?;
+
+    // ✅ Validate (or create) the stake PDA before any funds move
+    let creates_position = stake_account.owner != program_id;
+    let mut position = if stake_account.owner == program_id {
+        let position = StakePosition::decode(&stake_account.try_borrow_data()?)?;
+        let expected_stake_pda = Pubkey::create_program_address(
+            &[STAKE_SEED, user.key.as_ref(), &[position.bump]],
+            program_id,
+        )
+        .map_err(|_| ProgramError::InvalidSeeds)?;
+        if expected_stake_pda != *stake_account.key || position.user != *user.key {
+            return Err(ProgramError::InvalidSeeds);
+        }
+        position
+    } else {
+        let (expected_stake_pda, bump) =
+            Pubkey::find_program_address(&[STAKE_SEED, user.key.as_ref()], program_id);
+        if expected_stake_pda != *stake_account.key {
+            return Err(ProgramError::InvalidSeeds);
+        }
+        create_pda_account(
+            owner,
+            stake_account,
+            system_program,
+            program_id,
+            8 + std::mem::size_of::<StakePosition>(),
+            &[STAKE_SEED, user.key.as_ref(), &[bump]],
+        )?;
+        StakePosition {
+            user: *user.key,
+            amount: 0,
+            unlock_at: 0,
+            bump,
+        }
+    };
+
+    let ClaimOutcome { payout, staked, .. } = execute_claim(
+        program_id,
+        claim_accounts,
+        amount,
+        ClaimMode::Authority,
+        ClaimOptions {
+            stake_bps: config.stake_bps,
+            new_accounts: creates_position as u8,
+            ..ClaimOptions::default()
+        },
+    )?;
+
+    let now = Clock::get()?.unix_timestamp;
+    position.amount = position
+        .amount
+        .checked_add(staked)
+        .ok_or(ProgramError::ArithmeticOverflow)?;
+    position.unlock_at = now.saturating_add(config.stake_lock_seconds);
+    position.serialize(&mut &mut stake_account.try_borrow_mut_data()?[..])?;
+
+    msg!(
+        "STAKED user={} payout={} staked={} position={} unlock_at={}",
+        user.key,
+        payout,
+        staked,
+        position.amount,
+        position.unlock_at
+    );
+
+    Ok(())
 }
 
-pub fn process_initialize(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+/// Pay out the user's whole stake position once `unlock_at` has passed, releasing it from
+/// `locked_balance`. The emptied stake PDA is kept for the next `ClaimWithStake`.
+pub fn process_unstake(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
     let account_info_iter = &mut accounts.iter();
-    let payer = next_account_info(account_info_iter)?; // Deployer
-    let treasury_pda = next_account_info(account_info_iter)?; // Treasury PDA
-    let system_program = next_account_info(account_info_iter)?;
-    let config_account = next_account_info(account_info_iter)?; // Config PDA storing deployer key
+    let user = next_account(account_info_iter, "user")?; // Staker (must sign)
+    let user_token_account = next_account(account_info_iter, "user_token_account")?; // Receives the stake
+    let stake_account = next_account(account_info_iter, "stake_account")?; // Stake PDA for the user
+    let config_account = next_account(account_info_iter, "config_account")?; // Config PDA
+    let treasury_pda = next_account(account_info_iter, "treasury_pda")?;
+    let treasury_token_account = next_account(account_info_iter, "treasury_token_account")?;
+    let mint = next_account(account_info_iter, "mint")?;
+    let token_program = next_account(account_info_iter, "token_program")?;
 
-    // ✅ Validate Treasury PDA
-    let (expected_treasury_pda, treasury_bump) =
-        Pubkey::find_program_address(&[b"treasury"], program_id);
-    if expected_treasury_pda != *treasury_pda.key {
-        return Err(ProgramError::InvalidSeeds);
+    if !user.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    for (account, name) in [
+        (user_token_account, "user_token_account"),
+        (stake_account, "stake_account"),
+        (treasury_pda, "treasury_pda"),
+        (treasury_token_account, "treasury_token_account"),
+    ] {
+        assert_writable(account, name)?;
     }
 
-    // ✅ Validate Config PDA
-    let (expected_config_pda, config_bump) = Pubkey::find_program_address(&[b"config"], program_id);
-    if expected_config_pda != *config_account.key {
-        return Err(ProgramError::InvalidSeeds);
+    let config = load_config(program_id, config_account)?;
+    assert_treasury_pda(program_id, &config, treasury_pda)?;
+    if config.claims_paused {
+        return Err(TreasuryError::ClaimsPaused.into());
+    }
+    if config.mint != *mint.key {
+        return Err(ProgramError::InvalidAccountData);
     }
 
-    let rent = Rent::get()?;
+    if stake_account.owner != program_id {
+        return Err(ProgramError::UninitializedAccount);
+    }
+    let mut position = StakePosition::decode(&stake_account.try_borrow_data()?)?;
+    let expected_stake_pda = Pubkey::create_program_address(
+        &[STAKE_SEED, user.key.as_ref(), &[position.bump]],
+        program_id,
+    )
+    .map_err(|_| ProgramError::InvalidSeeds)?;
+    if expected_stake_pda != *stake_account.key || position.user != *user.key {
+        return Err(ProgramError::InvalidSeeds);
+    }
 
-    // 🔹 Check if Config PDA already initialized
-    if config_account.lamports() > 0 {
-        let config_data = config_account.try_borrow_data()?;
-        if config_data.len() >= std::mem::size_of::<TreasuryConfig>() {
-            let stored_config = TreasuryConfig::try_from_slice(&config_data)
-                .map_err(|_| ProgramError::InvalidAccountData)?;
+    let now = Clock::get()?.unix_timestamp;
+    if position.amount == 0 || now < position.unlock_at {
+        msg!(
+            "Stake of {} unlocks at {}, now {}",
+            position.amount,
+            position.unlock_at,
+            now
+        );
+        return Err(TreasuryError::StakeLocked.into());
+    }
 
-            return Err(ProgramError::AccountAlreadyInitialized);
-        }
+    let treasury_token_account_info =
+        assert_token_account(treasury_token_account, Some(treasury_pda.key), &config.mint)?;
+    assert_not_frozen(&treasury_token_account_info, treasury_token_account)?;
+    if treasury_token_account_info.amount < position.amount {
+        return Err(TreasuryError::InsufficientTreasuryBalance.into());
     }
+    assert_token_account(user_token_account, Some(user.key), &config.mint)?;
 
-    // 🔹 Treasury PDA Initialization
-    let treasury_space = 8 + std::mem::size_of::<TreasuryState>(); // Correct struct
-    let create_treasury_ix = system_instruction::create_account(
-        payer.key,
-        treasury_pda.key,
-        rent.minimum_balance(treasury_space),
-        treasury_space as u64,
-        program_id,
-    );
+    let mut state = TreasuryState::decode(&treasury_pda.try_borrow_data()?)?;
+    state.pay_out_locked(position.amount)?;
 
-    invoke_signed(
-        &create_treasury_ix,
-        &[payer.clone(), treasury_pda.clone(), system_program.clone()],
-        &[&[b"treasury", &[treasury_bump]]], // Treasury PDA Seed
+    transfer_from_treasury(
+        &config,
+        token_program,
+        treasury_token_account,
+        mint,
+        user_token_account,
+        treasury_pda,
+        position.amount,
+        config.decimals,
     )?;
 
-    // 🔹 Config PDA Initialization
-    let config_space = 8 + std::mem::size_of::<TreasuryConfig>();
-    let create_config_ix = system_instruction::create_account(
-        payer.key,
-        config_account.key,
-        rent.minimum_balance(config_space),
-        config_space as u64,
-        program_id,
-    );
+    state.serialize(&mut &mut treasury_pda.try_borrow_mut_data()?[..])?;
 
-    invoke_signed(
-        &create_config_ix,
-        &[
-            payer.clone(),
-            config_account.clone(),
-            system_program.clone(),
-        ],
-        &[&[b"config", &[config_bump]]], // Config PDA Seed
-    )?;
+    msg!("UNSTAKED user={} amount={}", user.key, position.amount);
 
-    // ✅ Store the deployer's public key in Config PDA
-    let mut config_data = config_account.try_borrow_mut_data()?;
-    let config = TreasuryConfig {
-        owner: *payer.key, // Store deployer’s key
-    };
-    config.serialize(&mut &mut config_data[..])?;
+    position.amount = 0;
+    position.serialize(&mut &mut stake_account.try_borrow_mut_data()?[..])?;
 
     Ok(())
 }
 
-pub fn process_claim(program_id: &Pubkey, accounts: &[AccountInfo], amount: u64) -> ProgramResult {
+/// `Claim` carrying a short audit `reference` (1-16 printable ASCII characters, zero-padded),
+/// logged as `reference=` on the `CLAIMED` event. Takes the same accounts as `Claim`.
+pub fn process_claim_with_reference(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    amount: u64,
+    reference: [u8; 16],
+) -> ProgramResult {
+    execute_claim(
+        program_id,
+        accounts,
+        amount,
+        ClaimMode::Authority,
+        ClaimOptions {
+            reference: Some(reference),
+            ..ClaimOptions::default()
+        },
+    )
+    .map(|_| ())
+}
+
+/// Create a voucher PDA for the code hashing to `code_hash`, worth `amount`, and lock that
+/// amount so it cannot be withdrawn or allocated elsewhere before redemption. The owner pays
+/// the rent and gets it back when the voucher is redeemed.
+pub fn process_create_voucher(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    code_hash: [u8; 32],
+    amount: u64,
+) -> ProgramResult {
     let account_info_iter = &mut accounts.iter();
+    let owner = next_account(account_info_iter, "owner")?; // Owner account (must sign, pays rent)
+    let config_account = next_account(account_info_iter, "config_account")?; // Config PDA
+    let treasury_pda = next_account(account_info_iter, "treasury_pda")?; // Tracks locked_balance
+    let voucher_account = next_account(account_info_iter, "voucher_account")?; // Voucher PDA to create
+    let system_program = next_account(account_info_iter, "system_program")?;
 
-    let user = next_account_info(account_info_iter)?;
-    let user_token_account = next_account_info(account_info_iter)?;
-    let treasury_token_account = next_account_info(account_info_iter)?;
-    let token_program = next_account_info(account_info_iter)?;
-    let treasury_pda = next_account_info(account_info_iter)?;
-    let owner = next_account_info(account_info_iter)?; // Owner account (must sign)
-    let config_account = next_account_info(account_info_iter)?; // Config PDA storing deployer
+    let config = load_config(program_id, config_account)?;
+    assert_owner(&config, owner)?;
+    assert_treasury_pda(program_id, &config, treasury_pda)?;
+    assert_writable(treasury_pda, "treasury_pda")?;
+    assert_writable(voucher_account, "voucher")?;
 
-    // ✅ Verify treasury PDA
-    let (expected_treasury_pda, bump_seed) =
-        Pubkey::find_program_address(&[b"treasury"], program_id);
-    if expected_treasury_pda != *treasury_pda.key {
+    if amount == 0 {
+        return Err(ProgramError::InvalidArgument);
+    }
+
+    let (expected_voucher_pda, bump) =
+        Pubkey::find_program_address(&[VOUCHER_SEED, &code_hash], program_id);
+    if expected_voucher_pda != *voucher_account.key {
         return Err(ProgramError::InvalidSeeds);
     }
-    // 🔹 Check if Config PDA is already initialized
-    if config_account.lamports() == 0 {
-        return Err(ProgramError::UninitializedAccount);
+    if voucher_account.owner == program_id {
+        return Err(ProgramError::AccountAlreadyInitialized);
     }
 
-    let config_data = config_account.try_borrow_data()?;
+    let mut state = TreasuryState::decode(&treasury_pda.try_borrow_data()?)?;
+    state.lock(amount)?;
+    state.serialize(&mut &mut treasury_pda.try_borrow_mut_data()?[..])?;
+
+    create_pda_account(
+        owner,
+        voucher_account,
+        system_program,
+        program_id,
+        8 + std::mem::size_of::<Voucher>(),
+        &[VOUCHER_SEED, &code_hash, &[bump]],
+    )?;
+
+    let voucher = Voucher {
+        code_hash,
+        amount,
+        creator: *owner.key,
+        bump,
+    };
+    voucher.serialize(&mut &mut voucher_account.try_borrow_mut_data()?[..])?;
+
+    msg!(
+        "VOUCHER_CREATED voucher={} amount={}",
+        voucher_account.key,
+        amount
+    );
+
+    Ok(())
+}
+
+/// Redeem the voucher for `code`: pay its amount to the redeemer's token account, release the
+/// lock and close the voucher, returning its rent to the creator. A redeemed voucher no longer
+/// exists, so a second redemption fails. The code is visible once submitted, so it should be
+/// redeemed by whoever received it rather than shared further. Once claim stats exist, the
+/// stats accounts follow and the redeemer pays for their claimant marker.
+pub fn process_redeem_voucher(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    code: String,
+) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let redeemer = next_account(account_info_iter, "redeemer")?; // Redeemer (must sign)
+    let redeemer_token_account = next_account(account_info_iter, "redeemer_token_account")?; // Receives the tokens
+    let voucher_account = next_account(account_info_iter, "voucher_account")?; // Voucher PDA
+    let creator = next_account(account_info_iter, "creator")?; // Receives the voucher rent
+    let config_account = next_account(account_info_iter, "config_account")?; // Config PDA
+    let treasury_pda = next_account(account_info_iter, "treasury_pda")?;
+    let treasury_token_account = next_account(account_info_iter, "treasury_token_account")?;
+    let mint = next_account(account_info_iter, "mint")?;
+    let token_program = next_account(account_info_iter, "token_program")?;
+
+    if !redeemer.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    for (account, name) in [
+        (redeemer_token_account, "redeemer_token_account"),
+        (voucher_account, "voucher"),
+        (creator, "creator"),
+        (treasury_pda, "treasury_pda"),
+        (treasury_token_account, "treasury_token_account"),
+    ] {
+        assert_writable(account, name)?;
+    }
+    if code.is_empty() || code.len() > MAX_VOUCHER_CODE_LEN {
+        return Err(ProgramError::InvalidInstructionData);
+    }
 
-    // 🔹 Ensure enough space is available
-    if config_data.len() < std::mem::size_of::<TreasuryConfig>() {
+    let config = load_config(program_id, config_account)?;
+    assert_treasury_pda(program_id, &config, treasury_pda)?;
+    if config.claims_paused {
+        return Err(TreasuryError::ClaimsPaused.into());
+    }
+    if config.mint != *mint.key {
         return Err(ProgramError::InvalidAccountData);
     }
 
-    // 🔹 Try parsing the config data safely
-    // ✅ Ensure Config PDA is initialized
-    if config_account.lamports() == 0 {
+    // ✅ The voucher must exist (it is closed once redeemed) and match the code
+    let code_hash = voucher_code_hash(&code);
+    if voucher_account.owner != program_id {
+        msg!("No unredeemed voucher at {}", voucher_account.key);
         return Err(ProgramError::UninitializedAccount);
     }
+    let voucher = Voucher::decode(&voucher_account.try_borrow_data()?)?;
+    let expected_voucher_pda =
+        Pubkey::create_program_address(&[VOUCHER_SEED, &code_hash, &[voucher.bump]], program_id)
+            .map_err(|_| ProgramError::InvalidSeeds)?;
+    if expected_voucher_pda != *voucher_account.key || voucher.code_hash != code_hash {
+        return Err(ProgramError::InvalidSeeds);
+    }
+    if voucher.creator != *creator.key {
+        return Err(ProgramError::InvalidArgument);
+    }
+
+    let treasury_token_account_info =
+        assert_token_account(treasury_token_account, Some(treasury_pda.key), &config.mint)?;
+    assert_not_frozen(&treasury_token_account_info, treasury_token_account)?;
+    if treasury_token_account_info.amount < voucher.amount {
+        return Err(TreasuryError::InsufficientTreasuryBalance.into());
+    }
+    assert_token_account(redeemer_token_account, None, &config.mint)?;
 
-    // Get raw data
-    let config_data = config_account.try_borrow_data()?;
+    // ✅ Once stats exist every payout must pass them, so the totals never miss one
+    let stats_accounts = load_stats_accounts(program_id, &config, account_info_iter.as_slice(), 1)?;
+    let new_marker = match &stats_accounts {
+        Some(stats_accounts) => {
+            check_claimant_marker(program_id, &stats_accounts.markers[0], redeemer.key)?
+        }
+        None => None,
+    };
 
-    // 🔹 Debug: Print raw stored data length
+    let now = Clock::get()?.unix_timestamp;
+    let mut state = TreasuryState::decode(&treasury_pda.try_borrow_data()?)?;
+    state.note_claim_time(now, config.min_claim_interval)?;
+    state.pay_out_locked(voucher.amount)?;
+    let seq = state.next_claim_seq()?;
 
-    // ✅ Check if stored data matches expected struct size
-    if config_data.len() < std::mem::size_of::<TreasuryConfig>() {
-        return Err(ProgramError::InvalidAccountData);
+    transfer_from_treasury(
+        &config,
+        token_program,
+        treasury_token_account,
+        mint,
+        redeemer_token_account,
+        treasury_pda,
+        voucher.amount,
+        config.decimals,
+    )?;
+
+    state.serialize(&mut &mut treasury_pda.try_borrow_mut_data()?[..])?;
+
+    close_account(voucher_account, creator)?;
+
+    if let Some(stats_accounts) = &stats_accounts {
+        record_stats(
+            program_id,
+            redeemer,
+            stats_accounts,
+            0,
+            redeemer.key,
+            new_marker,
+            voucher.amount,
+            now,
+        )?;
     }
 
-    // 🔹 Try parsing manually before using Borsh deserialization
-    let owner_bytes: &[u8; 32] = config_data[..32]
-        .try_into()
-        .map_err(|_| ProgramError::InvalidAccountData)?;
-    let stored_owner = Pubkey::new_from_array(*owner_bytes);
+    emit_claimed(redeemer.key, &config.mint, voucher.amount, seq, None);
 
-    // ✅ Ensure only the original deployer can execute
-    if stored_owner != *owner.key {
-        return Err(ProgramError::IllegalOwner);
+    Ok(())
+}
+
+/// Run the privileged instruction in `instruction` after checking the client that built it is
+/// at least `MIN_CLIENT_VERSION`. Accounts are those of the wrapped instruction. Carrying a
+/// version is optional: the same instructions still work unwrapped.
+pub fn process_versioned(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    client_version: u32,
+    instruction: &[u8],
+) -> ProgramResult {
+    if client_version < MIN_CLIENT_VERSION {
+        msg!(
+            "Client version {} predates the account layout this program uses (minimum {}); update the treasury client before retrying",
+            client_version,
+            MIN_CLIENT_VERSION
+        );
+        return Err(TreasuryError::ClientOutdated.into());
     }
 
-    // ✅ Ensure owner is a signer
-    if !owner.is_signer {
-        return Err(ProgramError::MissingRequiredSignature);
+    // ✅ Only privileged instructions are wrapped, which also rules out nesting
+    let inner = TreasuryInstruction::deserialize(&mut &instruction[..])
+        .map_err(|_| ProgramError::InvalidInstructionData)?;
+    if !inner.is_privileged() {
+        msg!("Versioned only wraps privileged instructions");
+        return Err(ProgramError::InvalidInstructionData);
     }
 
-    // ✅ Verify treasury token account's owner is treasury PDA
-    let treasury_token_account_data = treasury_token_account.try_borrow_data()?;
-    let treasury_token_account_info = TokenAccount::unpack(&treasury_token_account_data)
-        .map_err(|_| ProgramError::InvalidAccountData)?;
+    process_instruction(program_id, accounts, instruction)
+}
 
-    if treasury_token_account_info.owner != *treasury_pda.key {
-        return Err(ProgramError::IllegalOwner);
-    }
+/// Create the claim-stats PDA and record its bump in the config. From then on every payout
+/// path must pass the stats accounts after its own: the stats PDA, the system program and one
+/// claimant marker per user paid. The owner pays the rent.
+pub fn process_initialize_stats(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let owner = next_account(account_info_iter, "owner")?; // Owner account (must sign, pays rent)
+    let config_account = next_account(account_info_iter, "config_account")?; // Config PDA
+    let stats_account = next_account(account_info_iter, "stats_account")?; // Stats PDA to create
+    let system_program = next_account(account_info_iter, "system_program")?;
 
-    drop(treasury_token_account_data); // ✅ Drop before using treasury_token_account again
+    let mut config = load_config_mut(program_id, config_account)?;
+    assert_owner(&config, owner)?;
+    assert_writable(stats_account, "stats")?;
 
-    // ✅ Transfer tokens from treasury PDA to user
-    let transfer_ix = token_instruction::transfer(
-        token_program.key,
-        treasury_token_account.key,
-        user_token_account.key,
-        treasury_pda.key,
-        &[],
-        amount,
-    )?;
+    let (expected_stats_pda, bump) = Pubkey::find_program_address(&[STATS_SEED], program_id);
+    if expected_stats_pda != *stats_account.key {
+        return Err(ProgramError::InvalidSeeds);
+    }
+    if config.stats_bump.is_some() || stats_account.owner == program_id {
+        return Err(ProgramError::AccountAlreadyInitialized);
+    }
 
-    invoke_signed(
-        &transfer_ix,
-        &[
-            treasury_token_account.clone(),
-            user_token_account.clone(),
-            treasury_pda.clone(),
-            token_program.clone(),
-        ],
-        &[&[b"treasury", &[bump_seed]]],
+    create_pda_account(
+        owner,
+        stats_account,
+        system_program,
+        program_id,
+        8 + std::mem::size_of::<ClaimStats>(),
+        &[STATS_SEED, &[bump]],
     )?;
+    let stats = ClaimStats {
+        bump,
+        ..ClaimStats::default()
+    };
+    stats.serialize(&mut &mut stats_account.try_borrow_mut_data()?[..])?;
+
+    config.stats_bump = Some(bump);
+    store_config(&mut config, config_account)?;
+
+    msg!("STATS_INITIALIZED stats={}", stats_account.key);
+
+    Ok(())
+}
+
+/// Read-only dry run of `AirdropBatchCapped`, meant for `simulateTransaction`. Remaining
+/// accounts are the same `(token account, receipt, user claim)` triples. Each recipient is
+/// capped at their unclaimed allocation exactly as the batch would cap them, counting earlier
+/// lines of the same batch, and logged as a `SIMULATED` line with `status=paid`, `skipped`
+/// (over cap with `skip_over_cap`) or `aborts` (over cap without it, the vault runs dry, or
+/// the tracked balance or locked balance cannot cover the line once earlier lines are paid).
+/// `received` is what the receipt shows already paid this campaign. The closing
+/// `SIMULATED_TOTAL` line says whether the real batch would fail.
+pub fn process_simulate_batch_capped(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    amounts: Vec<u64>,
+    skip_over_cap: bool,
+) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let config_account = next_account(account_info_iter, "config_account")?; // Config PDA
+    let treasury_pda = next_account(account_info_iter, "treasury_pda")?;
+    let treasury_token_account = next_account(account_info_iter, "treasury_token_account")?;
+    let recipients = account_info_iter.as_slice(); // (token account, receipt, user claim) per amount
+
+    if amounts.is_empty() || recipients.len() != amounts.len() * 3 {
+        return Err(ProgramError::NotEnoughAccountKeys);
+    }
+
+    let config = load_config(program_id, config_account)?;
+    assert_treasury_pda(program_id, &config, treasury_pda)?;
+
+    let treasury_token_account_info =
+        assert_token_account(treasury_token_account, Some(treasury_pda.key), &config.mint)?;
+
+    let mut state = TreasuryState::decode(&treasury_pda.try_borrow_data()?)?;
+    let mut remaining = treasury_token_account_info.amount;
+    let mut total: u64 = 0;
+    let mut succeeded = 0usize;
+    let mut aborts = false;
+    // 🔹 The real batch rewrites each user claim as it goes, so repeats see a smaller cap
+    let mut paid_so_far: Vec<(Pubkey, u64)> = Vec::new();
+
+    for (index, (triple, amount)) in recipients.chunks_exact(3).zip(amounts.iter()).enumerate() {
+        let recipient_info = assert_token_account(&triple[0], None, &config.mint)?;
+        let user = recipient_info.owner;
+
+        // ✅ A missing user claim is created empty by the batch, so its cap is zero
+        let unclaimed = if triple[2].owner == program_id {
+            let user_claim = load_user_claim(program_id, &triple[2], &user)?;
+            user_claim.total.saturating_sub(user_claim.claimed)
+        } else {
+            0
+        };
+        let earlier = paid_so_far
+            .iter()
+            .find(|(key, _)| *key == user)
+            .map_or(0, |(_, paid)| *paid);
+        let cap = unclaimed.saturating_sub(earlier);
+        let received = if triple[1].owner == program_id {
+            ClaimReceipt::decode(&triple[1].try_borrow_data()?)?.total_claimed
+        } else {
+            0
+        };
+
+        // ✅ The real batch pays each line out of the recipient's lock, which can abort too
+        let mut after = state.clone();
+        after.total_allocated = after.total_allocated.saturating_sub(*amount);
+        let state_covers = after.pay_out_locked(*amount).is_ok();
+
+        let (sent, status) = if *amount > cap {
+            if skip_over_cap {
+                (0, "skipped")
+            } else {
+                aborts = true;
+                (0, "aborts")
+            }
+        } else if *amount > remaining || !state_covers {
+            aborts = true;
+            (0, "aborts")
+        } else {
+            (*amount, "paid")
+        };
+        let covered = *amount <= remaining;
+
+        if status == "paid" {
+            state = after;
+            remaining -= sent;
+            total = total
+                .checked_add(sent)
+                .ok_or(ProgramError::ArithmeticOverflow)?;
+            succeeded += 1;
+            match paid_so_far.iter_mut().find(|(key, _)| *key == user) {
+                Some((_, paid)) => *paid = paid.saturating_add(sent),
+                None => paid_so_far.push((user, sent)),
+            }
+        }
+
+        msg!(
+            "SIMULATED index={} recipient={} amount={} covered={} cap={} received={} status={}",
+            index,
+            user,
+            sent,
+            covered,
+            cap,
+            received,
+            status
+        );
+    }
+
+    msg!(
+        "SIMULATED_TOTAL total={} vault={} paused={} fails={}",
+        total,
+        treasury_token_account_info.amount,
+        config.claims_paused,
+        aborts || succeeded == 0
+    );
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn state(balance: u64, locked_balance: u64) -> TreasuryState {
+        TreasuryState {
+            balance,
+            claim_seq: 0,
+            pools: [SubPool::default(); MAX_POOLS],
+            total_allocated: 0,
+            locked_balance,
+            last_global_claim_ts: 0,
+            last_withdraw_ts: 0,
+        }
+    }
+
+    fn user_claim(total: u64, claimed: u64, start: i64, duration: i64) -> UserClaim {
+        UserClaim {
+            user: Pubkey::new_unique(),
+            total,
+            claimed,
+            vesting_start: start,
+            vesting_duration: duration,
+            bump: 255,
+            claim_count: 0,
+        }
+    }
+
+    #[test]
+    fn lock_and_unlock_move_funds_between_liquid_and_locked() {
+        let mut state = state(100, 0);
+        state.lock(60).unwrap();
+        assert_eq!((state.locked_balance, state.liquid_balance()), (60, 40));
+        assert_eq!(
+            state.lock(41),
+            Err(TreasuryError::InsufficientLiquidBalance.into())
+        );
+
+        state.unlock(10).unwrap();
+        assert_eq!((state.locked_balance, state.liquid_balance()), (50, 50));
+        assert_eq!(state.unlock(51), Err(ProgramError::InvalidArgument));
+        assert_eq!(state.balance, 100);
+    }
+
+    #[test]
+    fn pay_out_never_touches_locked_funds() {
+        let mut state = state(100, 70);
+        assert_eq!(
+            state.pay_out(31),
+            Err(TreasuryError::InsufficientLiquidBalance.into())
+        );
+        state.pay_out(30).unwrap();
+        assert_eq!((state.balance, state.locked_balance), (70, 70));
+    }
+
+    #[test]
+    fn pay_out_locked_spends_only_the_callers_lock() {
+        let mut state = state(100, 70);
+        state.pay_out_locked(50).unwrap();
+        assert_eq!((state.balance, state.locked_balance), (50, 20));
+        assert_eq!(state.liquid_balance(), 30);
+        assert_eq!(state.pay_out_locked(21), Err(ProgramError::InvalidArgument));
+    }
+
+    #[test]
+    fn reallocate_locks_increases_and_releases_decreases() {
+        let mut state = state(100, 0);
+        state.reallocate(0, 60, 0).unwrap();
+        assert_eq!((state.total_allocated, state.locked_balance), (60, 60));
+
+        state.reallocate(60, 20, 0).unwrap();
+        assert_eq!((state.total_allocated, state.locked_balance), (20, 20));
+
+        assert_eq!(
+            state.reallocate(20, 110, 0),
+            Err(TreasuryError::OverAllocated.into())
+        );
+        assert_eq!(
+            state.reallocate(20, 50, 40),
+            Err(TreasuryError::OverAllocated.into())
+        );
+        assert_eq!((state.total_allocated, state.locked_balance), (20, 20));
+    }
+
+    #[test]
+    fn note_claim_time_enforces_the_minimum_interval() {
+        let mut state = state(0, 0);
+        state.note_claim_time(1_000, 60).unwrap();
+        assert_eq!(
+            state.note_claim_time(1_059, 60),
+            Err(TreasuryError::ClaimRateLimited.into())
+        );
+        assert_eq!(state.last_global_claim_ts, 1_000);
+        state.note_claim_time(1_060, 60).unwrap();
+        state.note_claim_time(1_061, 0).unwrap();
+        assert_eq!(state.last_global_claim_ts, 1_061);
+    }
+
+    #[test]
+    fn apply_bps_rounded_honours_each_mode() {
+        assert_eq!(apply_bps_rounded(15, 5_000, ROUNDING_FLOOR), Ok(7));
+        assert_eq!(apply_bps_rounded(15, 5_000, ROUNDING_CEIL), Ok(8));
+        assert_eq!(apply_bps_rounded(15, 5_000, ROUNDING_NEAREST), Ok(8));
+        assert_eq!(apply_bps_rounded(13, 5_000, ROUNDING_NEAREST), Ok(7));
+        assert_eq!(apply_bps_rounded(14, 5_000, ROUNDING_CEIL), Ok(7));
+        assert_eq!(
+            apply_bps_rounded(1, 1, 3),
+            Err(ProgramError::InvalidArgument)
+        );
+        assert_eq!(
+            apply_bps_rounded(u64::MAX, 20_000, ROUNDING_FLOOR),
+            Err(ProgramError::ArithmeticOverflow)
+        );
+        assert_eq!(apply_bps(u64::MAX, 10_000), Ok(u64::MAX));
+    }
+
+    #[test]
+    fn claimable_follows_the_users_linear_schedule() {
+        let claim = user_claim(1_000, 0, 100, 1_000);
+        assert_eq!(claim.claimable(100), 0);
+        assert_eq!(claim.claimable(350), 250);
+        assert_eq!(claim.claimable(1_100), 1_000);
+        assert_eq!(claim.claimable(i64::MAX), 1_000);
+
+        let claim = user_claim(1_000, 400, 100, 1_000);
+        assert_eq!(claim.claimable(350), 0);
+        assert_eq!(claim.claimable(600), 100);
+
+        // ✅ A zero duration is a cliff at `start`
+        let cliff = user_claim(500, 0, 100, 0);
+        assert_eq!((cliff.claimable(100), cliff.claimable(101)), (0, 500));
+    }
+
+    /// Ed25519 program data for one signature with every offset inside the instruction.
+    fn ed25519_data(signer: &Pubkey, message: &[u8], ix_index: u16) -> Vec<u8> {
+        let public_key_offset = 2 + ED25519_OFFSETS_LEN as u16;
+        let signature_offset = public_key_offset + 32;
+        let message_offset = signature_offset + 64;
+        let mut data = vec![1, 0];
+        for field in [
+            signature_offset,
+            ix_index,
+            public_key_offset,
+            ix_index,
+            message_offset,
+            message.len() as u16,
+            ix_index,
+        ] {
+            data.extend_from_slice(&field.to_le_bytes());
+        }
+        data.extend_from_slice(signer.as_ref());
+        data.extend_from_slice(&[0u8; 64]);
+        data.extend_from_slice(message);
+        data
+    }
+
+    #[test]
+    fn ed25519_offsets_must_point_at_the_signer_and_message() {
+        let signer = Pubkey::new_unique();
+        let message = b"claim terms";
+        let data = ed25519_data(&signer, message, u16::MAX);
+        assert_eq!(check_ed25519_offsets(&data, &signer, message), Ok(()));
+
+        let rejected = Err(TreasuryError::InvalidAuthorization.into());
+        let other = Pubkey::new_unique();
+        assert_eq!(check_ed25519_offsets(&data, &other, message), rejected);
+        assert_eq!(
+            check_ed25519_offsets(&data, &signer, b"other terms"),
+            rejected
+        );
+        assert_eq!(
+            check_ed25519_offsets(&data[..15], &signer, message),
+            rejected
+        );
+
+        // 🔹 Offsets into another instruction could read attacker-supplied bytes
+        let elsewhere = ed25519_data(&signer, message, 0);
+        assert_eq!(
+            check_ed25519_offsets(&elsewhere, &signer, message),
+            rejected
+        );
+
+        let mut two_signatures = data.clone();
+        two_signatures[0] = 2;
+        assert_eq!(
+            check_ed25519_offsets(&two_signatures, &signer, message),
+            rejected
+        );
+
+        let mut truncated = data.clone();
+        truncated.truncate(data.len() - 1);
+        assert_eq!(
+            check_ed25519_offsets(&truncated, &signer, message),
+            rejected
+        );
+    }
+}
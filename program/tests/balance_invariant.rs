@@ -0,0 +1,104 @@
+mod common;
+
+use common::{default_params, Harness, GENESIS_TIMESTAMP};
+use solana_program_test::tokio;
+use solana_sdk::{pubkey::Pubkey, signature::Signer};
+use spl_associated_token_account::get_associated_token_address;
+use treasury::ConfigParams;
+
+#[tokio::test]
+async fn balance_tracks_the_vault_through_deposit_claim_refund_and_sweep() {
+    let mut harness = Harness::start().await;
+    harness.assert_balance_matches_vault("initialize").await;
+
+    harness.deposit(10_000).await;
+    harness.assert_balance_matches_vault("deposit").await;
+
+    let user = Pubkey::new_unique();
+    let destination = harness.create_token_account(&user).await;
+    harness.claim(&user, &destination, 1_500).await.unwrap();
+    harness.assert_balance_matches_vault("claim").await;
+
+    // A late claim withholds its fee in the vault, and the books keep it too
+    harness
+        .set_config(ConfigParams {
+            claim_deadline: GENESIS_TIMESTAMP + 100,
+            grace_period_seconds: 1_000,
+            late_fee_bps: 1_000,
+            ..default_params()
+        })
+        .await;
+    harness.warp_to(GENESIS_TIMESTAMP + 200).await;
+    harness.claim(&user, &destination, 1_000).await.unwrap();
+    harness.assert_balance_matches_vault("late claim").await;
+    assert_eq!(harness.token_balance(&destination).await, 2_400);
+
+    harness.deposit(2_000).await;
+    harness.assert_balance_matches_vault("second deposit").await;
+
+    // Refund part of the funds to the depositor and a second account
+    let owner = harness.owner();
+    let depositor_account = get_associated_token_address(&owner, &harness.mint);
+    let other_account = harness.create_token_account(&Pubkey::new_unique()).await;
+    harness
+        .withdraw_split(&[(depositor_account, 3_000), (other_account, 500)])
+        .await
+        .unwrap();
+    harness.assert_balance_matches_vault("refund").await;
+    assert_eq!(harness.state().await.balance, 6_100);
+
+    // Sweep everything that is left into a new treasury's vault
+    let new_vault = harness.create_token_account(&Pubkey::new_unique()).await;
+    harness.set_paused(true, true).await;
+    harness.migrate_treasury(&new_vault).await.unwrap();
+    harness.assert_balance_matches_vault("sweep").await;
+    assert_eq!(harness.token_balance(&new_vault).await, 6_100);
+}
+
+#[tokio::test]
+async fn balance_tracks_the_vault_while_allocations_are_locked() {
+    let mut harness = Harness::start().await;
+    harness.deposit(5_000).await;
+
+    let user = harness.funded_user().await;
+    let user_account = harness.create_token_account(&user.pubkey()).await;
+    harness
+        .set_vesting(&user.pubkey(), GENESIS_TIMESTAMP, 1_000, 2_000)
+        .await
+        .unwrap();
+    harness.assert_balance_matches_vault("set vesting").await;
+
+    harness.warp_to(GENESIS_TIMESTAMP + 500).await;
+    harness
+        .claim_vested(&user, &user_account, 1_000)
+        .await
+        .unwrap();
+    harness.assert_balance_matches_vault("vested claim").await;
+
+    // Only the liquid part can be refunded; a refund reaching into the lock changes nothing
+    let refund_account = harness.create_token_account(&Pubkey::new_unique()).await;
+    assert!(harness
+        .withdraw_split(&[(refund_account, 3_001)])
+        .await
+        .is_err());
+    harness
+        .assert_balance_matches_vault("rejected refund")
+        .await;
+    harness
+        .withdraw_split(&[(refund_account, 3_000)])
+        .await
+        .unwrap();
+    harness.assert_balance_matches_vault("refund").await;
+
+    harness.warp_to(GENESIS_TIMESTAMP + 1_000).await;
+    harness
+        .claim_vested(&user, &user_account, 1_000)
+        .await
+        .unwrap();
+    harness
+        .assert_balance_matches_vault("final vested claim")
+        .await;
+
+    let state = harness.state().await;
+    assert_eq!((state.balance, state.locked_balance), (0, 0));
+}
@@ -0,0 +1,66 @@
+mod common;
+
+use common::{default_params, treasury_error, Harness, GENESIS_TIMESTAMP};
+use solana_program_test::tokio;
+use solana_sdk::{pubkey::Pubkey, signature::Signer};
+use spl_associated_token_account::get_associated_token_address;
+use treasury::{ConfigParams, TreasuryError};
+
+#[tokio::test]
+async fn claim_to_pays_a_destination_the_user_names() {
+    let mut harness = Harness::start().await;
+    harness.deposit(10_000).await;
+    harness
+        .set_config(ConfigParams {
+            require_ata: true,
+            ..default_params()
+        })
+        .await;
+
+    let user = harness.funded_user().await;
+    harness
+        .set_vesting(&user.pubkey(), GENESIS_TIMESTAMP - 10, 0, 1_000)
+        .await
+        .unwrap();
+
+    // A cold wallet's account, neither owned by the user nor their ATA
+    let cold_wallet = Pubkey::new_unique();
+    let cold_account = harness.create_token_account(&cold_wallet).await;
+
+    // The user's own signature authorizes the claim; the owner never signs
+    harness.claim_to(&user, &cold_account, 400).await.unwrap();
+    assert_eq!(harness.token_balance(&cold_account).await, 400);
+    let default_account = get_associated_token_address(&user.pubkey(), &harness.mint);
+    assert!(harness
+        .context
+        .banks_client
+        .get_account(default_account)
+        .await
+        .unwrap()
+        .is_none());
+
+    let user_claim = harness.user_claim(&user.pubkey()).await;
+    assert_eq!((user_claim.claimed, user_claim.claim_count), (400, 1));
+    let state = harness.state().await;
+    assert_eq!((state.balance, state.locked_balance), (9_600, 600));
+
+    // The schedule still bounds what the user can send anywhere
+    let err = harness
+        .claim_to(&user, &cold_account, 601)
+        .await
+        .unwrap_err();
+    assert_eq!(err.unwrap(), treasury_error(TreasuryError::ExceedsVested));
+}
+
+#[tokio::test]
+async fn claim_to_needs_a_user_claim() {
+    let mut harness = Harness::start().await;
+    harness.deposit(10_000).await;
+
+    // Without an allocation there is nothing the user may claim on their own signature
+    let user = harness.funded_user().await;
+    let destination = harness.create_token_account(&user.pubkey()).await;
+    let err = harness.claim_to(&user, &destination, 1).await.unwrap_err();
+    assert_eq!(err.unwrap(), treasury_error(TreasuryError::NotEligible));
+    assert_eq!(harness.token_balance(&destination).await, 0);
+}
@@ -0,0 +1,81 @@
+mod common;
+
+use common::{treasury_error, Harness};
+use solana_program_test::tokio;
+use solana_sdk::pubkey::Pubkey;
+use treasury::TreasuryError;
+
+#[tokio::test]
+async fn close_zeroes_the_treasury_and_history() {
+    let mut harness = Harness::start().await;
+    let recipient = Pubkey::new_unique();
+    let treasury_pda = harness.treasury_pda;
+    let history = harness.history;
+    let banks_client = &mut harness.context.banks_client;
+    let rent = banks_client
+        .get_account(treasury_pda)
+        .await
+        .unwrap()
+        .unwrap()
+        .lamports
+        + banks_client
+            .get_account(history)
+            .await
+            .unwrap()
+            .unwrap()
+            .lamports;
+
+    harness.close_treasury(&recipient).await.unwrap();
+
+    // Both accounts are wiped and handed back to the system program
+    for address in [treasury_pda, history] {
+        let account = harness
+            .context
+            .banks_client
+            .get_account(address)
+            .await
+            .unwrap();
+        assert!(account
+            .is_none_or(|account| account.lamports == 0 && account.data.iter().all(|b| *b == 0)));
+    }
+    let refunded = harness
+        .context
+        .banks_client
+        .get_balance(recipient)
+        .await
+        .unwrap();
+    assert_eq!(refunded, rent);
+}
+
+#[tokio::test]
+async fn close_refuses_while_the_state_holds_funds() {
+    let mut harness = Harness::start().await;
+    harness.deposit(500).await;
+
+    let err = harness
+        .close_treasury(&Pubkey::new_unique())
+        .await
+        .unwrap_err();
+    assert_eq!(
+        err.unwrap(),
+        treasury_error(TreasuryError::TreasuryStillOpen)
+    );
+    assert_eq!(harness.state().await.balance, 500);
+}
+
+#[tokio::test]
+async fn close_refuses_while_the_vault_holds_untracked_tokens() {
+    let mut harness = Harness::start().await;
+    let vault = harness.vault;
+    harness.mint_to(&vault, 1).await;
+
+    let err = harness
+        .close_treasury(&Pubkey::new_unique())
+        .await
+        .unwrap_err();
+    assert_eq!(
+        err.unwrap(),
+        treasury_error(TreasuryError::TreasuryStillOpen)
+    );
+    assert_eq!(harness.state().await.balance, 0);
+}
@@ -0,0 +1,556 @@
+//! Shared `ProgramTest` harness for the integration tests.
+//!
+//! Every time-based test sets the clock through [`clock_at`] and [`Harness::set_clock`],
+//! so vesting, cooldowns, windows and epochs are all driven by the same fabricated sysvar.
+
+#![allow(dead_code)] // Each test binary uses its own subset of the harness
+
+use borsh::BorshSerialize;
+use solana_program_test::{processor, BanksClientError, ProgramTest, ProgramTestContext};
+use solana_sdk::{
+    clock::Clock,
+    instruction::{AccountMeta, Instruction, InstructionError},
+    program_pack::Pack,
+    pubkey::Pubkey,
+    signature::{Keypair, Signer},
+    system_instruction, system_program,
+    transaction::{Transaction, TransactionError},
+};
+use spl_associated_token_account::{
+    get_associated_token_address, instruction::create_associated_token_account,
+};
+use spl_token::state::{Account as TokenAccount, Mint};
+use treasury::{
+    ConfigParams, TreasuryError, TreasuryState, UserClaim, CONFIG_SEED, DEFAULT_MULTIPLIER_BPS,
+    ERROR_VERBOSITY_FULL, HISTORY_SEED, RECEIPT_SEED, ROUNDING_FLOOR, TREASURY_SEED,
+    USER_CLAIM_SEED,
+};
+
+/// `TreasuryInstruction` discriminants used by the tests; the enum itself is private.
+pub const INITIALIZE: u8 = 0;
+pub const CLAIM: u8 = 1;
+pub const SET_CONFIG: u8 = 3;
+pub const CLOSE_TREASURY: u8 = 9;
+pub const SET_PAUSED: u8 = 12;
+pub const DEPOSIT: u8 = 13;
+pub const SET_VESTING: u8 = 20;
+pub const CLAIM_VESTED: u8 = 21;
+pub const CREATE_TREASURY_TOKEN_ACCOUNT: u8 = 23;
+pub const WITHDRAW_SPLIT: u8 = 26;
+pub const CLAIM_TO: u8 = 28;
+pub const MIGRATE_TREASURY: u8 = 38;
+
+pub const DECIMALS: u8 = 6;
+
+/// Start of every test's timeline, well clear of the genesis clock.
+pub const GENESIS_TIMESTAMP: i64 = 1_700_000_000;
+
+/// A `Clock` sysvar at an arbitrary point in time. The epoch start is the timestamp itself,
+/// which no instruction in this program reads.
+pub fn clock_at(unix_timestamp: i64, epoch: u64, slot: u64) -> Clock {
+    Clock {
+        slot,
+        epoch_start_timestamp: unix_timestamp,
+        epoch,
+        leader_schedule_epoch: epoch,
+        unix_timestamp,
+    }
+}
+
+/// Instruction data for discriminant `tag` followed by its Borsh-encoded fields.
+pub fn instruction_data(tag: u8, fields: impl BorshSerialize) -> Vec<u8> {
+    let mut data = vec![tag];
+    fields.serialize(&mut data).unwrap();
+    data
+}
+
+/// How `error` from the first instruction of a transaction surfaces to the client.
+pub fn treasury_error(error: TreasuryError) -> TransactionError {
+    TransactionError::InstructionError(0, InstructionError::Custom(error as u32))
+}
+
+pub fn user_claim_address(program_id: &Pubkey, user: &Pubkey) -> Pubkey {
+    Pubkey::find_program_address(&[USER_CLAIM_SEED, user.as_ref()], program_id).0
+}
+
+pub fn receipt_address(program_id: &Pubkey, campaign_id: u64, user: &Pubkey) -> Pubkey {
+    Pubkey::find_program_address(
+        &[RECEIPT_SEED, &campaign_id.to_le_bytes(), user.as_ref()],
+        program_id,
+    )
+    .0
+}
+
+/// `SetConfig` parameters matching a freshly initialized config.
+pub fn default_params() -> ConfigParams {
+    ConfigParams {
+        flat_fee_lamports: 0,
+        fee_vault: Pubkey::default(),
+        owner_timelock_seconds: 0,
+        strict_amounts: false,
+        campaign_id: 0,
+        multiplier_bps: DEFAULT_MULTIPLIER_BPS,
+        claim_deadline: 0,
+        grace_period_seconds: 0,
+        late_fee_bps: 0,
+        enforce_treasury_ata: false,
+        gate_mint: None,
+        allocation_cap: 0,
+        max_claims_per_user: 0,
+        cosigner: None,
+        default_mint_fee_bps: 0,
+        rounding: ROUNDING_FLOOR,
+        inactivity_pause_seconds: 0,
+        min_claim_interval: 0,
+        ramp_duration: 0,
+        initial_cap: 0,
+        final_cap: 0,
+        decay_start: 0,
+        decay_end: 0,
+        referral_bps: 0,
+        withdraw_cooldown_seconds: 0,
+        require_ata: false,
+        stake_bps: 0,
+        stake_lock_seconds: 0,
+        error_verbosity: ERROR_VERBOSITY_FULL,
+    }
+}
+
+/// An initialized treasury with a vault, owned by the test payer.
+pub struct Harness {
+    pub context: ProgramTestContext,
+    pub program_id: Pubkey,
+    pub mint: Pubkey,
+    pub mint_authority: Keypair,
+    pub treasury_pda: Pubkey,
+    pub config: Pubkey,
+    pub history: Pubkey,
+    pub vault: Pubkey,
+}
+
+impl Harness {
+    /// Start a bank at [`GENESIS_TIMESTAMP`], create the mint, and run `Initialize` and
+    /// `CreateTreasuryTokenAccount`.
+    pub async fn start() -> Self {
+        let program_id = Pubkey::new_unique();
+        let program_test = ProgramTest::new(
+            "treasury",
+            program_id,
+            processor!(treasury::process_instruction),
+        );
+        let context = program_test.start_with_context().await;
+
+        let mut harness = Harness {
+            context,
+            program_id,
+            mint: Pubkey::default(),
+            mint_authority: Keypair::new(),
+            treasury_pda: Pubkey::find_program_address(&[TREASURY_SEED], &program_id).0,
+            config: Pubkey::find_program_address(&[CONFIG_SEED], &program_id).0,
+            history: Pubkey::find_program_address(&[HISTORY_SEED], &program_id).0,
+            vault: Pubkey::default(),
+        };
+        harness.set_clock(clock_at(GENESIS_TIMESTAMP, 0, 1));
+        harness.create_mint().await;
+        harness.vault = get_associated_token_address(&harness.treasury_pda, &harness.mint);
+
+        let owner = harness.owner();
+        let initialize = Instruction::new_with_bytes(
+            program_id,
+            &[INITIALIZE],
+            vec![
+                AccountMeta::new(owner, true),
+                AccountMeta::new(harness.treasury_pda, false),
+                AccountMeta::new_readonly(system_program::id(), false),
+                AccountMeta::new(harness.config, false),
+                AccountMeta::new(harness.history, false),
+                AccountMeta::new_readonly(owner, true),
+                AccountMeta::new_readonly(harness.mint, false),
+            ],
+        );
+        let create_vault = Instruction::new_with_bytes(
+            program_id,
+            &[CREATE_TREASURY_TOKEN_ACCOUNT],
+            vec![
+                AccountMeta::new(owner, true),
+                AccountMeta::new(harness.config, false),
+                AccountMeta::new_readonly(harness.treasury_pda, false),
+                AccountMeta::new(harness.vault, false),
+                AccountMeta::new_readonly(harness.mint, false),
+                AccountMeta::new_readonly(system_program::id(), false),
+                AccountMeta::new_readonly(spl_token::id(), false),
+                AccountMeta::new_readonly(spl_associated_token_account::id(), false),
+            ],
+        );
+        harness
+            .process(&[initialize, create_vault], &[])
+            .await
+            .unwrap();
+        harness
+    }
+
+    /// The payer, which `start` also makes the treasury owner.
+    pub fn owner(&self) -> Pubkey {
+        self.context.payer.pubkey()
+    }
+
+    /// Replace the bank's `Clock` sysvar; every later instruction reads this time.
+    pub fn set_clock(&mut self, clock: Clock) {
+        self.context.set_sysvar(&clock);
+    }
+
+    /// Move the clock to `unix_timestamp`, keeping the slot and epoch.
+    pub async fn warp_to(&mut self, unix_timestamp: i64) {
+        let clock = self.clock().await;
+        self.set_clock(clock_at(unix_timestamp, clock.epoch, clock.slot));
+    }
+
+    pub async fn clock(&mut self) -> Clock {
+        self.context
+            .banks_client
+            .get_sysvar::<Clock>()
+            .await
+            .unwrap()
+    }
+
+    /// Send `instructions` in one transaction paid and signed by the payer plus `signers`.
+    pub async fn process(
+        &mut self,
+        instructions: &[Instruction],
+        signers: &[&Keypair],
+    ) -> Result<(), BanksClientError> {
+        // A fresh blockhash lets a test repeat an identical transaction
+        let blockhash = self.context.get_new_latest_blockhash().await.unwrap();
+
+        let mut all_signers = vec![&self.context.payer];
+        all_signers.extend_from_slice(signers);
+        let transaction = Transaction::new_signed_with_payer(
+            instructions,
+            Some(&self.context.payer.pubkey()),
+            &all_signers,
+            blockhash,
+        );
+        self.context
+            .banks_client
+            .process_transaction(transaction)
+            .await
+    }
+
+    async fn create_mint(&mut self) {
+        let mint = Keypair::new();
+        let rent = self.context.banks_client.get_rent().await.unwrap();
+        let owner = self.owner();
+        let instructions = [
+            system_instruction::create_account(
+                &owner,
+                &mint.pubkey(),
+                rent.minimum_balance(Mint::LEN),
+                Mint::LEN as u64,
+                &spl_token::id(),
+            ),
+            spl_token::instruction::initialize_mint(
+                &spl_token::id(),
+                &mint.pubkey(),
+                &self.mint_authority.pubkey(),
+                None,
+                DECIMALS,
+            )
+            .unwrap(),
+        ];
+        self.process(&instructions, &[&mint]).await.unwrap();
+        self.mint = mint.pubkey();
+    }
+
+    /// A fresh wallet holding enough lamports to pay for its own receipt and fees.
+    pub async fn funded_user(&mut self) -> Keypair {
+        let user = Keypair::new();
+        let fund = system_instruction::transfer(&self.owner(), &user.pubkey(), 1_000_000_000);
+        self.process(&[fund], &[]).await.unwrap();
+        user
+    }
+
+    /// Create `wallet`'s associated token account for the treasury mint.
+    pub async fn create_token_account(&mut self, wallet: &Pubkey) -> Pubkey {
+        let owner = self.owner();
+        let create = create_associated_token_account(&owner, wallet, &self.mint, &spl_token::id());
+        self.process(&[create], &[]).await.unwrap();
+        get_associated_token_address(wallet, &self.mint)
+    }
+
+    /// Mint `amount` to the owner and deposit all of it into the treasury.
+    pub async fn deposit(&mut self, amount: u64) {
+        let owner = self.owner();
+        let source = get_associated_token_address(&owner, &self.mint);
+        if self
+            .context
+            .banks_client
+            .get_account(source)
+            .await
+            .unwrap()
+            .is_none()
+        {
+            self.create_token_account(&owner).await;
+        }
+        self.mint_to(&source, amount).await;
+        let deposit = Instruction::new_with_bytes(
+            self.program_id,
+            &instruction_data(DEPOSIT, amount),
+            vec![
+                AccountMeta::new_readonly(owner, true),
+                AccountMeta::new(source, false),
+                AccountMeta::new(self.vault, false),
+                AccountMeta::new(self.treasury_pda, false),
+                AccountMeta::new_readonly(self.config, false),
+                AccountMeta::new_readonly(self.mint, false),
+                AccountMeta::new_readonly(spl_token::id(), false),
+            ],
+        );
+        self.process(&[deposit], &[]).await.unwrap();
+    }
+
+    /// Give `user` a linear schedule of `total` over `duration` seconds from `start`.
+    pub async fn set_vesting(
+        &mut self,
+        user: &Pubkey,
+        start: i64,
+        duration: i64,
+        total: u64,
+    ) -> Result<(), BanksClientError> {
+        let set_vesting = Instruction::new_with_bytes(
+            self.program_id,
+            &instruction_data(SET_VESTING, (*user, start, duration, total)),
+            vec![
+                AccountMeta::new(self.owner(), true),
+                AccountMeta::new_readonly(self.config, false),
+                AccountMeta::new(user_claim_address(&self.program_id, user), false),
+                AccountMeta::new_readonly(system_program::id(), false),
+                AccountMeta::new(self.treasury_pda, false),
+            ],
+        );
+        self.process(&[set_vesting], &[]).await
+    }
+
+    /// `user` claims `amount` of their vested tokens into `destination`.
+    pub async fn claim_vested(
+        &mut self,
+        user: &Keypair,
+        destination: &Pubkey,
+        amount: u64,
+    ) -> Result<(), BanksClientError> {
+        let claim = Instruction::new_with_bytes(
+            self.program_id,
+            &instruction_data(CLAIM_VESTED, amount),
+            self.user_claim_metas(&user.pubkey(), destination),
+        );
+        self.process(&[claim], &[user]).await
+    }
+
+    /// Mint `amount` straight into `token_account`, bypassing the program.
+    pub async fn mint_to(&mut self, token_account: &Pubkey, amount: u64) {
+        let mint_to = spl_token::instruction::mint_to(
+            &spl_token::id(),
+            &self.mint,
+            token_account,
+            &self.mint_authority.pubkey(),
+            &[],
+            amount,
+        )
+        .unwrap();
+        let mint_authority = self.mint_authority.insecure_clone();
+        self.process(&[mint_to], &[&mint_authority]).await.unwrap();
+    }
+
+    pub async fn set_config(&mut self, params: ConfigParams) {
+        let set_config = Instruction::new_with_bytes(
+            self.program_id,
+            &instruction_data(SET_CONFIG, params),
+            vec![
+                AccountMeta::new_readonly(self.owner(), true),
+                AccountMeta::new(self.config, false),
+            ],
+        );
+        self.process(&[set_config], &[]).await.unwrap();
+    }
+
+    /// The owner claims `amount` on behalf of `user` into `destination`.
+    pub async fn claim(
+        &mut self,
+        user: &Pubkey,
+        destination: &Pubkey,
+        amount: u64,
+    ) -> Result<(), BanksClientError> {
+        let mut accounts = self.user_claim_metas(user, destination);
+        accounts[0] = AccountMeta::new_readonly(*user, false);
+        accounts[5] = AccountMeta::new(self.owner(), true);
+        accounts.pop(); // Open treasuries take no user-claim PDA
+        let claim = Instruction::new_with_bytes(
+            self.program_id,
+            &instruction_data(CLAIM, amount),
+            accounts,
+        );
+        self.process(&[claim], &[]).await
+    }
+
+    /// The owner withdraws each `(destination, amount)` pair in one `WithdrawSplit`.
+    pub async fn withdraw_split(
+        &mut self,
+        splits: &[(Pubkey, u64)],
+    ) -> Result<(), BanksClientError> {
+        let amounts: Vec<u64> = splits.iter().map(|(_, amount)| *amount).collect();
+        let total: u64 = amounts.iter().sum();
+        let mut accounts = vec![
+            AccountMeta::new_readonly(self.owner(), true),
+            AccountMeta::new_readonly(self.config, false),
+            AccountMeta::new(self.treasury_pda, false),
+            AccountMeta::new(self.vault, false),
+            AccountMeta::new_readonly(self.mint, false),
+            AccountMeta::new_readonly(spl_token::id(), false),
+        ];
+        accounts.extend(
+            splits
+                .iter()
+                .map(|(destination, _)| AccountMeta::new(*destination, false)),
+        );
+        let withdraw = Instruction::new_with_bytes(
+            self.program_id,
+            &instruction_data(WITHDRAW_SPLIT, (total, amounts)),
+            accounts,
+        );
+        self.process(&[withdraw], &[]).await
+    }
+
+    /// `Claim`'s account layout for a user-signed claim into `destination` on an open
+    /// treasury: the user also pays for their receipt, and their user-claim PDA comes last.
+    pub fn user_claim_metas(&self, user: &Pubkey, destination: &Pubkey) -> Vec<AccountMeta> {
+        vec![
+            AccountMeta::new(*user, true),
+            AccountMeta::new(*destination, false),
+            AccountMeta::new(self.vault, false),
+            AccountMeta::new_readonly(spl_token::id(), false),
+            AccountMeta::new(self.treasury_pda, false),
+            AccountMeta::new(*user, true),
+            AccountMeta::new_readonly(self.config, false),
+            AccountMeta::new(self.history, false),
+            AccountMeta::new_readonly(Pubkey::default(), false), // Fee vault, unused
+            AccountMeta::new_readonly(system_program::id(), false),
+            AccountMeta::new_readonly(self.mint, false),
+            AccountMeta::new(receipt_address(&self.program_id, 0, user), false),
+            AccountMeta::new(user_claim_address(&self.program_id, user), false),
+        ]
+    }
+
+    /// `user` claims `amount` of their vested tokens into a `destination` of their choosing.
+    pub async fn claim_to(
+        &mut self,
+        user: &Keypair,
+        destination: &Pubkey,
+        amount: u64,
+    ) -> Result<(), BanksClientError> {
+        let claim = Instruction::new_with_bytes(
+            self.program_id,
+            &instruction_data(CLAIM_TO, (amount, *destination)),
+            self.user_claim_metas(&user.pubkey(), destination),
+        );
+        self.process(&[claim], &[user]).await
+    }
+
+    pub async fn set_paused(&mut self, claims: bool, deposits: bool) {
+        let set_paused = Instruction::new_with_bytes(
+            self.program_id,
+            &instruction_data(SET_PAUSED, (claims, deposits)),
+            vec![
+                AccountMeta::new_readonly(self.owner(), true),
+                AccountMeta::new(self.config, false),
+            ],
+        );
+        self.process(&[set_paused], &[]).await.unwrap();
+    }
+
+    /// Move the whole vault to `destination`, repointing `config.vault` at it.
+    pub async fn migrate_treasury(&mut self, destination: &Pubkey) -> Result<(), BanksClientError> {
+        let migrate = Instruction::new_with_bytes(
+            self.program_id,
+            &instruction_data(MIGRATE_TREASURY, *destination),
+            vec![
+                AccountMeta::new_readonly(self.owner(), true),
+                AccountMeta::new(self.config, false),
+                AccountMeta::new(self.treasury_pda, false),
+                AccountMeta::new(self.vault, false),
+                AccountMeta::new(*destination, false),
+                AccountMeta::new_readonly(self.mint, false),
+                AccountMeta::new_readonly(spl_token::id(), false),
+            ],
+        );
+        self.process(&[migrate], &[]).await
+    }
+
+    /// Close the treasury state and history, sending their rent to `recipient`.
+    pub async fn close_treasury(&mut self, recipient: &Pubkey) -> Result<(), BanksClientError> {
+        let close = Instruction::new_with_bytes(
+            self.program_id,
+            &[CLOSE_TREASURY],
+            vec![
+                AccountMeta::new_readonly(self.owner(), true),
+                AccountMeta::new_readonly(self.config, false),
+                AccountMeta::new(self.treasury_pda, false),
+                AccountMeta::new(self.history, false),
+                AccountMeta::new(*recipient, false),
+                AccountMeta::new_readonly(self.vault, false),
+            ],
+        );
+        self.process(&[close], &[]).await
+    }
+
+    pub async fn token_balance(&mut self, token_account: &Pubkey) -> u64 {
+        let account = self
+            .context
+            .banks_client
+            .get_account(*token_account)
+            .await
+            .unwrap()
+            .expect("token account exists");
+        TokenAccount::unpack(&account.data).unwrap().amount
+    }
+
+    /// Panic unless the tracked `TreasuryState.balance` equals what the vault really holds.
+    /// Fees and reserves never leave the vault, so nothing needs adjusting for them; `after`
+    /// names the operation just run for the failure message.
+    pub async fn assert_balance_matches_vault(&mut self, after: &str) {
+        let state = self.state().await;
+        let vault = self.vault;
+        let vault_amount = self.token_balance(&vault).await;
+        assert_eq!(
+            state.balance, vault_amount,
+            "tracked balance drifted from the vault after {after}"
+        );
+        assert!(
+            state.locked_balance <= state.balance,
+            "locked {} exceeds balance {} after {after}",
+            state.locked_balance,
+            state.balance
+        );
+    }
+
+    pub async fn state(&mut self) -> TreasuryState {
+        let account = self
+            .context
+            .banks_client
+            .get_account(self.treasury_pda)
+            .await
+            .unwrap()
+            .expect("treasury state exists");
+        TreasuryState::decode(&account.data).unwrap()
+    }
+
+    pub async fn user_claim(&mut self, user: &Pubkey) -> UserClaim {
+        let address = user_claim_address(&self.program_id, user);
+        let account = self
+            .context
+            .banks_client
+            .get_account(address)
+            .await
+            .unwrap()
+            .expect("user claim exists");
+        UserClaim::decode(&account.data).unwrap()
+    }
+}
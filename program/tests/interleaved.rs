@@ -0,0 +1,200 @@
+mod common;
+
+use common::{Harness, GENESIS_TIMESTAMP};
+use solana_program_test::tokio;
+use solana_sdk::{
+    pubkey::Pubkey,
+    signature::{Keypair, Signer},
+};
+use treasury::vested_amount;
+
+const SEEDS: [u64; 4] = [1, 7, 42, 0x5eed_cafe];
+const STEPS: usize = 40;
+const USERS: usize = 3;
+
+/// Deterministic 64-bit LCG, so every run replays the same sequences.
+struct Lcg(u64);
+
+impl Lcg {
+    fn next(&mut self) -> u64 {
+        self.0 = self
+            .0
+            .wrapping_mul(6_364_136_223_846_793_005)
+            .wrapping_add(1_442_695_040_888_963_407);
+        self.0 >> 33
+    }
+
+    /// Uniform-enough value in `low..=high`.
+    fn range(&mut self, low: u64, high: u64) -> u64 {
+        low + self.next() % (high - low + 1)
+    }
+}
+
+/// What the program should have recorded for one user.
+#[derive(Clone, Copy, Default)]
+struct Schedule {
+    total: u64,
+    claimed: u64,
+    start: i64,
+    duration: i64,
+}
+
+/// Off-chain mirror of the treasury books that each step is reconciled against.
+#[derive(Default)]
+struct Model {
+    deposited: u64,
+    balance: u64,
+    locked: u64,
+    allocated: u64,
+    schedules: [Option<Schedule>; USERS],
+    received: [u64; USERS],
+}
+
+impl Model {
+    fn liquid(&self) -> u64 {
+        self.balance - self.locked
+    }
+}
+
+struct User {
+    keypair: Keypair,
+    account: Pubkey,
+}
+
+/// Compare chain state and token accounts with the model after a step.
+async fn reconcile(harness: &mut Harness, model: &Model, users: &[User], step: &str) {
+    harness.assert_balance_matches_vault(step).await;
+    let state = harness.state().await;
+    assert_eq!(state.balance, model.balance, "balance after {step}");
+    assert_eq!(state.locked_balance, model.locked, "locked after {step}");
+    assert_eq!(
+        state.total_allocated, model.allocated,
+        "total_allocated after {step}"
+    );
+    for (index, user) in users.iter().enumerate() {
+        assert_eq!(
+            harness.token_balance(&user.account).await,
+            model.received[index],
+            "user {index} tokens after {step}"
+        );
+        if let Some(schedule) = model.schedules[index] {
+            let user_claim = harness.user_claim(&user.keypair.pubkey()).await;
+            assert_eq!(
+                (user_claim.total, user_claim.claimed),
+                (schedule.total, schedule.claimed),
+                "user {index} allocation after {step}"
+            );
+        }
+    }
+}
+
+/// Run one seeded sequence and return how much it paid out to users.
+async fn run_sequence(seed: u64) -> u64 {
+    let mut rng = Lcg(seed);
+    let mut harness = Harness::start().await;
+    let mut model = Model::default();
+    let mut users = Vec::new();
+    for _ in 0..USERS {
+        let keypair = harness.funded_user().await;
+        let account = harness.create_token_account(&keypair.pubkey()).await;
+        users.push(User { keypair, account });
+    }
+    let mut now = GENESIS_TIMESTAMP;
+
+    for step in 0..STEPS {
+        let index = rng.range(0, USERS as u64 - 1) as usize;
+        let label = format!("seed {seed} step {step}");
+        match rng.range(0, 4) {
+            0 => {
+                let amount = rng.range(1, 2_000);
+                harness.deposit(amount).await;
+                model.deposited += amount;
+                model.balance += amount;
+            }
+            1 => {
+                // Owner claims only ever draw on the liquid part
+                let amount = rng.range(1, model.liquid() + 200);
+                let user = &users[index];
+                let result = harness
+                    .claim(&user.keypair.pubkey(), &user.account, amount)
+                    .await;
+                assert_eq!(result.is_ok(), amount <= model.liquid(), "{label} claim");
+                if result.is_ok() {
+                    model.balance -= amount;
+                    model.received[index] += amount;
+                }
+            }
+            2 => {
+                // Raise or lower the allocation, never below what was already claimed
+                let current = model.schedules[index].unwrap_or_default();
+                let total = rng.range(current.claimed, current.claimed + 3_000);
+                let start = now - rng.range(0, 1_000) as i64;
+                let duration = rng.range(0, 2_000) as i64;
+                let result = harness
+                    .set_vesting(&users[index].keypair.pubkey(), start, duration, total)
+                    .await;
+                let old_outstanding = current.total - current.claimed;
+                let new_outstanding = total - current.claimed;
+                let fits = new_outstanding <= old_outstanding
+                    || new_outstanding - old_outstanding <= model.liquid();
+                assert_eq!(result.is_ok(), fits, "{label} set_vesting");
+                if result.is_ok() {
+                    if new_outstanding >= old_outstanding {
+                        model.locked += new_outstanding - old_outstanding;
+                        model.allocated += new_outstanding - old_outstanding;
+                    } else {
+                        model.locked -= old_outstanding - new_outstanding;
+                        model.allocated -= old_outstanding - new_outstanding;
+                    }
+                    model.schedules[index] = Some(Schedule {
+                        total,
+                        claimed: current.claimed,
+                        start,
+                        duration,
+                    });
+                }
+            }
+            3 => {
+                let claimable = model.schedules[index].map_or(0, |schedule| {
+                    // A rescheduled allocation can have vested less than was already claimed
+                    vested_amount(schedule.total, schedule.start, schedule.duration, now)
+                        .saturating_sub(schedule.claimed)
+                });
+                let amount = rng.range(1, claimable + 100);
+                let user = &users[index];
+                let result = harness
+                    .claim_vested(&user.keypair, &user.account, amount)
+                    .await;
+                assert_eq!(result.is_ok(), amount <= claimable, "{label} claim_vested");
+                if result.is_ok() {
+                    let schedule = model.schedules[index].as_mut().unwrap();
+                    schedule.claimed += amount;
+                    model.balance -= amount;
+                    model.locked -= amount;
+                    model.allocated -= amount;
+                    model.received[index] += amount;
+                }
+            }
+            _ => {
+                now += rng.range(1, 500) as i64;
+                harness.warp_to(now).await;
+            }
+        }
+        reconcile(&mut harness, &model, &users, &label).await;
+    }
+
+    // Every deposited token is either still in the vault or in a user's account
+    let paid: u64 = model.received.iter().sum();
+    let vault = harness.vault;
+    assert_eq!(harness.token_balance(&vault).await + paid, model.deposited);
+    paid
+}
+
+#[tokio::test]
+async fn interleaved_sequences_keep_state_and_vault_consistent() {
+    let mut paid = 0;
+    for seed in SEEDS {
+        paid += run_sequence(seed).await;
+    }
+    assert!(paid > 0, "no sequence paid anything out");
+}
@@ -0,0 +1,35 @@
+mod common;
+
+use common::{Harness, GENESIS_TIMESTAMP};
+use solana_program_test::tokio;
+use solana_sdk::pubkey::Pubkey;
+
+#[tokio::test]
+async fn migrate_drains_the_vault_and_clears_the_books() {
+    let mut harness = Harness::start().await;
+    harness.deposit(5_000).await;
+    let user = Pubkey::new_unique();
+    harness
+        .set_vesting(&user, GENESIS_TIMESTAMP, 1_000, 2_000)
+        .await
+        .unwrap();
+
+    // The new treasury's vault belongs to some other program's PDA
+    let new_treasury = Pubkey::new_unique();
+    let destination = harness.create_token_account(&new_treasury).await;
+
+    // Unpaused treasuries cannot be drained
+    assert!(harness.migrate_treasury(&destination).await.is_err());
+
+    harness.set_paused(true, true).await;
+    harness.migrate_treasury(&destination).await.unwrap();
+
+    let vault = harness.vault;
+    assert_eq!(harness.token_balance(&vault).await, 0);
+    assert_eq!(harness.token_balance(&destination).await, 5_000);
+
+    let state = harness.state().await;
+    assert_eq!(state.balance, 0);
+    assert_eq!(state.locked_balance, 0);
+    assert_eq!(state.total_allocated, 0);
+}
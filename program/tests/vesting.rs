@@ -0,0 +1,162 @@
+mod common;
+
+use common::{clock_at, treasury_error, Harness, GENESIS_TIMESTAMP};
+use solana_program_test::tokio;
+use solana_sdk::{
+    instruction::InstructionError, pubkey::Pubkey, signature::Signer, transaction::TransactionError,
+};
+use treasury::TreasuryError;
+
+#[tokio::test]
+async fn vested_claims_follow_the_injected_clock() {
+    let mut harness = Harness::start().await;
+    harness.deposit(10_000).await;
+
+    let user = harness.funded_user().await;
+    let destination = harness.create_token_account(&user.pubkey()).await;
+    let start = GENESIS_TIMESTAMP + 100;
+    harness
+        .set_vesting(&user.pubkey(), start, 1_000, 1_000)
+        .await
+        .unwrap();
+
+    // Nothing has vested before the schedule starts
+    let err = harness
+        .claim_vested(&user, &destination, 1)
+        .await
+        .unwrap_err();
+    assert_eq!(err.unwrap(), treasury_error(TreasuryError::ExceedsVested));
+
+    // A quarter of the way through, exactly a quarter is claimable
+    harness.set_clock(clock_at(start + 250, 3, 500));
+    assert_eq!(harness.clock().await.epoch, 3);
+    let err = harness
+        .claim_vested(&user, &destination, 251)
+        .await
+        .unwrap_err();
+    assert_eq!(err.unwrap(), treasury_error(TreasuryError::ExceedsVested));
+    harness
+        .claim_vested(&user, &destination, 250)
+        .await
+        .unwrap();
+    assert_eq!(harness.token_balance(&destination).await, 250);
+
+    // Past the end the rest unlocks, and the allocation lock is fully spent
+    harness.warp_to(start + 5_000).await;
+    harness
+        .claim_vested(&user, &destination, 750)
+        .await
+        .unwrap();
+    assert_eq!(harness.token_balance(&destination).await, 1_000);
+    assert_eq!(harness.user_claim(&user.pubkey()).await.claimed, 1_000);
+
+    let state = harness.state().await;
+    assert_eq!(state.balance, 9_000);
+    assert_eq!(state.locked_balance, 0);
+    assert_eq!(state.total_allocated, 0);
+    assert_eq!(harness.token_balance(&harness.vault.clone()).await, 9_000);
+}
+
+#[tokio::test]
+async fn each_user_vests_on_their_own_schedule() {
+    let mut harness = Harness::start().await;
+    harness.deposit(10_000).await;
+
+    let fast = harness.funded_user().await;
+    let slow = harness.funded_user().await;
+    let fast_account = harness.create_token_account(&fast.pubkey()).await;
+    let slow_account = harness.create_token_account(&slow.pubkey()).await;
+
+    // Same start, but one schedule runs four times as long for twice the total
+    let start = GENESIS_TIMESTAMP;
+    harness
+        .set_vesting(&fast.pubkey(), start, 1_000, 1_000)
+        .await
+        .unwrap();
+    harness
+        .set_vesting(&slow.pubkey(), start, 4_000, 2_000)
+        .await
+        .unwrap();
+
+    harness.warp_to(start + 500).await;
+    harness
+        .claim_vested(&fast, &fast_account, 500)
+        .await
+        .unwrap();
+    harness
+        .claim_vested(&slow, &slow_account, 250)
+        .await
+        .unwrap();
+    let err = harness
+        .claim_vested(&slow, &slow_account, 1)
+        .await
+        .unwrap_err();
+    assert_eq!(err.unwrap(), treasury_error(TreasuryError::ExceedsVested));
+
+    // The fast schedule is done while the slow one is only halfway
+    harness.warp_to(start + 2_000).await;
+    harness
+        .claim_vested(&fast, &fast_account, 500)
+        .await
+        .unwrap();
+    harness
+        .claim_vested(&slow, &slow_account, 750)
+        .await
+        .unwrap();
+    let err = harness
+        .claim_vested(&fast, &fast_account, 1)
+        .await
+        .unwrap_err();
+    assert_eq!(err.unwrap(), treasury_error(TreasuryError::ExceedsVested));
+
+    assert_eq!(harness.token_balance(&fast_account).await, 1_000);
+    assert_eq!(harness.token_balance(&slow_account).await, 1_000);
+    assert_eq!(harness.user_claim(&fast.pubkey()).await.claimed, 1_000);
+    assert_eq!(harness.user_claim(&slow.pubkey()).await.claimed, 1_000);
+
+    // Only the slow user's unvested half is still locked
+    let state = harness.state().await;
+    assert_eq!(state.balance, 8_000);
+    assert_eq!(state.locked_balance, 1_000);
+    assert_eq!(state.total_allocated, 1_000);
+}
+
+#[tokio::test]
+async fn vested_claims_pass_the_claim_checks() {
+    let mut harness = Harness::start().await;
+    harness.deposit(10_000).await;
+
+    let user = harness.funded_user().await;
+    let own_account = harness.create_token_account(&user.pubkey()).await;
+    harness
+        .set_vesting(&user.pubkey(), GENESIS_TIMESTAMP - 10, 0, 1_000)
+        .await
+        .unwrap();
+
+    // The payout may only land in a token account the user owns
+    let stranger_account = harness.create_token_account(&Pubkey::new_unique()).await;
+    let err = harness
+        .claim_vested(&user, &stranger_account, 100)
+        .await
+        .unwrap_err();
+    assert_eq!(
+        err.unwrap(),
+        TransactionError::InstructionError(0, InstructionError::IllegalOwner)
+    );
+
+    // Pausing claims stops vested claims too
+    harness.set_paused(true, false).await;
+    let err = harness
+        .claim_vested(&user, &own_account, 100)
+        .await
+        .unwrap_err();
+    assert_eq!(err.unwrap(), treasury_error(TreasuryError::ClaimsPaused));
+
+    harness.set_paused(false, false).await;
+    harness
+        .claim_vested(&user, &own_account, 100)
+        .await
+        .unwrap();
+    assert_eq!(harness.token_balance(&stranger_account).await, 0);
+    assert_eq!(harness.token_balance(&own_account).await, 100);
+}